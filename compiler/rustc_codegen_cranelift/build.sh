@@ -6,6 +6,7 @@ export CHANNEL="release"
 build_sysroot="clif"
 target_dir='build'
 oldbe=''
+no_jit=''
 while [[ $# != 0 ]]; do
     case $1 in
         "--debug")
@@ -22,9 +23,12 @@ while [[ $# != 0 ]]; do
         "--oldbe")
             oldbe='--features oldbe'
             ;;
+        "--no-jit")
+            no_jit='--no-default-features --features inline_asm'
+            ;;
         *)
             echo "Unknown flag '$1'"
-            echo "Usage: ./build.sh [--debug] [--sysroot none|clif|llvm] [--target-dir DIR] [--oldbe]"
+            echo "Usage: ./build.sh [--debug] [--sysroot none|clif|llvm] [--target-dir DIR] [--oldbe] [--no-jit]"
             exit 1
             ;;
     esac
@@ -44,9 +48,9 @@ else
    exit 1
 fi
 if [[ "$CHANNEL" == "release" ]]; then
-    cargo build $oldbe --release
+    cargo build $oldbe $no_jit --release
 else
-    cargo build $oldbe
+    cargo build $oldbe $no_jit
 fi
 
 source scripts/ext_config.sh
@@ -54,7 +58,10 @@ source scripts/ext_config.sh
 rm -rf "$target_dir"
 mkdir "$target_dir"
 mkdir "$target_dir"/bin "$target_dir"/lib
-ln target/$CHANNEL/cg_clif{,_build_sysroot} "$target_dir"/bin
+ln target/$CHANNEL/cg_clif{,_build_sysroot,_object_sink,_mir_transform} "$target_dir"/bin
+if [[ "$no_jit" == "" ]]; then
+    ln target/$CHANNEL/cg_clif_jit_sessions "$target_dir"/bin
+fi
 ln target/$CHANNEL/*rustc_codegen_cranelift* "$target_dir"/lib
 ln rust-toolchain scripts/config.sh scripts/cargo.sh "$target_dir"
 
@@ -17,6 +17,48 @@
     fn puts(s: *const u8) -> i32;
 }
 
+// Provides the symbols normally defined by the allocator shim when it is disabled using
+// `-Cllvm-args=disable_allocator_shim=1`.
+#[cfg(external_allocator_shim)]
+mod allocator_shim {
+    extern "C" {
+        fn malloc(size: usize) -> *mut u8;
+        fn calloc(nmemb: usize, size: usize) -> *mut u8;
+        fn realloc(ptr: *mut u8, size: usize) -> *mut u8;
+        fn free(ptr: *mut u8);
+    }
+
+    #[no_mangle]
+    unsafe fn __rust_alloc(size: usize, _align: usize) -> *mut u8 {
+        malloc(size)
+    }
+
+    #[no_mangle]
+    unsafe fn __rust_dealloc(ptr: *mut u8, _size: usize, _align: usize) {
+        free(ptr)
+    }
+
+    #[no_mangle]
+    unsafe fn __rust_realloc(
+        ptr: *mut u8,
+        _old_size: usize,
+        _align: usize,
+        new_size: usize,
+    ) -> *mut u8 {
+        realloc(ptr, new_size)
+    }
+
+    #[no_mangle]
+    unsafe fn __rust_alloc_zeroed(size: usize, _align: usize) -> *mut u8 {
+        calloc(1, size)
+    }
+
+    #[no_mangle]
+    fn __rust_alloc_error_handler(_size: usize, _align: usize) -> ! {
+        core::intrinsics::abort();
+    }
+}
+
 #[panic_handler]
 fn panic_handler(_: &core::panic::PanicInfo) -> ! {
     core::intrinsics::abort();
@@ -0,0 +1,52 @@
+// Arguments and return values must be passed correctly for every calling convention supported on
+// the current target. Calls go through function pointers to prevent any inlining.
+
+extern "C" fn c_sum(a: u8, b: u16, c: u32, d: u64, e: f32, f: f64) -> f64 {
+    a as f64 + b as f64 + c as f64 + d as f64 + e as f64 + f
+}
+
+// Equivalent to `extern "C"` on all targets except 32bit x86.
+extern "fastcall" fn fastcall_sum(a: u8, b: u16, c: u32, d: u64, e: f32, f: f64) -> f64 {
+    c_sum(a, b, c, d, e, f)
+}
+
+extern "system" fn system_sum(a: u8, b: u16, c: u32, d: u64, e: f32, f: f64) -> f64 {
+    c_sum(a, b, c, d, e, f)
+}
+
+#[cfg(target_arch = "x86_64")]
+extern "sysv64" fn sysv64_sum(a: u8, b: u16, c: u32, d: u64, e: f32, f: f64) -> f64 {
+    c_sum(a, b, c, d, e, f)
+}
+
+#[cfg(target_arch = "x86_64")]
+extern "win64" fn win64_sum(a: u8, b: u16, c: u32, d: u64, e: f32, f: f64) -> f64 {
+    c_sum(a, b, c, d, e, f)
+}
+
+fn black_box<T>(val: T) -> T {
+    unsafe { std::ptr::read_volatile(&val) }
+}
+
+fn main() {
+    let expected = 1.0 + 2.0 + 3.0 + 4.0 + 5.5 + 6.25;
+
+    let c: extern "C" fn(u8, u16, u32, u64, f32, f64) -> f64 = black_box(c_sum);
+    assert_eq!(c(1, 2, 3, 4, 5.5, 6.25), expected);
+
+    let fastcall: extern "fastcall" fn(u8, u16, u32, u64, f32, f64) -> f64 =
+        black_box(fastcall_sum);
+    assert_eq!(fastcall(1, 2, 3, 4, 5.5, 6.25), expected);
+
+    let system: extern "system" fn(u8, u16, u32, u64, f32, f64) -> f64 = black_box(system_sum);
+    assert_eq!(system(1, 2, 3, 4, 5.5, 6.25), expected);
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let sysv64: extern "sysv64" fn(u8, u16, u32, u64, f32, f64) -> f64 = black_box(sysv64_sum);
+        assert_eq!(sysv64(1, 2, 3, 4, 5.5, 6.25), expected);
+
+        let win64: extern "win64" fn(u8, u16, u32, u64, f32, f64) -> f64 = black_box(win64_sum);
+        assert_eq!(win64(1, 2, 3, 4, 5.5, 6.25), expected);
+    }
+}
@@ -0,0 +1,18 @@
+// Compiled as cdylib and loaded using `dlopen` by `dlopen_cdylib.c`. Uses both the allocator and
+// atomics, which need the allocator shim and the global lock used to emulate atomics to be part of
+// the cdylib.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static CALLS: AtomicU32 = AtomicU32::new(0);
+
+#[no_mangle]
+pub extern "C" fn cg_clif_cdylib_sum(n: u32) -> u32 {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+    (1..=n).collect::<Vec<u32>>().iter().sum()
+}
+
+#[no_mangle]
+pub extern "C" fn cg_clif_cdylib_calls() -> u32 {
+    CALLS.load(Ordering::SeqCst)
+}
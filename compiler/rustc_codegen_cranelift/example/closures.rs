@@ -0,0 +1,88 @@
+// Closures and `Fn*` trait calls use the "rust-call" ABI, which passes the fields of the tupled
+// arguments as separate arguments.
+
+#![feature(unboxed_closures, fn_traits)]
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Big {
+    a: u64,
+    b: u64,
+    c: u64,
+    d: u64,
+}
+
+extern "rust-call" fn only_tuple(args: (u8, u16, Big)) -> u64 {
+    args.0 as u64 + args.1 as u64 + args.2.d
+}
+
+extern "rust-call" fn empty_tuple(_args: ()) -> u8 {
+    42
+}
+
+fn call_fn<F: Fn() -> u64>(f: F) -> u64 {
+    f()
+}
+
+fn call_fn_mut<F: FnMut(u8) -> u64>(mut f: F) -> u64 {
+    f(1) + f(2)
+}
+
+fn call_fn_once<F: FnOnce(u8, Big, (), &str) -> Big>(f: F) -> Big {
+    f(1, Big { a: 2, b: 3, c: 4, d: 5 }, (), "six")
+}
+
+fn call_dyn(f: &dyn Fn(u32, u64, Big) -> u64) -> u64 {
+    f(1, 2, Big { a: 3, b: 4, c: 5, d: 6 })
+}
+
+fn black_box<T>(val: T) -> T {
+    unsafe { std::ptr::read_volatile(&val) }
+}
+
+fn main() {
+    // Extern "rust-call" functions without self argument
+    assert_eq!(only_tuple((1, 2, Big { a: 0, b: 0, c: 0, d: 3 })), 6);
+    assert_eq!(empty_tuple(()), 42);
+
+    // No captures and no arguments
+    assert_eq!(call_fn(|| 1), 1);
+    let no_args = || black_box(2u64);
+    assert_eq!(no_args(), 2);
+    assert_eq!(no_args.call(()), 2);
+
+    // Capture by reference
+    let captured = black_box(Big { a: 1, b: 2, c: 3, d: 4 });
+    assert_eq!(call_fn(|| captured.a + captured.d), 5);
+
+    // Capture by mutable reference
+    let mut counter = 0u64;
+    assert_eq!(
+        call_fn_mut(|x| {
+            counter += x as u64;
+            counter
+        }),
+        4
+    );
+    assert_eq!(counter, 3);
+
+    // Capture by value and many arguments of different sizes
+    let factor = black_box(10u64);
+    let big = call_fn_once(move |a, b, (), s| Big {
+        a: a as u64 * factor,
+        b: b.a + captured.b,
+        c: b.d + s.len() as u64,
+        d: captured.c,
+    });
+    assert_eq!(big, Big { a: 10, b: 4, c: 8, d: 3 });
+
+    // Calls through a trait object
+    let offset = black_box(100u64);
+    assert_eq!(
+        call_dyn(&|a, b, c| a as u64 + b + c.a + c.d + offset),
+        112
+    );
+
+    // Calls through a function pointer
+    let fn_ptr: fn(u8) -> u64 = |x| x as u64 * 2;
+    assert_eq!(call_fn_mut(fn_ptr), 6);
+}
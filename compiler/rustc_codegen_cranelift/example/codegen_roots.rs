@@ -0,0 +1,29 @@
+// Compiled with `codegen_roots=cg_clif_root*`. `cg_clif_unreached` is neither a root nor called by
+// one, so it must not be codegened. Everything reachable from `main` and the roots must be.
+
+#[no_mangle]
+pub extern "C" fn cg_clif_root() -> u32 {
+    reached_through_root() + 1
+}
+
+#[inline(never)]
+fn reached_through_root() -> u32 {
+    41
+}
+
+#[no_mangle]
+pub extern "C" fn cg_clif_unreached() -> u32 {
+    7
+}
+
+static CALLBACKS: [fn() -> u32; 1] = [reached_through_static];
+
+fn reached_through_static() -> u32 {
+    1
+}
+
+fn main() {
+    assert_eq!(CALLBACKS[0](), 1);
+    let v: Vec<Box<dyn Fn() -> String>> = vec![Box::new(|| format!("{}", 42))];
+    assert_eq!(v[0](), "42");
+}
@@ -0,0 +1,26 @@
+// Compiled with `-Zfunction-sections=yes` to check that `#[cold]` functions are placed in
+// `.text.unlikely.*` sections, and without it to check that they stay in `.text`.
+
+#[cold]
+#[no_mangle]
+pub extern "C" fn cg_clif_cold_fn(a: u32) -> u32 {
+    a * 3
+}
+
+#[cold]
+#[inline(never)]
+fn cold_path(a: u32) -> u32 {
+    a + 7
+}
+
+#[no_mangle]
+pub extern "C" fn cg_clif_hot_fn(a: u32) -> u32 {
+    a + 1
+}
+
+fn main() {
+    let n = std::env::args().count() as u32;
+    assert_eq!(cg_clif_hot_fn(n), 2);
+    assert_eq!(cg_clif_cold_fn(n), 3);
+    assert_eq!(cold_path(n), 8);
+}
@@ -0,0 +1,28 @@
+// Sums the basic block counters emitted by `-Cllvm-args=coverage=1`. The coverage map itself is
+// checked by the test script.
+
+extern "C" {
+    static __start___cg_clif_cov: u64;
+    static __stop___cg_clif_cov: u64;
+}
+
+#[inline(never)]
+fn covered(n: u32) -> u32 {
+    if n % 2 == 0 { n / 2 } else { 3 * n + 1 }
+}
+
+fn main() {
+    let mut n = 27;
+    while n != 1 {
+        n = covered(n);
+    }
+
+    let counters = unsafe {
+        let start = &__start___cg_clif_cov as *const u64;
+        let stop = &__stop___cg_clif_cov as *const u64;
+        std::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    };
+    let total: u64 = counters.iter().sum();
+    println!("{} counters, {} blocks entered", counters.len(), total);
+    assert!(total > 111);
+}
@@ -0,0 +1,13 @@
+// Defines the C `main` function itself besides the Rust `main` function. This only links when the
+// entry wrapper is disabled using the `disable_entry_wrapper` option, as the entry wrapper is named
+// `main` too.
+
+fn main() {
+    unreachable!("the entry wrapper calling the Rust main function is disabled");
+}
+
+#[export_name = "main"]
+pub extern "C" fn c_main(_argc: i32, _argv: *const *const u8) -> i32 {
+    println!("Hello from a custom entry");
+    0
+}
@@ -0,0 +1,12 @@
+// The debuginfo of every function has to contain its path including generic arguments as name, so
+// debuggers show readable names for closures and generic functions.
+
+#[inline(never)]
+fn generic<T: Copy>(a: T) -> T {
+    a
+}
+
+fn main() {
+    let closure = |a: u32| generic(a) + 1;
+    assert_eq!(closure(1), 2);
+}
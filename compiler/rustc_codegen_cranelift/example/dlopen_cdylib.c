@@ -0,0 +1,33 @@
+// Loads the cdylib built from `cdylib.rs` passed as first argument and calls its exported
+// functions.
+
+#include <dlfcn.h>
+#include <stdint.h>
+#include <stdio.h>
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <cdylib>\n", argv[0]);
+        return 1;
+    }
+
+    void *lib = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+    if (!lib) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+
+    uint32_t (*sum)(uint32_t) = (uint32_t (*)(uint32_t))dlsym(lib, "cg_clif_cdylib_sum");
+    uint32_t (*calls)(void) = (uint32_t (*)(void))dlsym(lib, "cg_clif_cdylib_calls");
+    if (!sum || !calls) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+
+    if (sum(4) != 10 || sum(100) != 5050 || calls() != 2) {
+        fprintf(stderr, "wrong results\n");
+        return 1;
+    }
+
+    return 0;
+}
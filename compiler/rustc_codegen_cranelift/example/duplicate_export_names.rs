@@ -0,0 +1,16 @@
+// Both functions are exported with the same symbol name from different modules and thus from
+// different codegen units. This must be reported as error before codegen.
+
+mod a {
+    #[export_name = "cg_clif_duplicate"]
+    pub extern "C" fn a() -> u32 {
+        1
+    }
+}
+
+mod b {
+    #[export_name = "cg_clif_duplicate"]
+    pub extern "C" fn b() -> u32 {
+        2
+    }
+}
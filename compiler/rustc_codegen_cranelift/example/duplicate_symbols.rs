@@ -0,0 +1,16 @@
+// `cg_clif_duplicate` is defined by global asm in one module and by a function in another module
+// and thus in different codegen units. Symbols defined by global asm are unknown to rustc, so only
+// the `check_duplicate_symbols` option can report the collision.
+
+#![feature(global_asm)]
+
+mod a {
+    global_asm!(".data\n.globl cg_clif_duplicate\ncg_clif_duplicate:\n.byte 0");
+}
+
+mod b {
+    #[no_mangle]
+    pub extern "C" fn cg_clif_duplicate() -> u32 {
+        2
+    }
+}
@@ -0,0 +1,23 @@
+// Compiled as cdylib with `-Cllvm-args=exports_file=...` to check that only the `#[no_mangle]`
+// items end up in the list of exported symbols, and as dylib to check that the Rust ABI items are
+// exported too.
+
+#[no_mangle]
+pub extern "C" fn cg_clif_exported_fn(a: u32) -> u32 {
+    not_exported(a) + 1
+}
+
+#[no_mangle]
+pub static CG_CLIF_EXPORTED_STATIC: u32 = 42;
+
+pub fn rust_abi_fn(a: u32) -> u32 {
+    a * 3
+}
+
+pub fn generic_fn<T: Copy>(a: T) -> T {
+    a
+}
+
+fn not_exported(a: u32) -> u32 {
+    generic_fn(rust_abi_fn(a))
+}
@@ -0,0 +1,12 @@
+// Compiled with `-Cforce-frame-pointers=yes`. Even leaf functions which don't use the stack must
+// set up the frame pointer.
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn cg_clif_leaf(a: u32, b: u32) -> u32 {
+    a.wrapping_add(b)
+}
+
+fn main() {
+    assert_eq!(cg_clif_leaf(40, 2), 42);
+}
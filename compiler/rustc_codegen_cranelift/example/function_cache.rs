@@ -0,0 +1,17 @@
+// Built twice in the same incremental session directory, the second time with the body of `answer`
+// changed to return 42. `unchanged` must be reused from the function cache, while `answer` has to
+// be compiled again.
+
+#[inline(never)]
+fn answer() -> u32 {
+    41
+}
+
+#[inline(never)]
+fn unchanged(x: u32) -> u32 {
+    x * 3 + 1
+}
+
+fn main() {
+    println!("{} {}", answer(), unchanged(2));
+}
@@ -0,0 +1,18 @@
+// Compiled with `function_section=boot_*:.text.boot`. Both `boot_*` functions have to be placed in
+// `.text.boot`, while `main` stays in a regular text section.
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn boot_init() -> u32 {
+    40
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn boot_main() -> u32 {
+    boot_init() + 2
+}
+
+fn main() {
+    assert_eq!(boot_main(), 42);
+}
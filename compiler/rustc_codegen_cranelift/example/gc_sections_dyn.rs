@@ -0,0 +1,37 @@
+// Compiled with `-Zfunction-sections=yes` and `--gc-sections` to check that functions which are
+// only referenced through vtables and function pointers are not removed by the linker.
+
+trait Shape {
+    fn area(&self) -> u32;
+}
+
+struct Square(u32);
+
+impl Shape for Square {
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+}
+
+struct Rect(u32, u32);
+
+impl Shape for Rect {
+    fn area(&self) -> u32 {
+        self.0 * self.1
+    }
+}
+
+fn double(a: u32) -> u32 {
+    a * 2
+}
+
+static CALLBACKS: [fn(u32) -> u32; 1] = [double];
+
+fn main() {
+    let shapes: Vec<Box<dyn Shape>> = vec![Box::new(Square(3)), Box::new(Rect(2, 4))];
+    let total = shapes.iter().map(|shape| shape.area()).sum::<u32>();
+    assert_eq!(total, 17);
+
+    let callback = CALLBACKS[std::env::args().count() - 1];
+    assert_eq!(callback(total), 34);
+}
@@ -0,0 +1,13 @@
+// Built twice in the same incremental session directory, the second time with the body of `answer`
+// changed to return 42. The codegen unit of `answer` must be codegened again instead of being
+// reused from the incremental cache.
+
+mod answer {
+    pub fn answer() -> u32 {
+        41
+    }
+}
+
+fn main() {
+    println!("{}", answer::answer());
+}
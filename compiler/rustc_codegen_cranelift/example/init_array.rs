@@ -0,0 +1,26 @@
+// The functions in `.init_array` have to be called before `main`, even when the statics pointing
+// to them are never referenced and the linker is invoked with `--gc-sections`.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static INITIALIZED: AtomicU32 = AtomicU32::new(0);
+
+extern "C" fn init() {
+    INITIALIZED.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn init_with_priority() {
+    INITIALIZED.fetch_add(10, Ordering::Relaxed);
+}
+
+#[used]
+#[link_section = ".init_array"]
+static INIT: extern "C" fn() = init;
+
+#[allow(dead_code)]
+#[link_section = ".init_array.00100"]
+static INIT_WITH_PRIORITY: [extern "C" fn(); 2] = [init_with_priority, init_with_priority];
+
+fn main() {
+    assert_eq!(INITIALIZED.load(Ordering::Relaxed), 21);
+}
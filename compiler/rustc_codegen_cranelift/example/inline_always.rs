@@ -0,0 +1,69 @@
+// Compiled with `-Copt-level=3` to check that calls to `#[inline(always)]` functions are inlined
+// correctly. The inputs depend on the number of arguments to prevent constant folding.
+
+#[inline(always)]
+fn add_one(a: u32) -> u32 {
+    a.wrapping_add(1)
+}
+
+#[inline(always)]
+fn max(a: u64, b: u64) -> u64 {
+    if a > b { a } else { b }
+}
+
+#[inline(always)]
+fn classify(a: u8) -> u8 {
+    match a {
+        0 => 10,
+        1 => 20,
+        2 => 30,
+        3 => 40,
+        _ => 0,
+    }
+}
+
+#[inline(always)]
+fn swap(pair: (u16, u16)) -> (u16, u16) {
+    (pair.1, pair.0)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Big {
+    a: u64,
+    b: u64,
+    c: u64,
+}
+
+#[inline(always)]
+fn rotate(big: Big) -> Big {
+    Big { a: big.b, b: big.c, c: big.a }
+}
+
+#[inline(always)]
+fn sum(values: &[u32]) -> u32 {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < values.len() {
+        sum += values[i];
+        i += 1;
+    }
+    sum
+}
+
+fn main() {
+    let n = std::env::args().count() as u32;
+
+    assert_eq!(add_one(n), 2);
+    assert_eq!(add_one(add_one(u32::MAX - 1 + n)), 1);
+    assert_eq!(max(n as u64, 7), 7);
+    assert_eq!(max(n as u64 + 7, 7), 8);
+    assert_eq!(classify(n as u8), 20);
+    assert_eq!(classify(n as u8 + 2), 40);
+    assert_eq!(classify(n as u8 + 8), 0);
+    assert_eq!(swap((n as u16, 2)), (2, 1));
+    assert_eq!(
+        rotate(Big { a: n as u64, b: 2, c: 3 }),
+        Big { a: 2, b: 3, c: 1 }
+    );
+    assert_eq!(sum(&[n, 2, 3]), 6);
+}
@@ -0,0 +1,16 @@
+// Run with `-Cllvm-args=override_intrinsic=ctpop:call:fake_ctpop`.
+
+#![feature(core_intrinsics)]
+
+#[no_mangle]
+extern "C" fn fake_ctpop(_x: u32) -> u32 {
+    42
+}
+
+fn black_box<T>(val: T) -> T {
+    unsafe { std::ptr::read_volatile(&val) }
+}
+
+fn main() {
+    assert_eq!(std::intrinsics::ctpop(black_box(1u32)), 42);
+}
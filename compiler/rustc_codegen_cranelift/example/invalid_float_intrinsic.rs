@@ -0,0 +1,9 @@
+// Using a float intrinsic on an integer type must result in an error instead of a crash.
+
+#![feature(core_intrinsics)]
+
+fn main() {
+    unsafe {
+        std::intrinsics::fadd_fast(1i32, 2i32);
+    }
+}
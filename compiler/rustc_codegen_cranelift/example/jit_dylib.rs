@@ -0,0 +1,12 @@
+// Compiled as Rust dylib and called by `jit_dylib_user.rs` from JIT compiled code. On macOS the
+// symbols in the export table of the dylib have a leading `_`, which the names used by the JIT
+// module don't have.
+
+#[no_mangle]
+pub extern "C" fn cg_clif_jit_dylib_add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+pub fn greeting(name: &str) -> String {
+    format!("Hello {}!", name)
+}
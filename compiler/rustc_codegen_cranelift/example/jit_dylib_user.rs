@@ -0,0 +1,6 @@
+extern crate jit_dylib;
+
+fn main() {
+    assert_eq!(jit_dylib::cg_clif_jit_dylib_add(1, 2), 3);
+    assert_eq!(jit_dylib::greeting("JIT"), "Hello JIT!");
+}
@@ -0,0 +1,37 @@
+// JIT compiled with and without `disable_jit_host_features` to check that the cpu features of the
+// host are only used when enabled, which has to be indicated using `--cfg host_features`. With
+// `--cfg bench` a longer running loop is run to benchmark the difference.
+
+#[inline(never)]
+fn leading_zeros(a: u64) -> u32 {
+    a.leading_zeros()
+}
+
+/// Returns whether the machine code of `leading_zeros` contains an `lzcnt` instruction. Without
+/// `lzcnt` Cranelift uses `bsr` instead.
+#[cfg(target_arch = "x86_64")]
+fn uses_lzcnt() -> bool {
+    // Enough to cover the prologue and the `lzcnt`, which is encoded as `F3 REX.W 0F BD`.
+    let code = unsafe { std::slice::from_raw_parts(leading_zeros as *const u8, 24) };
+    code.windows(4)
+        .any(|insn| insn[0] == 0xf3 && insn[1] & 0xf0 == 0x40 && insn[2..] == [0x0f, 0xbd])
+}
+
+fn main() {
+    let n = std::env::args().count() as u64;
+    assert_eq!(leading_zeros(n), 63);
+
+    #[cfg(target_arch = "x86_64")]
+    assert_eq!(
+        uses_lzcnt(),
+        cfg!(host_features) && std::is_x86_feature_detected!("lzcnt")
+    );
+
+    if cfg!(bench) {
+        let mut sum = 0u64;
+        for i in 0..100_000_000 {
+            sum = sum.wrapping_add(u64::from(leading_zeros(i ^ sum)));
+        }
+        println!("{}", sum);
+    }
+}
@@ -0,0 +1,28 @@
+// JIT compiled multiple times into the same JIT module by `cg_clif_jit_sessions`. Every session
+// returns a pointer to `get`, which is called once all sessions are done. `get` calls `value`
+// through its symbol, so redefining `value` in a later session changes what `get` of an earlier
+// session returns, unless the sessions use a different `symbol_prefix`. With `--cfg
+// changed_signature` the signature of `value` changes, which can't be redefined.
+
+#[cfg(not(changed_signature))]
+#[inline(never)]
+fn value() -> u32 {
+    if cfg!(second) { 2 } else { 1 }
+}
+
+#[cfg(changed_signature)]
+#[inline(never)]
+fn value() -> u64 {
+    3
+}
+
+extern "C" fn get() -> u32 {
+    value() as u32
+}
+
+#[no_mangle]
+pub extern "C" fn jit_session_entry() -> extern "C" fn() -> u32 {
+    get
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+// JIT compiled by `cg_clif_jit_sessions`. `jit_session_host_value` isn't defined by any crate or
+// dylib, it is only provided by the `JitSymbolResolver` of the driver.
+
+extern "C" {
+    fn jit_session_host_value() -> u32;
+}
+
+extern "C" fn get() -> u32 {
+    unsafe { jit_session_host_value() }
+}
+
+#[no_mangle]
+pub extern "C" fn jit_session_entry() -> extern "C" fn() -> u32 {
+    get
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+// `cg_clif_undefined_symbol` isn't defined anywhere, so finalizing the JIT module must fail with an
+// error naming it.
+
+extern "C" {
+    fn cg_clif_undefined_symbol();
+}
+
+fn main() {
+    unsafe { cg_clif_undefined_symbol() }
+}
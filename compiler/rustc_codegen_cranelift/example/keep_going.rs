@@ -0,0 +1,41 @@
+// Compiled with `-Cllvm-args=keep_going=1` and multiple codegen units. Only the codegen unit of
+// the `bad` module fails to codegen, all other codegen units have to be codegened successfully.
+
+#![feature(core_intrinsics)]
+
+mod bad {
+    #[inline(never)]
+    pub fn bad() {
+        unsafe {
+            std::intrinsics::fadd_fast(1i32, 2i32);
+        }
+    }
+
+    // Make this the largest codegen unit, which is codegened first.
+    #[inline(never)]
+    pub fn filler(values: &[u32]) -> Vec<String> {
+        let mut sorted = values.iter().map(|v| v.wrapping_mul(3)).collect::<Vec<_>>();
+        sorted.sort_unstable();
+        sorted.dedup();
+        sorted.iter().rev().map(|v| format!("{:x}", v)).collect()
+    }
+}
+
+mod good_a {
+    #[inline(never)]
+    pub fn a() -> u32 {
+        1
+    }
+}
+
+mod good_b {
+    #[inline(never)]
+    pub fn b() -> u32 {
+        2
+    }
+}
+
+fn main() {
+    bad::bad();
+    println!("{:?}", bad::filler(&[good_a::a(), good_b::b()]));
+}
@@ -0,0 +1,10 @@
+// Compiled with `-Clink-dead-code`. `cg_clif_dead_function` is never used, but still has to be
+// part of the linked executable.
+
+#[allow(dead_code)]
+#[inline(never)]
+fn cg_clif_dead_function() -> u32 {
+    42
+}
+
+fn main() {}
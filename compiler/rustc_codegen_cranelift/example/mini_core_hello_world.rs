@@ -296,8 +296,28 @@ struct ExternTypeWrapper {
     unsafe {
         global_asm_test();
     }
+
+    #[cfg(all(not(jit), target_os = "linux"))]
+    unsafe {
+        // Set by a constructor placed in `.init_array` using `#[link_section]`.
+        assert_eq!(LINK_SECTION_CTOR_RAN, 1);
+    }
 }
 
+#[cfg(all(not(jit), target_os = "linux"))]
+static mut LINK_SECTION_CTOR_RAN: u8 = 0;
+
+#[cfg(all(not(jit), target_os = "linux"))]
+extern "C" fn link_section_ctor() {
+    unsafe {
+        LINK_SECTION_CTOR_RAN = 1;
+    }
+}
+
+#[cfg(all(not(jit), target_os = "linux"))]
+#[link_section = ".init_array"]
+static LINK_SECTION_CTOR: extern "C" fn() = link_section_ctor;
+
 #[cfg(all(not(jit), target_os = "linux"))]
 extern "C" {
     fn global_asm_test();
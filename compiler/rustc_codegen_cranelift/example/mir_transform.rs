@@ -0,0 +1,16 @@
+// Compiled by the `cg_clif_mir_transform` test driver, which replaces the MIR of `replace_me` with
+// the MIR of `replacement`.
+
+#[inline(never)]
+fn replace_me() -> u32 {
+    1
+}
+
+#[allow(dead_code)]
+fn replacement() -> u32 {
+    42
+}
+
+fn main() {
+    assert_eq!(replace_me(), 42);
+}
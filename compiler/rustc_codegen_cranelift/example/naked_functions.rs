@@ -0,0 +1,26 @@
+// Naked functions must not have a prologue or epilogue, so the `ret` returns to the caller.
+
+#![feature(naked_functions, asm)]
+
+#[naked]
+extern "C" fn add_one(_x: u64) -> u64 {
+    unsafe {
+        asm!("lea rax, [rdi + 1]", "ret", options(noreturn));
+    }
+}
+
+#[naked]
+pub extern "C" fn add_two(_x: u64) -> u64 {
+    unsafe {
+        asm!("leaq 2(%rdi), %rax", "ret", options(noreturn, att_syntax));
+    }
+}
+
+fn black_box<T>(val: T) -> T {
+    unsafe { std::ptr::read_volatile(&val) }
+}
+
+fn main() {
+    assert_eq!(add_one(black_box(41)), 42);
+    assert_eq!(add_two(black_box(40)), 42);
+}
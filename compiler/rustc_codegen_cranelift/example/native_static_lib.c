@@ -0,0 +1,7 @@
+// Static C library linked by `native_static_lib.rs`.
+
+int cg_clif_native_add(int a, int b) {
+    return a + b;
+}
+
+int cg_clif_native_counter = 40;
@@ -0,0 +1,19 @@
+// Links the static C library built from `native_static_lib.c`. The library is bundled into the
+// rlib of this crate, so crates using it don't need the library to be available.
+
+#[link(name = "cg_clif_native", kind = "static")]
+extern "C" {
+    fn cg_clif_native_add(a: i32, b: i32) -> i32;
+    static mut cg_clif_native_counter: i32;
+}
+
+pub fn add(a: i32, b: i32) -> i32 {
+    unsafe { cg_clif_native_add(a, b) }
+}
+
+pub fn bump_counter() -> i32 {
+    unsafe {
+        cg_clif_native_counter += 1;
+        cg_clif_native_counter
+    }
+}
@@ -0,0 +1,7 @@
+extern crate native_static_lib;
+
+fn main() {
+    assert_eq!(native_static_lib::add(1, 2), 3);
+    assert_eq!(native_static_lib::bump_counter(), 41);
+    assert_eq!(native_static_lib::bump_counter(), 42);
+}
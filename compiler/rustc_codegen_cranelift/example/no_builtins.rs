@@ -0,0 +1,15 @@
+// Calls to the memory functions must stay real calls in `#![no_builtins]` crates.
+
+#![no_builtins]
+
+extern "C" {
+    fn memset(dst: *mut u8, val: i32, count: usize) -> *mut u8;
+}
+
+pub unsafe fn copy_four(dst: *mut u8, src: *const u8) {
+    std::ptr::copy_nonoverlapping(src, dst, 4);
+}
+
+pub unsafe fn clear_sixteen(dst: *mut u8) {
+    memset(dst, 0, 16);
+}
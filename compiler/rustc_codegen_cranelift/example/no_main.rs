@@ -0,0 +1,10 @@
+// A `#![no_main]` binary defining the C `main` function itself. Built both normally and with the
+// `disable_entry_wrapper` option, neither of which may generate an entry wrapper clashing with it.
+
+#![no_main]
+
+#[no_mangle]
+pub extern "C" fn main(_argc: i32, _argv: *const *const u8) -> i32 {
+    println!("Hello from a custom entry");
+    0
+}
@@ -0,0 +1,30 @@
+// Run with `wrap` when compiled with `-Coverflow-checks=off` and with the name of an operation
+// when compiled with `-Coverflow-checks=on`, in which case the operation has to panic.
+
+fn main() {
+    let mode = std::env::args().nth(1).unwrap();
+    // Derived from the arguments to prevent const propagation.
+    let one = std::env::args().count() as u8 - 1;
+    let max = u8::MAX - 1 + one;
+    let min = i32::MIN + one as i32 - 1;
+
+    match &*mode {
+        "wrap" => {
+            assert_eq!(max + one, 0);
+            assert_eq!(0 - one, u8::MAX);
+            assert_eq!(max * 2, 254);
+            assert_eq!(-min, i32::MIN);
+            assert_eq!(one << (8 + one as u32), 2);
+            // The arithmetic operator impls of libcore inherit the overflow checks of this crate.
+            assert_eq!([max, one].iter().sum::<u8>(), 0);
+            assert_eq!(std::ops::Add::add(i128::MAX - 1 + one as i128, 1), i128::MIN);
+        }
+        "add" => println!("{}", max + one),
+        "sub" => println!("{}", 0 - one),
+        "mul" => println!("{}", max * 2),
+        "neg" => println!("{}", -min),
+        "shl" => println!("{}", one << (8 + one as u32)),
+        "sum" => println!("{}", [max, one].iter().sum::<u8>()),
+        _ => unreachable!(),
+    }
+}
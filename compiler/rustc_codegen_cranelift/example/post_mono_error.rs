@@ -0,0 +1,16 @@
+// The constant only fails to evaluate once `T` is known to be zero sized. This is reported while
+// collecting the mono items, which must abort compilation with that error instead of an ICE.
+
+struct Check<T>(T);
+
+impl<T> Check<T> {
+    const NOT_ZST: usize = 0 - (std::mem::size_of::<T>() == 0) as usize;
+}
+
+fn not_zst<T>() -> usize {
+    Check::<T>::NOT_ZST
+}
+
+fn main() {
+    not_zst::<()>();
+}
@@ -0,0 +1,49 @@
+#![feature(repr_simd, platform_intrinsics)]
+#![allow(non_camel_case_types)]
+
+#[repr(simd)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct f32x4(f32, f32, f32, f32);
+
+#[repr(simd)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct i32x4(i32, i32, i32, i32);
+
+#[repr(simd)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct i16x8(i16, i16, i16, i16, i16, i16, i16, i16);
+
+extern "platform-intrinsic" {
+    fn simd_add<T>(x: T, y: T) -> T;
+    fn simd_sub<T>(x: T, y: T) -> T;
+    fn simd_mul<T>(x: T, y: T) -> T;
+    fn simd_div<T>(x: T, y: T) -> T;
+    fn simd_xor<T>(x: T, y: T) -> T;
+}
+
+// Prevent constant folding by passing the vectors through a function call.
+#[inline(never)]
+fn black_box<T>(v: T) -> T {
+    v
+}
+
+fn main() {
+    unsafe {
+        let a = black_box(f32x4(1.0, 2.5, -3.0, 4.0));
+        let b = black_box(f32x4(0.5, 0.5, 1.0, -8.0));
+        assert_eq!(simd_add(a, b), f32x4(1.5, 3.0, -2.0, -4.0));
+        assert_eq!(simd_sub(a, b), f32x4(0.5, 2.0, -4.0, 12.0));
+        assert_eq!(simd_mul(a, b), f32x4(0.5, 1.25, -3.0, -32.0));
+        assert_eq!(simd_div(a, b), f32x4(2.0, 5.0, -3.0, -0.5));
+
+        let c = black_box(i32x4(1, i32::MAX, -3, 4));
+        let d = black_box(i32x4(2, 1, 3, -5));
+        assert_eq!(simd_add(c, d), i32x4(3, i32::MIN, 0, -1));
+        assert_eq!(simd_mul(c, d), i32x4(2, i32::MAX, -9, -20));
+        assert_eq!(simd_xor(c, d), i32x4(3, i32::MAX - 1, -2, -1));
+
+        let e = black_box(i16x8(1, 2, 3, 4, -5, 6, 7, 300));
+        let f = black_box(i16x8(8, 7, 6, 5, 4, -3, 2, 300));
+        assert_eq!(simd_mul(e, f), i16x8(8, 14, 18, 20, -20, -18, 14, 24464));
+    }
+}
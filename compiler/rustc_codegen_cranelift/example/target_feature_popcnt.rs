@@ -0,0 +1,6 @@
+// Compiled with and without `-Ctarget-feature=-popcnt` to check that target features are passed to
+// Cranelift.
+
+pub fn count_ones(a: u64) -> u32 {
+    a.count_ones()
+}
@@ -0,0 +1,45 @@
+// Every thread must see its own copy of thread local statics, both for `#[thread_local]` and
+// `thread_local!`.
+
+#![feature(thread_local)]
+
+use std::cell::Cell;
+
+#[thread_local]
+static mut COUNTER: u32 = 1;
+
+#[thread_local]
+static ZEROED: Cell<u64> = Cell::new(0);
+
+thread_local! {
+    static NAME: Cell<&'static str> = Cell::new("main");
+}
+
+fn bump(by: u32) -> u32 {
+    unsafe {
+        COUNTER += by;
+        COUNTER
+    }
+}
+
+fn main() {
+    assert_eq!(bump(1), 2);
+    ZEROED.set(42);
+    NAME.with(|name| name.set("changed"));
+
+    let other = std::thread::spawn(|| {
+        // Every thread starts with the initial values.
+        assert_eq!(bump(10), 11);
+        assert_eq!(ZEROED.get(), 0);
+        ZEROED.set(7);
+        NAME.with(|name| assert_eq!(name.get(), "main"));
+        NAME.with(|name| name.set("other"));
+        (bump(0), ZEROED.get())
+    });
+    assert_eq!(other.join().unwrap(), (11, 7));
+
+    // Writes by the other thread are not visible here.
+    assert_eq!(bump(0), 2);
+    assert_eq!(ZEROED.get(), 42);
+    NAME.with(|name| assert_eq!(name.get(), "changed"));
+}
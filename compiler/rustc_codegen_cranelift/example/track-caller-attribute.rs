@@ -37,4 +37,38 @@ fn main() {
     assert_eq!(contained.file(), file!());
     assert_eq!(contained.line(), 17);
     assert_eq!(contained.column(), 5);
+
+    let chained = tracked_chain();
+    assert_eq!(chained.file(), file!());
+    assert_eq!(chained.line(), 41);
+    assert_eq!(chained.column(), 19);
+
+    let generic = tracked_generic(0u8);
+    assert_eq!(generic.file(), file!());
+    assert_eq!(generic.line(), 46);
+    assert_eq!(generic.column(), 19);
+
+    let method = Tracker.tracked();
+    assert_eq!(method.file(), file!());
+    assert_eq!(method.line(), 51);
+    assert_eq!(method.column(), 26);
+}
+
+#[track_caller]
+fn tracked_chain() -> &'static Location<'static> {
+    tracked()
+}
+
+#[track_caller]
+fn tracked_generic<T>(_: T) -> &'static Location<'static> {
+    Location::caller()
+}
+
+struct Tracker;
+
+impl Tracker {
+    #[track_caller]
+    fn tracked(&self) -> &'static Location<'static> {
+        Location::caller()
+    }
 }
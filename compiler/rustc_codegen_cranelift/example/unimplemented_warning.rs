@@ -0,0 +1,13 @@
+// Calling an unsupported LLVM intrinsic compiles to a trap. The warning about it must point to the
+// call.
+
+#![feature(link_llvm_intrinsics)]
+
+extern "C" {
+    #[link_name = "llvm.cg.clif.unsupported"]
+    fn unsupported_intrinsic() -> u32;
+}
+
+pub fn calls_unsupported_intrinsic() -> u32 {
+    unsafe { unsupported_intrinsic() }
+}
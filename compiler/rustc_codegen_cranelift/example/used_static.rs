@@ -0,0 +1,7 @@
+// `USED_STATIC` is never referenced, but must still be present in the final executable even though
+// the linker is invoked with `--gc-sections`.
+
+#[used]
+static USED_STATIC: [u8; 4] = *b"used";
+
+fn main() {}
@@ -0,0 +1,12 @@
+// Compiled with `CG_CLIF_TEST_BREAK_IR=break_me` to check that the `verify_ir` option reports
+// invalid Cranelift IR and with `CG_CLIF_TEST_SKIP_DEFINITION=break_me` to check that it reports
+// functions which were declared, but never defined.
+
+#[inline(never)]
+fn break_me() -> u32 {
+    42
+}
+
+fn main() {
+    assert_eq!(break_me(), 42);
+}
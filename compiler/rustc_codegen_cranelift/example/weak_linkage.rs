@@ -0,0 +1,18 @@
+#![feature(linkage)]
+
+// Both items have to be emitted as weak symbols, so another object file can override them.
+
+#[no_mangle]
+#[linkage = "weak"]
+pub extern "C" fn cg_clif_weak_fn() -> u32 {
+    42
+}
+
+#[no_mangle]
+#[linkage = "weak"]
+pub static CG_CLIF_WEAK_STATIC: u32 = 7;
+
+fn main() {
+    assert_eq!(cg_clif_weak_fn(), 42);
+    assert_eq!(CG_CLIF_WEAK_STATIC, 7);
+}
@@ -0,0 +1,16 @@
+// Nothing in this crate needs to be codegened, so its only codegen unit is empty.
+
+pub struct Zst;
+
+pub const ZST: Zst = Zst;
+
+pub const UNIT: () = ();
+
+pub fn generic<T: Default>() -> T {
+    T::default()
+}
+
+#[inline]
+pub fn inline_zst() -> Zst {
+    Zst
+}
@@ -34,20 +34,159 @@ function no_sysroot_tests() {
 }
 
 function base_sysroot_tests() {
+    echo "[BUILD] print_features"
+    $RUSTC --print cg-clif-features --target "$TARGET_TRIPLE" > target/out/features.txt
+    grep -q "^target=$TARGET_TRIPLE$" target/out/features.txt
+    grep -q "^target_supported=true$" target/out/features.txt
+    grep -q "^opt_level=none$" target/out/features.txt
+    $RUSTC --print target-features --target "$TARGET_TRIPLE" -Copt-level=3 | grep -q "^opt_level=speed_and_size$"
+
     echo "[AOT] alloc_example"
     $MY_RUSTC example/alloc_example.rs --crate-type bin --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/alloc_example
 
+    echo "[AOT] no_main"
+    $MY_RUSTC example/no_main.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/no_main | grep -q "Hello from a custom entry"
+    $MY_RUSTC example/no_main.rs --crate-name no_main_without_wrapper --crate-type bin -Cllvm-args=disable_entry_wrapper=1 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/no_main_without_wrapper | grep -q "Hello from a custom entry"
+
+    echo "[AOT] custom_entry"
+    $MY_RUSTC example/custom_entry.rs --crate-type bin -Cllvm-args=disable_entry_wrapper=1 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/custom_entry | grep -q "Hello from a custom entry"
+
+    echo "[AOT] alloc_example (external allocator shim)"
+    $MY_RUSTC example/alloc_example.rs --crate-name alloc_example_external_shim --crate-type bin --cfg external_allocator_shim -Cllvm-args=disable_allocator_shim=1 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/alloc_example_external_shim
+
     if [[ "$JIT_SUPPORTED" = "1" ]]; then
         echo "[JIT] std_example"
         $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/std_example.rs --target "$HOST_TRIPLE"
 
         echo "[JIT-lazy] std_example"
         $MY_RUSTC -Cllvm-args=mode=jit-lazy -Cprefer-dynamic example/std_example.rs --cfg lazy_jit --target "$HOST_TRIPLE"
+
+        echo "[JIT] std_example (multiple codegen units)"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic -Ccodegen-units=16 example/std_example.rs --target "$HOST_TRIPLE"
+
+        echo "[JIT] std_example (without host cpu features)"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cllvm-args=disable_jit_host_features=1 -Cprefer-dynamic example/std_example.rs --target "$HOST_TRIPLE"
+
+        echo "[JIT] jit_host_features"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/jit_host_features.rs --cfg host_features --target "$HOST_TRIPLE"
+        $MY_RUSTC -Cllvm-args=mode=jit -Cllvm-args=disable_jit_host_features=1 -Cprefer-dynamic example/jit_host_features.rs --target "$HOST_TRIPLE"
+
+        echo "[JIT] jit_undefined_symbol"
+        if $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/jit_undefined_symbol.rs --target "$HOST_TRIPLE" 2> target/out/jit_undefined_symbol.stderr; then
+            echo "JIT compiled code with an undefined symbol"
+            exit 1
+        fi
+        grep -q "symbol \`cg_clif_undefined_symbol\` not found" target/out/jit_undefined_symbol.stderr
+
+        JIT_SESSIONS="$(dirname "$RUSTC")/cg_clif_jit_sessions"
+        JIT_SESSION_FLAGS="$RUSTFLAGS -L crate=target/out --out-dir target/out"
+
+        echo "[JIT] jit_entry"
+        $JIT_SESSIONS --no-keep-module $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic --target "$HOST_TRIPLE" > target/out/jit_entry.txt
+        if [[ "$(cat target/out/jit_entry.txt)" != "1" ]]; then
+            echo "The JIT entry wasn't called instead of main"
+            exit 1
+        fi
+        $JIT_SESSIONS --no-keep-module $JIT_SESSION_FLAGS example/jit_sessions.rs --crate-name jit_entry_aot -Cllvm-args=mode=aot --target "$HOST_TRIPLE" 2> target/out/jit_entry_aot.stderr
+        grep -q "the \`jit_entry\` option is ignored in AOT mode" target/out/jit_entry_aot.stderr
+        $RUN_WRAPPER ./target/out/jit_entry_aot
+
+        echo "[JIT] run_jit_entry"
+        $JIT_SESSIONS --no-keep-module --after-analysis $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic --target "$HOST_TRIPLE" > target/out/run_jit_entry.txt
+        if [[ "$(cat target/out/run_jit_entry.txt)" != "1" ]]; then
+            echo "The JIT entry wasn't called by run_jit_entry"
+            exit 1
+        fi
+        if $JIT_SESSIONS --no-keep-module --after-analysis $JIT_SESSION_FLAGS example/jit_undefined_symbol.rs -Cprefer-dynamic --target "$HOST_TRIPLE" 2> target/out/run_jit_entry_undefined.stderr; then
+            echo "run_jit_entry didn't return an error for an undefined symbol"
+            exit 1
+        fi
+        grep -q 'SymbolNotFound { symbol: "cg_clif_undefined_symbol"' target/out/run_jit_entry_undefined.stderr
+        if $JIT_SESSIONS --after-analysis $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic -Cllvm-args=mode=jit-lazy --target "$HOST_TRIPLE" 2> target/out/run_jit_entry_lazy.stderr; then
+            echo "run_jit_entry didn't return an error for keeping a lazy JIT module"
+            exit 1
+        fi
+        grep -q '^InvalidConfig("keeping the JIT module is not supported in lazy JIT mode")$' target/out/run_jit_entry_lazy.stderr
+
+        echo "[JIT] jit_function_code"
+        $JIT_SESSIONS --no-keep-module --code-size $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic --target "$HOST_TRIPLE" 2> target/out/jit_function_code.stderr > /dev/null
+        grep -q "^jit_session_entry: [1-9][0-9]* bytes$" target/out/jit_function_code.stderr
+        # The code of a kept module's functions which weren't redefined is still known.
+        $JIT_SESSIONS --code-size $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            --next $JIT_SESSION_FLAGS example/jit_sessions.rs --cfg second -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            2> target/out/jit_function_code_kept.stderr > /dev/null
+        [[ $(grep -c "^jit_session_entry: [1-9][0-9]* bytes$" target/out/jit_function_code_kept.stderr) == 2 ]]
+
+        echo "[JIT] jit_symbol_resolver"
+        $JIT_SESSIONS --no-keep-module $JIT_SESSION_FLAGS example/jit_symbol_resolver.rs -Cprefer-dynamic --target "$HOST_TRIPLE" > target/out/jit_symbol_resolver.txt
+        if [[ "$(cat target/out/jit_symbol_resolver.txt)" != "42" ]]; then
+            echo "The import wasn't resolved using the JIT symbol resolver"
+            exit 1
+        fi
+
+        echo "[JIT] jit_sessions"
+        # The second session redefines `value`, which the function returned by the first one calls.
+        $JIT_SESSIONS $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            --next $JIT_SESSION_FLAGS example/jit_sessions.rs --cfg second -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            > target/out/jit_sessions.txt
+        if [[ "$(tr '\n' ' ' < target/out/jit_sessions.txt)" != "2 2 " ]]; then
+            echo "A JIT session didn't redefine a function of the previous session"
+            exit 1
+        fi
+
+        echo "[JIT] jit_sessions (changed signature)"
+        if $JIT_SESSIONS $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            --next $JIT_SESSION_FLAGS example/jit_sessions.rs --cfg changed_signature -Cprefer-dynamic -Crelocation-model=pic --target "$HOST_TRIPLE" \
+            2> target/out/jit_sessions_changed_signature.stderr; then
+            echo "A JIT session redefined a function with a different signature"
+            exit 1
+        fi
+        grep -q "can't redefine \`_ZN12jit_sessions5value17h[0-9a-f]*E\` in the kept JIT module: its signature changed" target/out/jit_sessions_changed_signature.stderr
+
+        echo "[JIT] jit_sessions (symbol_prefix)"
+        # Both sessions define the same symbols, unless they use a different prefix.
+        $JIT_SESSIONS $JIT_SESSION_FLAGS example/jit_sessions.rs -Cprefer-dynamic -Crelocation-model=pic -Cllvm-args=symbol_prefix=first_ --target "$HOST_TRIPLE" \
+            --next $JIT_SESSION_FLAGS example/jit_sessions.rs --cfg second -Cprefer-dynamic -Crelocation-model=pic -Cllvm-args=symbol_prefix=second_ --target "$HOST_TRIPLE" \
+            > target/out/jit_sessions_symbol_prefix.txt
+        if [[ "$(tr '\n' ' ' < target/out/jit_sessions_symbol_prefix.txt)" != "1 2 " ]]; then
+            echo "A JIT session with a different symbol prefix redefined a function of another session"
+            exit 1
+        fi
+
+        if [[ "$HOST_TRIPLE" == *"apple-darwin"* ]]; then
+            # The symbols of the dylib have to be registered without their leading `_`.
+            echo "[JIT] jit_dylib_user"
+            $MY_RUSTC example/jit_dylib.rs --crate-type dylib -Cprefer-dynamic --target "$HOST_TRIPLE"
+            $MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/jit_dylib_user.rs --target "$HOST_TRIPLE"
+        else
+            echo "[JIT] jit_dylib_user (skipped)"
+        fi
     else
         echo "[JIT] std_example (skipped)"
     fi
 
+    echo "[AOT] opt-level mapping"
+    # Every option whose default depends on the opt-level is pinned, so the MIR and set of functions
+    # codegened by cg_clif are the same. Only the Cranelift opt_level differs.
+    function cg_stats() {
+        CG_CLIF_DISPLAY_CG_TIME=1 $MY_RUSTC example/std_example.rs --crate-name "opt_level_$1" --crate-type bin --emit obj -Copt-level="$1" \
+            -Cdebug-assertions=off -Coverflow-checks=off -Zshare-generics=no -Zinline-in-all-cgus=no -Zmir-opt-level=1 --target "$TARGET_TRIPLE" \
+            | sed -n 's/.*\] functions: \([0-9]*\), .*code bytes: \([0-9]*\).*/\1 \2/p' | awk '{ functions += $1; code_bytes += $2 } END { print functions, code_bytes }'
+    }
+    read -r functions_0 code_bytes_0 <<< "$(cg_stats 0)"
+    read -r functions_s code_bytes_s <<< "$(cg_stats s)"
+    echo "code bytes: $code_bytes_0 (opt-level=0), $code_bytes_s (opt-level=s)"
+    [[ "$functions_0" -eq "$functions_s" ]]
+    if [[ "$code_bytes_s" -ge "$code_bytes_0" ]]; then
+        echo "opt-level=s didn't reduce the code size"
+        exit 1
+    fi
+
     echo "[AOT] dst_field_align"
     # FIXME Re-add -Zmir-opt-level=2 once rust-lang/rust#67529 is fixed.
     $MY_RUSTC example/dst-field-align.rs --crate-name dst_field_align --crate-type bin --target "$TARGET_TRIPLE"
@@ -57,14 +196,751 @@ function base_sysroot_tests() {
     $MY_RUSTC example/std_example.rs --crate-type bin --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/std_example arg
 
+    echo "[AOT] closures"
+    $MY_RUSTC example/closures.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/closures
+
+    echo "[AOT] inline_always"
+    $MY_RUSTC example/inline_always.rs --crate-type bin --emit obj,link -Ccodegen-units=1 -Copt-level=3 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/inline_always
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        # Without optimizations `max` is called, with optimizations the call is inlined, so nothing
+        # references it anymore.
+        $MY_RUSTC example/inline_always.rs --crate-name inline_always_debug --crate-type bin --emit obj -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        objdump -dr target/out/inline_always_debug.o | grep -q "R_.*19inline_always_debug3max17h"
+        if objdump -dr target/out/inline_always.o | grep "R_.*13inline_always3max17h"; then
+            echo "call to #[inline(always)] function wasn't inlined"
+            exit 1
+        fi
+    fi
+
+    echo "[AOT] calling_conventions"
+    $MY_RUSTC example/calling_conventions.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/calling_conventions
+
+    echo "[AOT] thread_local_static"
+    $MY_RUSTC example/thread_local_static.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/thread_local_static
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        if $MY_RUSTC example/thread_local_static.rs --crate-name thread_local_static_le --crate-type bin -Ztls-model=local-exec --target "$TARGET_TRIPLE" 2> target/out/thread_local_static_le.stderr; then
+            echo "-Ztls-model=local-exec was accepted"
+            exit 1
+        fi
+        grep -q "the \`local-exec\` TLS model is not supported" target/out/thread_local_static_le.stderr
+    fi
+
+    echo "[AOT] stable object file order"
+    for i in 1 2; do
+        $MY_RUSTC example/std_example.rs --crate-name object_order --crate-type bin -Ccodegen-units=16 --print link-args --target "$TARGET_TRIPLE" \
+            | grep -o 'object_order\.[^ "]*\.o' | grep -v "allocator\|metadata" > target/out/object_order_$i.txt
+    done
+    diff target/out/object_order_1.txt target/out/object_order_2.txt
+    # rustc sorts the codegen units by size, so they are only in name order when cg_clif sorts them.
+    if [[ $(wc -l < target/out/object_order_1.txt) -lt 2 ]]; then
+        echo "Expected multiple codegen units"
+        exit 1
+    fi
+    if ! LC_ALL=C sort -c target/out/object_order_1.txt; then
+        echo "The object files are not passed to the linker in the order of their names"
+        exit 1
+    fi
+
     echo "[AOT] subslice-patterns-const-eval"
     $MY_RUSTC example/subslice-patterns-const-eval.rs --crate-type bin -Cpanic=abort --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/subslice-patterns-const-eval
 
+    echo "[AOT] simd_arith"
+    $MY_RUSTC example/simd_arith.rs --crate-type bin --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/simd_arith
+
+    echo "[BUILD] unimplemented_warning"
+    $MY_RUSTC example/unimplemented_warning.rs --crate-type lib --emit obj --target "$TARGET_TRIPLE" 2> target/out/unimplemented_warning.stderr
+    grep -q "unsupported llvm intrinsic llvm.cg.clif.unsupported in \`.*calls_unsupported_intrinsic" target/out/unimplemented_warning.stderr
+    grep -q "example/unimplemented_warning.rs:12:14" target/out/unimplemented_warning.stderr
+
+    echo "[BUILD] post_mono_error"
+    if $MY_RUSTC example/post_mono_error.rs --crate-type bin --target "$TARGET_TRIPLE" 2> target/out/post_mono_error.stderr; then
+        echo "post_mono_error compiled successfully"
+        exit 1
+    fi
+    grep -q "error\[E0080\]" target/out/post_mono_error.stderr
+    if grep -q "internal compiler error\|panicked at" target/out/post_mono_error.stderr; then
+        cat target/out/post_mono_error.stderr
+        exit 1
+    fi
+
     echo "[AOT] track-caller-attribute"
     $MY_RUSTC example/track-caller-attribute.rs --crate-type bin -Cpanic=abort --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/track-caller-attribute
 
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] used_static"
+        $MY_RUSTC example/used_static.rs --crate-type bin -Clink-arg=-Wl,--gc-sections --target "$TARGET_TRIPLE"
+        nm ./target/out/used_static | grep -q USED_STATIC
+        # Only the section of the `#[used]` static is retained, not everything else in `.rodata`.
+        $MY_RUSTC example/used_static.rs --crate-name used_static_obj --crate-type bin --emit obj -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        readelf -S -W target/out/used_static_obj.o | grep -q " \.rodata\.[^ ]*USED_STATIC"
+    else
+        echo "[AOT] used_static (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] debuginfo_names"
+        $MY_RUSTC example/debuginfo_names.rs --crate-type bin --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/debuginfo_names
+        readelf --debug-dump=info ./target/out/debuginfo_names > target/out/debuginfo_names.txt
+        grep -q "DW_AT_name .*: generic::<u32>$" target/out/debuginfo_names.txt
+        grep -q "DW_AT_name .*: main::{closure#0}$" target/out/debuginfo_names.txt
+        grep -q "DW_AT_linkage_name.*: _ZN15debuginfo_names7generic" target/out/debuginfo_names.txt
+    else
+        echo "[AOT] debuginfo_names (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] init_array"
+        $MY_RUSTC example/init_array.rs --crate-type bin -Clink-arg=-Wl,--gc-sections --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/init_array
+    else
+        echo "[AOT] init_array (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] weak_linkage"
+        $MY_RUSTC example/weak_linkage.rs --crate-type bin --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/weak_linkage
+        nm ./target/out/weak_linkage | grep -q " W cg_clif_weak_fn"
+        nm ./target/out/weak_linkage | grep -q " V CG_CLIF_WEAK_STATIC"
+    else
+        echo "[AOT] weak_linkage (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] link_dead_code"
+        $MY_RUSTC example/link_dead_code.rs --crate-type bin -Clink-dead-code --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/link_dead_code
+        nm ./target/out/link_dead_code | grep -q " t .*cg_clif_dead_function"
+    else
+        echo "[AOT] link_dead_code (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] function_section"
+        $MY_RUSTC example/function_sections.rs --crate-type bin --emit obj,link -Ccodegen-units=1 -Cllvm-args=function_section=boot_*:.text.boot --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/function_sections
+        [[ $(readelf -S -W target/out/function_sections.o | grep -c " \.text\.boot ") -eq 2 ]]
+        readelf -S -W target/out/function_sections.o | grep -q "\.text\._ZN17function_sections4main"
+    else
+        echo "[AOT] function_section (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] symbol_prefix"
+        $MY_RUSTC example/used_static.rs --crate-name symbol_prefix --crate-type bin -Cllvm-args=symbol_prefix=cg_clif_prefix_ -Cllvm-args=symbol_map=target/out/symbol_prefix_map.txt --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/symbol_prefix
+        nm ./target/out/symbol_prefix | grep -q "cg_clif_prefix_.*USED_STATIC"
+        # The symbol map lists the prefixed symbols.
+        grep -q '^cg_clif_prefix__ZN13symbol_prefix11USED_STATIC17h[0-9a-f]*E ' target/out/symbol_prefix_map.txt
+        grep -q '^cg_clif_prefix__ZN13symbol_prefix4main17h[0-9a-f]*E ' target/out/symbol_prefix_map.txt
+        if grep -q '^_ZN13symbol_prefix' target/out/symbol_prefix_map.txt; then
+            echo "The symbol map contains unprefixed symbols"
+            exit 1
+        fi
+        if $MY_RUSTC example/used_static.rs --crate-name symbol_prefix_lib --crate-type lib -Cllvm-args=symbol_prefix=cg_clif_prefix_ --target "$TARGET_TRIPLE" 2> target/out/symbol_prefix_lib.stderr; then
+            echo "Compiled an rlib with a symbol prefix"
+            exit 1
+        fi
+        grep -q "symbol_prefix\` option is not supported" target/out/symbol_prefix_lib.stderr
+    else
+        echo "[AOT] symbol_prefix (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] no_builtins"
+        $MY_RUSTC example/no_builtins.rs --crate-name no_builtins --crate-type lib --emit obj -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        nm target/out/no_builtins*.o | grep -q " U memcpy"
+        nm target/out/no_builtins*.o | grep -q " U memset"
+    else
+        echo "[AOT] no_builtins (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == "x86_64"*"linux"* ]]; then
+        echo "[AOT] naked_functions"
+        $MY_RUSTC example/naked_functions.rs --crate-type bin --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/naked_functions
+        # Internal naked functions must be local symbols, not hidden global symbols.
+        nm target/out/naked_functions.o | grep -q " t .*7add_one"
+        if nm target/out/naked_functions.o | grep -q " T .*7add_one"; then
+            echo "Internal naked function is a global symbol"
+            exit 1
+        fi
+    else
+        echo "[AOT] naked_functions (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == "x86_64"* ]]; then
+        echo "[AOT] target_feature_popcnt"
+        $MY_RUSTC example/target_feature_popcnt.rs --crate-name feature_default --crate-type lib --emit asm -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        grep -q popcnt target/out/feature_default.s
+        $MY_RUSTC example/target_feature_popcnt.rs --crate-name feature_disabled --crate-type lib --emit asm -Ccodegen-units=1 -Ctarget-feature=-popcnt --target "$TARGET_TRIPLE"
+        if grep -q popcnt target/out/feature_disabled.s; then
+            echo "popcnt used despite -Ctarget-feature=-popcnt"
+            exit 1
+        fi
+    else
+        echo "[AOT] target_feature_popcnt (skipped)"
+    fi
+
+    echo "[AOT] emit_asm_and_ir"
+    rm -rf target/out/emit_asm_and_ir_incr
+    # The second session reuses nothing, as the outputs can only be produced by codegening.
+    for session in 1 2; do
+        rm -f target/out/emit_asm_and_ir.s target/out/emit_asm_and_ir.ll
+        $MY_RUSTC example/std_example.rs --crate-name emit_asm_and_ir --crate-type bin --emit link,asm=target/out/emit_asm_and_ir.s,llvm-ir -Ccodegen-units=2 -Cincremental=target/out/emit_asm_and_ir_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE"
+        grep -q "^/\* module emit_asm_and_ir\." target/out/emit_asm_and_ir.s
+        grep -q "^function u0:[0-9]*(" target/out/emit_asm_and_ir.ll
+    done
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] gc_sections_dyn"
+        $MY_RUSTC example/gc_sections_dyn.rs --crate-type bin -Zfunction-sections=yes -Clink-arg=-Wl,--gc-sections --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/gc_sections_dyn
+    else
+        echo "[AOT] gc_sections_dyn (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] custom_section"
+        echo "build info" > target/out/custom_section.txt
+        $MY_RUSTC example/used_static.rs --crate-name custom_section --crate-type bin -Cllvm-args=custom_section=.cg_clif_build_info:target/out/custom_section.txt --target "$TARGET_TRIPLE"
+        objcopy --dump-section .cg_clif_build_info=target/out/custom_section.dump ./target/out/custom_section target/out/custom_section.stripped
+        cmp target/out/custom_section.txt target/out/custom_section.dump
+    else
+        echo "[AOT] custom_section (skipped)"
+    fi
+
+    echo "[AOT] module_list"
+    $MY_RUSTC example/std_example.rs --crate-name module_list --crate-type bin -Csave-temps -Cllvm-args=module_list=target/out/module_list.txt --target "$TARGET_TRIPLE"
+    grep -q "^regular	.*module_list\..*\.o$" target/out/module_list.txt
+    grep -q "^allocator	" target/out/module_list.txt
+    cut -f2 target/out/module_list.txt | xargs ls > /dev/null
+
+    echo "[AOT] zst_items (empty codegen unit)"
+    $MY_RUSTC example/zst_items.rs --crate-name zst_items --crate-type lib -Csave-temps -Cllvm-args=module_list=target/out/zst_items.txt --target "$TARGET_TRIPLE"
+    if grep "^regular	" target/out/zst_items.txt; then
+        echo "object file emitted for empty codegen unit"
+        exit 1
+    fi
+    $MY_RUSTC example/zst_items.rs --crate-name zst_items_obj --crate-type lib --emit obj --target "$TARGET_TRIPLE"
+    nm target/out/zst_items_obj.o > /dev/null
+
+    echo "[AOT] small_allocator_shim"
+    $MY_RUSTC example/std_example.rs --crate-name alloc_shim_default --crate-type bin -Csave-temps -Cllvm-args=module_list=target/out/alloc_shim_default.txt --target "$TARGET_TRIPLE"
+    $MY_RUSTC example/std_example.rs --crate-name alloc_shim_small --crate-type bin -Copt-level=z -Csave-temps -Cllvm-args=module_list=target/out/alloc_shim_small.txt --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/alloc_shim_small arg
+    default_size=$(grep "^allocator	" target/out/alloc_shim_default.txt | cut -f2 | xargs cat | wc -c)
+    small_size=$(grep "^allocator	" target/out/alloc_shim_small.txt | cut -f2 | xargs cat | wc -c)
+    echo "allocator shim: $default_size bytes by default, $small_size bytes with opt-level=z"
+    [[ "$small_size" -lt "$default_size" ]]
+
+    echo "[BUILD] check_duplicate_symbols"
+    $MY_RUSTC example/std_example.rs --crate-name no_duplicate_symbols --crate-type bin -Ccodegen-units=16 -Cllvm-args=check_duplicate_symbols=1 --target "$TARGET_TRIPLE"
+    # Global asm is only supported on Linux.
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        if $MY_RUSTC example/duplicate_symbols.rs --crate-type lib -Ccodegen-units=16 -Cllvm-args=check_duplicate_symbols=1 --target "$TARGET_TRIPLE" 2> target/out/duplicate_symbols.stderr; then
+            echo "duplicate symbol not detected"
+            exit 1
+        fi
+        grep -q "symbol \`cg_clif_duplicate\` is defined by both \`duplicate_symbols\..*\` and \`duplicate_symbols\..*\`" target/out/duplicate_symbols.stderr
+    fi
+
+    echo "[BUILD] duplicate_export_names"
+    if $MY_RUSTC example/duplicate_export_names.rs --crate-type lib -Ccodegen-units=16 --target "$TARGET_TRIPLE" 2> target/out/duplicate_export_names.stderr; then
+        echo "duplicate export name not detected"
+        exit 1
+    fi
+    grep -q "symbol \`cg_clif_duplicate\` is already defined" target/out/duplicate_export_names.stderr
+
+    echo "[BUILD] print=cgu-names"
+    $MY_RUSTC example/std_example.rs --crate-name print_cgu_names --crate-type bin -Ccodegen-units=16 -Cllvm-args=print=cgu-names --target "$TARGET_TRIPLE" > target/out/cgu_names.txt
+    $MY_RUSTC example/std_example.rs --crate-name print_cgu_names --crate-type bin -Ccodegen-units=16 -Cllvm-args=print=cgu-names --target "$TARGET_TRIPLE" > target/out/cgu_names2.txt
+    cmp target/out/cgu_names.txt target/out/cgu_names2.txt
+    [[ $(wc -l < target/out/cgu_names.txt) -gt 1 ]]
+    if grep -v "^print_cgu_names\..*-cgu\.[0-9]*$" target/out/cgu_names.txt; then
+        echo "print=cgu-names printed something other than codegen unit names"
+        exit 1
+    fi
+    [[ ! -e target/out/print_cgu_names ]]
+
+    echo "[BUILD] max_function_size"
+    if $MY_RUSTC example/std_example.rs --crate-name max_function_size --crate-type bin -Cllvm-args=max_function_size=10 --target "$TARGET_TRIPLE" 2> target/out/max_function_size.stderr; then
+        echo "max_function_size not enforced"
+        exit 1
+    fi
+    grep -q "error: function \`.*\` exceeds the maximum function size of 10 instructions" target/out/max_function_size.stderr
+    # The error mentions the symbol name with the symbol prefix applied.
+    if $MY_RUSTC example/std_example.rs --crate-name max_function_size_prefix --crate-type bin -Cllvm-args=max_function_size=10 -Cllvm-args=symbol_prefix=cg_clif_prefix_ --target "$TARGET_TRIPLE" 2> target/out/max_function_size_prefix.stderr; then
+        echo "max_function_size not enforced"
+        exit 1
+    fi
+    grep -q "error: function \`cg_clif_prefix_.*\` exceeds the maximum function size" target/out/max_function_size_prefix.stderr
+
+    echo "[AOT] std_example (verify_ir)"
+    $MY_RUSTC example/std_example.rs --crate-name verify_ir --crate-type bin -Cllvm-args=verify_ir=1 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/verify_ir arg
+
+    echo "[BUILD] verify_ir_broken_function"
+    if CG_CLIF_TEST_BREAK_IR=break_me $MY_RUSTC example/verify_ir.rs --crate-name verify_ir_broken --crate-type bin -Cllvm-args=verify_ir=1 --target "$TARGET_TRIPLE" 2> target/out/verify_ir_broken.stderr; then
+        echo "verify_ir didn't reject the broken function"
+        exit 1
+    fi
+    grep -q "cranelift verify error in \`_ZN16verify_ir_broken8break_me" target/out/verify_ir_broken.stderr
+
+    echo "[BUILD] verify_ir_undefined_function"
+    if CG_CLIF_TEST_SKIP_DEFINITION=break_me $MY_RUSTC example/verify_ir.rs --crate-name verify_ir_undefined --crate-type bin -Cllvm-args=verify_ir=1 --target "$TARGET_TRIPLE" 2> target/out/verify_ir_undefined.stderr; then
+        echo "verify_ir didn't reject the undefined function"
+        exit 1
+    fi
+    grep -q "function \`_ZN19verify_ir_undefined8break_me.*\` was declared, but never defined" target/out/verify_ir_undefined.stderr
+
+    echo "[BUILD] compile_error_mir"
+    if CG_CLIF_TEST_BREAK_IR=break_me $MY_RUSTC example/verify_ir.rs --crate-name compile_error_mir --crate-type bin --target "$TARGET_TRIPLE" 2> target/out/compile_error_mir.stderr; then
+        echo "compiling the broken function didn't fail"
+        exit 1
+    fi
+    grep -q "failed to compile \`_ZN17compile_error_mir8break_me.* using Cranelift" target/out/compile_error_mir.stderr
+    grep -q "fn break_me() -> u32 {" target/out/compile_error_mir.stderr
+
+    echo "[AOT] object_per_function"
+    rm -rf target/out/object_per_function_incr
+    for session in 1 2; do
+        CG_CLIF_DISPLAY_CG_TIME=1 $MY_RUSTC example/std_example.rs --crate-name object_per_function --crate-type bin -Ccodegen-units=4 -Cincremental=target/out/object_per_function_incr -Cllvm-args=disable_incr_cache=0 -Csave-temps -Cllvm-args=object_per_function=1 -Cllvm-args=module_list=target/out/object_per_function_$session.txt --target "$TARGET_TRIPLE" > target/out/object_per_function_$session.stats
+        [[ $(grep -c "^regular	.*\.fn[0-9]*\.rcgu\.o$" target/out/object_per_function_$session.txt) -gt 10 ]]
+        $RUN_WRAPPER ./target/out/object_per_function arg
+    done
+    # The second session reuses the object files of all codegen units and their functions.
+    grep -q "\] functions: " target/out/object_per_function_1.stats
+    if grep -q "\] functions: " target/out/object_per_function_2.stats; then
+        echo "the object_per_function codegen units were not reused"
+        exit 1
+    fi
+    cmp <(sort target/out/object_per_function_1.txt) <(sort target/out/object_per_function_2.txt)
+
+    echo "[AOT] overflow_checks"
+    $MY_RUSTC example/overflow_checks.rs --crate-name overflow_checks_off --crate-type bin -Coverflow-checks=off --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/overflow_checks_off wrap
+    $MY_RUSTC example/overflow_checks.rs --crate-name overflow_checks_on --crate-type bin -Coverflow-checks=on --target "$TARGET_TRIPLE"
+    for op in add sub mul neg shl sum; do
+        if $RUN_WRAPPER ./target/out/overflow_checks_on $op 2> target/out/overflow_checks_on.stderr; then
+            echo "$op didn't panic on overflow"
+            exit 1
+        fi
+        grep -q "attempt to .* overflow" target/out/overflow_checks_on.stderr
+    done
+
+    echo "[AOT] codegen_roots"
+    $MY_RUSTC example/codegen_roots.rs --crate-type bin -Ccodegen-units=1 -Cllvm-args=codegen_roots=cg_clif_root* --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/codegen_roots
+    nm ./target/out/codegen_roots | grep -q " T _\?cg_clif_root"
+    if nm ./target/out/codegen_roots | grep "cg_clif_unreached"; then
+        echo "function not reachable from the codegen roots was codegened"
+        exit 1
+    fi
+
+    echo "[AOT] std_example (codegen_roots)"
+    $MY_RUSTC example/std_example.rs --crate-name codegen_roots_std --crate-type bin -Ccodegen-units=1 -Cllvm-args=codegen_roots=none --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/codegen_roots_std arg
+
+    if [[ "$HOST_TRIPLE" == "$TARGET_TRIPLE" && "$HOST_TRIPLE" != *"windows"* ]]; then
+        echo "[AOT] native_static_lib"
+        cc -c example/native_static_lib.c -o target/out/native_static_lib.o
+        ar rcs target/out/libcg_clif_native.a target/out/native_static_lib.o
+        $MY_RUSTC example/native_static_lib.rs --crate-type lib -L native=target/out --target "$TARGET_TRIPLE"
+        # The library has to be bundled into the rlib.
+        rm target/out/libcg_clif_native.a
+        $MY_RUSTC example/native_static_lib_user.rs --crate-type bin --extern native_static_lib=target/out/libnative_static_lib.rlib --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/native_static_lib_user
+    else
+        echo "[AOT] native_static_lib (skipped)"
+    fi
+
+    if [[ "$HOST_TRIPLE" == "$TARGET_TRIPLE" && "$HOST_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] cdylib"
+        $MY_RUSTC example/cdylib.rs --crate-type cdylib --target "$TARGET_TRIPLE"
+        cc example/dlopen_cdylib.c -o target/out/dlopen_cdylib -ldl
+        ./target/out/dlopen_cdylib ./target/out/libcdylib.so
+        # Only the `#[no_mangle]` functions are exported.
+        nm -D --defined-only target/out/libcdylib.so | grep -q " cg_clif_cdylib_sum$"
+        if nm -D --defined-only target/out/libcdylib.so | grep -q "_ZN\|__rust\|__cg_clif"; then
+            echo "cdylib exports internal symbols"
+            exit 1
+        fi
+    else
+        echo "[AOT] cdylib (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == "x86_64"*"linux"* ]]; then
+        echo "[AOT] compress_debug_sections"
+        $MY_RUSTC example/std_example.rs --crate-name debug_uncompressed --crate-type bin --emit obj -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        $MY_RUSTC example/std_example.rs --crate-name debug_compressed --crate-type bin --emit obj -Ccodegen-units=1 -Cllvm-args=compress_debug_sections=1 --target "$TARGET_TRIPLE"
+        uncompressed_size=$(wc -c < target/out/debug_uncompressed.o)
+        compressed_size=$(wc -c < target/out/debug_compressed.o)
+        echo "object file: $uncompressed_size bytes uncompressed, $compressed_size bytes compressed"
+        [[ "$compressed_size" -lt "$uncompressed_size" ]]
+        readelf -S -W target/out/debug_compressed.o | grep -q "\.debug_info .* C "
+        # The decompressed debuginfo must still be readable, including the relocated references.
+        readelf --debug-dump=info target/out/debug_compressed.o | grep -q "DW_AT_name.*: main"
+        $MY_RUSTC example/std_example.rs --crate-name debug_compressed_bin --crate-type bin -Cllvm-args=compress_debug_sections=1 --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/debug_compressed_bin arg
+        readelf --debug-dump=info ./target/out/debug_compressed_bin | grep -q "DW_AT_name.*: main"
+    else
+        echo "[AOT] compress_debug_sections (skipped)"
+    fi
+
+    if [[ "$HOST_TRIPLE" == *"linux"* && "$HOST_TRIPLE" == "$TARGET_TRIPLE" ]]; then
+        echo "[AOT] coverage"
+        $MY_RUSTC example/coverage.rs --crate-name coverage --crate-type bin -Cllvm-args=coverage=1 --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/coverage
+        # The coverage map isn't referenced by any code, but must survive `--gc-sections`.
+        objcopy --dump-section __cg_clif_covmap=target/out/coverage.covmap ./target/out/coverage target/out/coverage.stripped
+        grep -q '"function":"_ZN8coverage7covered' target/out/coverage.covmap
+        grep -q '"file":"example/coverage.rs"' target/out/coverage.covmap
+    else
+        echo "[AOT] coverage (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] panic_strategy_unwind_tables"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_panic --crate-type lib --emit obj=target/out/mini_core_panic_default.o -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_panic --crate-type lib --emit obj=target/out/mini_core_panic_unwind.o -Ccodegen-units=1 -Cpanic=unwind --target "$TARGET_TRIPLE"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_panic --crate-type lib --emit obj=target/out/mini_core_panic_abort.o -Ccodegen-units=1 -Cpanic=abort --target "$TARGET_TRIPLE"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_panic --crate-type lib --emit obj=target/out/mini_core_panic_uwtable.o -Ccodegen-units=1 -Cpanic=abort -Cforce-unwind-tables=yes --target "$TARGET_TRIPLE"
+        # An explicit `-Cpanic=unwind` behaves exactly like the default, which is `-Cpanic=abort` for
+        # the cg_clif driver.
+        cmp target/out/mini_core_panic_default.o target/out/mini_core_panic_unwind.o
+        if readelf -S -W target/out/mini_core_panic_abort.o | grep -q " \.eh_frame "; then
+            echo "unwind tables emitted despite -Cpanic=abort"
+            exit 1
+        fi
+        readelf -S -W target/out/mini_core_panic_uwtable.o | grep -q " \.eh_frame "
+    else
+        echo "[AOT] panic_strategy_unwind_tables (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] elf_e_flags"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_e_flags --crate-type lib --emit obj -Ccodegen-units=1 -Cllvm-args=elf_e_flags=0x1234 --target "$TARGET_TRIPLE"
+        readelf -h target/out/mini_core_e_flags.o | grep -q "Flags: *0x1234\b"
+    else
+        echo "[AOT] elf_e_flags (skipped)"
+    fi
+
+    if [[ "$HOST_TRIPLE" == "x86_64"*"linux"* ]]; then
+        echo "[AOT] elf_os_abi"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_freebsd --crate-type lib --emit obj -Ccodegen-units=1 --target x86_64-unknown-freebsd
+        readelf -h target/out/mini_core_freebsd*.o | grep -q "OS/ABI:.*FreeBSD"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_os_abi --crate-type lib --emit obj -Ccodegen-units=1 -Cllvm-args=elf_os_abi=3 -Cllvm-args=elf_e_flags=0 --target "$HOST_TRIPLE"
+        readelf -h target/out/mini_core_os_abi*.o | grep -q "OS/ABI:.*UNIX - \(GNU\|Linux\)"
+    else
+        echo "[AOT] elf_os_abi (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] elf_os_abi (global_asm)"
+        $MY_RUSTC example/mini_core_hello_world.rs --crate-name mini_core_hello_world_os_abi --crate-type bin --emit obj -Ccodegen-units=1 -Cllvm-args=elf_os_abi=3 --target "$TARGET_TRIPLE"
+        readelf -h target/out/mini_core_hello_world_os_abi*.o | grep -q "OS/ABI:.*UNIX - \(GNU\|Linux\)"
+    else
+        echo "[AOT] elf_os_abi (global_asm) (skipped)"
+    fi
+
+    if [[ "$HOST_TRIPLE" == "x86_64"*"linux"* && "$HOST_TRIPLE" == "$TARGET_TRIPLE" ]]; then
+        echo "[AOT] force_frame_pointers"
+        $MY_RUSTC example/frame_pointers.rs --crate-type bin -Cforce-frame-pointers=yes --target "$TARGET_TRIPLE"
+        $RUN_WRAPPER ./target/out/frame_pointers
+        objdump -d --no-show-raw-insn --disassemble=cg_clif_leaf ./target/out/frame_pointers | grep -A1 "push *%rbp" | grep -q "mov *%rsp,%rbp"
+    else
+        echo "[AOT] force_frame_pointers (skipped)"
+    fi
+
+    if [[ "$HOST_TRIPLE" == "x86_64"* && "$HOST_TRIPLE" != *"windows"* ]]; then
+        echo "[AOT] debuginfo on unsupported target"
+        $MY_RUSTC example/mini_core.rs --crate-name mini_core_msvc --crate-type lib --emit obj --target x86_64-pc-windows-msvc 2> target/out/mini_core_msvc.stderr
+        grep -q "warning: debuginfo is not supported" target/out/mini_core_msvc.stderr
+        if objdump -h target/out/mini_core_msvc.o | grep -q "\.debug_"; then
+            echo "debuginfo emitted for unsupported target"
+            exit 1
+        fi
+    else
+        echo "[AOT] debuginfo on unsupported target (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] cold_functions"
+        $MY_RUSTC example/cold_functions.rs --crate-type bin --emit obj,link -Zfunction-sections=yes -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        readelf -SW target/out/cold_functions.o > target/out/cold_functions.sections
+        grep -q " \.text\.unlikely\.cg_clif_cold_fn " target/out/cold_functions.sections
+        grep -q " \.text\.unlikely\..*cold_path" target/out/cold_functions.sections
+        grep -q " \.text\.cg_clif_hot_fn " target/out/cold_functions.sections
+        $RUN_WRAPPER ./target/out/cold_functions
+        # Without `-Zfunction-sections` all functions stay in `.text`.
+        $MY_RUSTC example/cold_functions.rs --crate-name cold_functions_text --crate-type bin --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        readelf -SW target/out/cold_functions_text.o > target/out/cold_functions_text.sections
+        if grep "\.text\." target/out/cold_functions_text.sections; then
+            echo "function moved to a section of its own without -Zfunction-sections"
+            exit 1
+        fi
+        $RUN_WRAPPER ./target/out/cold_functions_text
+    else
+        echo "[AOT] cold_functions (skipped)"
+    fi
+
+    echo "[AOT] cgu_graph"
+    $MY_RUSTC example/std_example.rs --crate-name cgu_graph --crate-type bin -Ccodegen-units=4 -Cllvm-args=cgu_graph=target/out/cgu_graph.dot --target "$TARGET_TRIPLE"
+    head -n1 target/out/cgu_graph.dot | grep -q '^graph "cgu_graph" {$'
+    grep -q '^    "cgu_graph\.[^"]*" \[label=".* items\\nsize estimate [0-9]*"\];$' target/out/cgu_graph.dot
+    if command -v dot > /dev/null; then
+        dot -Tsvg target/out/cgu_graph.dot > /dev/null
+    fi
+
+    echo "[AOT] symbol_map"
+    $MY_RUSTC example/std_example.rs --crate-name symbol_map --crate-type bin -Ccodegen-units=4 -Cllvm-args=symbol_map=target/out/symbol_map.txt --target "$TARGET_TRIPLE"
+    grep -q '^_ZN10symbol_map4main17h[0-9a-f]*E symbol_map\.[^ ]*$' target/out/symbol_map.txt
+    LC_ALL=C sort -c target/out/symbol_map.txt
+
+    echo "[AOT] force_cgu_regen"
+    rm -rf target/out/force_cgu_regen_incr
+    for force in "" "-Cllvm-args=force_cgu_regen=*-cgu.0"; do
+        CG_CLIF_DISPLAY_CG_TIME=1 $MY_RUSTC example/std_example.rs --crate-name force_cgu_regen --crate-type bin -Ccodegen-units=4 -Cincremental=target/out/force_cgu_regen_incr -Cllvm-args=disable_incr_cache=0 $force --target "$TARGET_TRIPLE" > target/out/force_cgu_regen.txt
+    done
+    # Only the forced codegen unit is codegened again.
+    [[ $(grep -c "\] functions: " target/out/force_cgu_regen.txt) == 1 ]]
+    grep -q -- "-cgu\.0\] functions: " target/out/force_cgu_regen.txt
+
+    echo "[AOT] incremental_change"
+    rm -rf target/out/incremental_change_incr
+    for answer in 41 42; do
+        sed "s/41/$answer/" example/incremental_change.rs > target/out/incremental_change.rs
+        $MY_RUSTC target/out/incremental_change.rs --crate-type bin -Ccodegen-units=2 -Cincremental=target/out/incremental_change_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE"
+        [[ $($RUN_WRAPPER ./target/out/incremental_change) == "$answer" ]]
+    done
+
+    echo "[AOT] function_cache"
+    rm -rf target/out/function_cache_incr
+    for answer in 41 42; do
+        sed "s/41/$answer/" example/function_cache.rs > target/out/function_cache.rs
+        CG_CLIF_DISPLAY_CG_TIME=1 $MY_RUSTC target/out/function_cache.rs --crate-type bin -Ccodegen-units=1 -Cdebuginfo=0 -Cincremental=target/out/function_cache_incr -Cllvm-args=function_cache=1 -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE" > target/out/function_cache_$answer.txt
+        [[ $($RUN_WRAPPER ./target/out/function_cache) == "$answer 7" ]]
+    done
+    grep -q "\] functions: [0-9]*, cached functions: 0," target/out/function_cache_41.txt
+    grep -q "\] functions: [0-9]*, cached functions: [1-9]" target/out/function_cache_42.txt
+
+    echo "[AOT] allocator_shim_reuse"
+    rm -rf target/out/allocator_shim_reuse_incr target/out/allocator_shim_reuse.txt
+    for opt_level in 2 2 s; do
+        CG_CLIF_DISPLAY_CG_TIME=1 $MY_RUSTC example/alloc_example.rs --crate-name allocator_shim_reuse --crate-type bin -Copt-level=$opt_level -Cincremental=target/out/allocator_shim_reuse_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE" >> target/out/allocator_shim_reuse.txt
+    done
+    # The second session reuses the allocator shim, the third one needs the small variant instead.
+    [[ $(grep -c "codegen allocator shim\] start" target/out/allocator_shim_reuse.txt) == 2 ]]
+    $RUN_WRAPPER ./target/out/allocator_shim_reuse
+
+    echo "[AOT] reuse_copy_retry"
+    rm -rf target/out/reuse_copy_retry_incr
+    $MY_RUSTC example/std_example.rs --crate-name reuse_copy_retry --crate-type bin -Cincremental=target/out/reuse_copy_retry_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE"
+    # Failing first attempts are retried.
+    CG_CLIF_TEST_FAILING_COPIES=2 $MY_RUSTC example/std_example.rs --crate-name reuse_copy_retry --crate-type bin -Cincremental=target/out/reuse_copy_retry_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/reuse_copy_retry arg
+    # Failing all attempts aborts the compilation.
+    if CG_CLIF_TEST_FAILING_COPIES=4 $MY_RUSTC example/std_example.rs --crate-name reuse_copy_retry --crate-type bin -Cincremental=target/out/reuse_copy_retry_incr -Cllvm-args=disable_incr_cache=0 --target "$TARGET_TRIPLE" 2> target/out/reuse_copy_retry.stderr; then
+        echo "Compilation succeeded despite failing to copy the cached object files"
+        exit 1
+    fi
+    grep -q "unable to copy .*: simulated failure" target/out/reuse_copy_retry.stderr
+
+    echo "[AOT] mir_transform"
+    MIR_TRANSFORM="$(dirname "$RUSTC")/cg_clif_mir_transform"
+    $MIR_TRANSFORM $RUSTFLAGS -L crate=target/out --out-dir target/out example/mir_transform.rs --crate-name mir_transform --crate-type bin --target "$TARGET_TRIPLE" 2> target/out/mir_transform.stderr
+    grep -q "replaced the MIR of \`replace_me\`" target/out/mir_transform.stderr
+    $RUN_WRAPPER ./target/out/mir_transform
+
+    echo "[AOT] object_sink"
+    OBJECT_SINK="$(dirname "$RUSTC")/cg_clif_object_sink"
+    rm -f target/out/object_sink*.sink.o
+    $OBJECT_SINK target/out $RUSTFLAGS -L crate=target/out --out-dir target/out example/mod_bench.rs --crate-name object_sink --crate-type bin --target "$TARGET_TRIPLE"
+    ls target/out/object_sink*.sink.o > /dev/null
+    $RUN_WRAPPER ./target/out/object_sink
+    if $OBJECT_SINK target/out $RUSTFLAGS -L crate=target/out --out-dir target/out example/mod_bench.rs --crate-name object_sink_module_list --crate-type bin -Cllvm-args=module_list=target/out/object_sink_module_list.txt --target "$TARGET_TRIPLE" 2> target/out/object_sink_module_list.stderr; then
+        echo "module_list wasn't rejected together with an object sink"
+        exit 1
+    fi
+    grep -q "are not supported when object files are handed to an object sink" target/out/object_sink_module_list.stderr
+
+    echo "[AOT] exports"
+    $MY_RUSTC example/exports.rs --crate-type cdylib -Cllvm-args=exports_file=target/out/exports.txt -Cllvm-args=version_script=target/out/exports.map --target "$TARGET_TRIPLE"
+    grep -qx "CG_CLIF_EXPORTED_STATIC" target/out/exports.txt
+    grep -qx "cg_clif_exported_fn" target/out/exports.txt
+    if grep "rust_abi_fn\|generic_fn\|not_exported" target/out/exports.txt; then
+        echo "cdylib exports list contains Rust ABI items"
+        exit 1
+    fi
+    grep -q "^    cg_clif_exported_fn;$" target/out/exports.map
+    # A dylib has to keep exporting its metadata and the allocator shim.
+    $MY_RUSTC example/exports.rs --crate-name exports_dylib --crate-type dylib -Cprefer-dynamic -Cllvm-args=exports_file=target/out/exports_dylib.txt --target "$TARGET_TRIPLE"
+    grep -q "^rust_metadata_exports_dylib_" target/out/exports_dylib.txt
+    grep -qx "__rust_alloc" target/out/exports_dylib.txt
+    grep -q "rust_abi_fn" target/out/exports_dylib.txt
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] strip_local_symbols"
+        $MY_RUSTC example/std_example.rs --crate-name strip_locals_no --crate-type bin --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        $MY_RUSTC example/std_example.rs --crate-name strip_locals_yes --crate-type bin --emit obj,link -Ccodegen-units=1 -Cllvm-args=strip_local_symbols=1 --target "$TARGET_TRIPLE"
+        nm --extern-only --defined-only target/out/strip_locals_no*.o | awk '{ print $3 }' | sort > target/out/strip_locals_no.txt
+        nm --extern-only --defined-only target/out/strip_locals_yes*.o | awk '{ print $3 }' | sort > target/out/strip_locals_yes.txt
+        cmp target/out/strip_locals_no.txt target/out/strip_locals_yes.txt
+        if nm --defined-only target/out/strip_locals_yes*.o | grep -q " [tdrb] ."; then
+            echo "strip_local_symbols kept named local symbols"
+            exit 1
+        fi
+        $RUN_WRAPPER ./target/out/strip_locals_yes arg
+    else
+        echo "[AOT] strip_local_symbols (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] crate_hash_note"
+        rm -f target/out/crate_hash_*.txt
+        for cfg in a a b; do
+            $MY_RUSTC example/mod_bench.rs --crate-name crate_hash_$cfg --crate-type bin --cfg $cfg -Cllvm-args=crate_hash_note=1 --target "$TARGET_TRIPLE"
+            readelf -nW ./target/out/crate_hash_$cfg | sed -n '/\.note\.rustc\.crate-hash/,$p' | grep -m1 "description data" >> target/out/crate_hash_$cfg.txt
+        done
+        [[ $(wc -l < target/out/crate_hash_a.txt) == 2 && $(sort -u target/out/crate_hash_a.txt | wc -l) == 1 ]]
+        [[ $(wc -w < target/out/crate_hash_b.txt) == 10 ]]
+        if cmp -s <(head -n1 target/out/crate_hash_a.txt) target/out/crate_hash_b.txt; then
+            echo "crate hash didn't change with a different cfg"
+            exit 1
+        fi
+    else
+        echo "[AOT] crate_hash_note (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] build_id"
+        for i in 1 2; do
+            $MY_RUSTC example/mod_bench.rs --crate-name build_id --crate-type bin -Cllvm-args=build_id=1 -Clink-arg=-Wl,--build-id=none --target "$TARGET_TRIPLE"
+            readelf -nW ./target/out/build_id | grep "Build ID" > target/out/build_id_$i.txt
+        done
+        cmp target/out/build_id_1.txt target/out/build_id_2.txt
+        [[ $(wc -l < target/out/build_id_1.txt) -eq 1 ]]
+        $MY_RUSTC example/mod_bench.rs --crate-name build_id_lib --crate-type lib -Cllvm-args=build_id=1 --target "$TARGET_TRIPLE" 2> target/out/build_id_lib.stderr
+        grep -q "the \`build_id\` option is ignored for rlibs" target/out/build_id_lib.stderr
+        if ar t target/out/libbuild_id_lib.rlib | grep -q "build_id"; then
+            echo "The build-id note was added to an rlib"
+            exit 1
+        fi
+    else
+        echo "[AOT] build_id (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == "x86_64"*"linux"* ]]; then
+        echo "[AOT] relocation-model"
+        $MY_RUSTC example/mod_bench.rs --crate-name reloc_model_pic --crate-type bin -Crelocation-model=pic --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        readelf -rW target/out/reloc_model_pic*.o | grep -q "R_X86_64_PLT32\|R_X86_64_GOTPCREL"
+        $RUN_WRAPPER ./target/out/reloc_model_pic
+        $MY_RUSTC example/mod_bench.rs --crate-name reloc_model_static --crate-type bin -Crelocation-model=static --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+        if readelf -rW target/out/reloc_model_static*.o | grep -q "R_X86_64_PLT32\|R_X86_64_GOTPCREL"; then
+            echo "-Crelocation-model=static used PIC relocations"
+            exit 1
+        fi
+        $RUN_WRAPPER ./target/out/reloc_model_static
+    else
+        echo "[AOT] relocation-model (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] std_example (pie)"
+        $MY_RUSTC example/std_example.rs --crate-name std_example_pie --crate-type bin -Crelocation-model=pic --target "$TARGET_TRIPLE"
+        readelf -hW ./target/out/std_example_pie | grep -q "Type:.*DYN"
+        $RUN_WRAPPER ./target/out/std_example_pie arg
+    else
+        echo "[AOT] std_example (pie) (skipped)"
+    fi
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] std_example (full relro)"
+        $MY_RUSTC example/std_example.rs --crate-name std_example_relro --crate-type bin -Crelocation-model=pic -Clink-arg=-Wl,-z,relro,-z,now --target "$TARGET_TRIPLE"
+        readelf -lW ./target/out/std_example_relro | grep -q GNU_RELRO
+        readelf -dW ./target/out/std_example_relro | grep -q "BIND_NOW\|FLAGS.*NOW"
+        if readelf -dW ./target/out/std_example_relro | grep -q TEXTREL; then
+            echo "std_example_relro needs text relocations"
+            exit 1
+        fi
+        $RUN_WRAPPER ./target/out/std_example_relro arg
+    else
+        echo "[AOT] std_example (full relro) (skipped)"
+    fi
+
+    echo "[AOT] std_example (v0 symbol mangling)"
+    $MY_RUSTC example/std_example.rs --crate-name std_example_v0 --crate-type bin -Zsymbol-mangling-version=v0 --emit obj,link -Ccodegen-units=1 --target "$TARGET_TRIPLE"
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        nm target/out/std_example_v0*.o | grep -q " _RNv"
+    fi
+    $RUN_WRAPPER ./target/out/std_example_v0 arg
+
+    if [[ "$TARGET_TRIPLE" == *"linux"* ]]; then
+        echo "[AOT] non-executable stack"
+        if ! readelf -lW ./target/out/std_example | grep GNU_STACK | grep -qv RWE; then
+            echo "std_example has an executable stack"
+            exit 1
+        fi
+    else
+        echo "[AOT] non-executable stack (skipped)"
+    fi
+
+    echo "[AOT] invalid_float_intrinsic"
+    if $MY_RUSTC example/invalid_float_intrinsic.rs --crate-type bin --target "$TARGET_TRIPLE" 2> target/out/invalid_float_intrinsic.stderr; then
+        echo "invalid_float_intrinsic should have failed to compile"
+        exit 1
+    fi
+    grep -q "invalid monomorphization of \`fadd_fast\` intrinsic" target/out/invalid_float_intrinsic.stderr
+
+    echo "[AOT] invalid_float_intrinsic (keep_going)"
+    if $MY_RUSTC example/invalid_float_intrinsic.rs --crate-type bin -Cllvm-args=keep_going=1 --target "$TARGET_TRIPLE" 2> target/out/invalid_float_intrinsic_keep_going.stderr; then
+        echo "invalid_float_intrinsic should have failed to compile"
+        exit 1
+    fi
+    grep -q "failed to codegen 1 of" target/out/invalid_float_intrinsic_keep_going.stderr
+
+    echo "[AOT] keep_going (multiple codegen units)"
+    if $MY_RUSTC example/keep_going.rs --crate-type bin -Ccodegen-units=4 -Cllvm-args=keep_going=1 --target "$TARGET_TRIPLE" 2> target/out/keep_going.stderr; then
+        echo "keep_going should have failed to compile"
+        exit 1
+    fi
+    # Codegen units codegened after the failing one must not be counted as failed.
+    grep -q "failed to codegen 1 of [2-9] codegen units: keep_going\.[^ ,]*-cgu\.[0-9]*$" target/out/keep_going.stderr
+
+    echo "[AOT] std_example (isolate_cgus)"
+    $MY_RUSTC example/std_example.rs --crate-name std_example_isolated --crate-type bin -Cllvm-args=isolate_cgus=1 -Ccodegen-units=4 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/std_example_isolated arg
+    $MY_RUSTC example/std_example.rs --crate-name std_example_isolated_parallel --crate-type bin -Cllvm-args=isolate_cgus=1 -Cllvm-args=max_parallel_cgus=3 -Ccodegen-units=8 --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/std_example_isolated_parallel arg
+    # Only the parent may write the metadata and dep-info and notify about them.
+    $MY_RUSTC example/std_example.rs --crate-name std_example_isolated_outputs --crate-type lib --emit=link,metadata,dep-info --error-format=json --json=artifacts -Cllvm-args=isolate_cgus=1 -Ccodegen-units=4 --target "$TARGET_TRIPLE" 2> target/out/std_example_isolated_outputs.stderr
+    [ "$(grep -c '"emit":"metadata"' target/out/std_example_isolated_outputs.stderr)" = 1 ]
+    [ -f target/out/std_example_isolated_outputs.d ]
+
+    $MY_RUSTC example/mod_bench.rs --crate-name max_parallel_cgus_ignored --crate-type bin -Cllvm-args=max_parallel_cgus=3 --target "$TARGET_TRIPLE" 2> target/out/max_parallel_cgus_ignored.stderr
+    grep -q "the \`max_parallel_cgus\` option is ignored without \`isolate_cgus\`" target/out/max_parallel_cgus_ignored.stderr
+
+    echo "[AOT] invalid_float_intrinsic (isolate_cgus)"
+    if $MY_RUSTC example/invalid_float_intrinsic.rs --crate-name invalid_float_intrinsic_isolated --crate-type bin -Cllvm-args=isolate_cgus=1 --target "$TARGET_TRIPLE" 2> target/out/invalid_float_intrinsic_isolated.stderr; then
+        echo "invalid_float_intrinsic should have failed to compile"
+        exit 1
+    fi
+    grep -q "codegen worker for codegen unit .* failed" target/out/invalid_float_intrinsic_isolated.stderr
+    grep -q "failed to codegen 1 of" target/out/invalid_float_intrinsic_isolated.stderr
+
+    echo "[AOT] intrinsic_override"
+    $MY_RUSTC example/intrinsic_override.rs --crate-type bin -Cllvm-args=override_intrinsic=ctpop:call:fake_ctpop --target "$TARGET_TRIPLE"
+    $RUN_WRAPPER ./target/out/intrinsic_override
+
     echo "[AOT] mod_bench"
     $MY_RUSTC example/mod_bench.rs --crate-type bin --target "$TARGET_TRIPLE"
     $RUN_WRAPPER ./target/out/mod_bench
@@ -94,6 +970,15 @@ function extended_sysroot_tests() {
     fi
     popd
 
+    if [[ "$HOST_TRIPLE" = "$TARGET_TRIPLE" ]]; then
+        echo "[BENCH RUN] jit_host_features"
+        hyperfine --runs "${RUN_RUNS:-10}" \
+        "$MY_RUSTC -Cllvm-args=mode=jit -Cprefer-dynamic example/jit_host_features.rs --cfg bench --cfg host_features --target $HOST_TRIPLE" \
+        "$MY_RUSTC -Cllvm-args=mode=jit -Cllvm-args=disable_jit_host_features=1 -Cprefer-dynamic example/jit_host_features.rs --cfg bench --target $HOST_TRIPLE"
+    else
+        echo "[BENCH RUN] jit_host_features (skipped)"
+    fi
+
     pushd build_sysroot/sysroot_src/library/core/tests
     echo "[TEST] libcore"
     rm -r ./target || true
@@ -23,6 +23,8 @@ fn clif_sig_from_fn_abi<'tcx>(
     triple: &target_lexicon::Triple,
     fn_abi: &FnAbi<'tcx, Ty<'tcx>>,
 ) -> Signature {
+    // `extern "system"`, `extern "fastcall"`, `extern "stdcall"` and the like are already turned
+    // into `Conv::C` by rustc on targets where they are equivalent to `extern "C"`.
     let call_conv = match fn_abi.conv {
         Conv::Rust | Conv::C => CallConv::triple_default(triple),
         Conv::X86_64SysV => CallConv::SystemV,
@@ -38,9 +40,10 @@ fn clif_sig_from_fn_abi<'tcx>(
         | Conv::X86VectorCall
         | Conv::AmdGpuKernel
         | Conv::AvrInterrupt
-        | Conv::AvrNonBlockingInterrupt => {
-            todo!("{:?}", fn_abi.conv)
-        }
+        | Conv::AvrNonBlockingInterrupt => tcx.sess.fatal(&format!(
+            "the {:?} calling convention is not yet supported by rustc_codegen_cranelift",
+            fn_abi.conv
+        )),
     };
     let inputs = fn_abi
         .args
@@ -76,9 +79,10 @@ pub(crate) fn get_function_sig<'tcx>(
 pub(crate) fn import_function<'tcx>(
     tcx: TyCtxt<'tcx>,
     module: &mut impl Module,
+    symbol_prefix: Option<&str>,
     inst: Instance<'tcx>,
 ) -> FuncId {
-    let name = tcx.symbol_name(inst).name.to_string();
+    let name = crate::linkage::symbol_name(tcx, symbol_prefix, inst);
     let sig = get_function_sig(tcx, module.isa().triple(), inst);
     module
         .declare_function(&name, Linkage::Import, &sig)
@@ -88,7 +92,12 @@ pub(crate) fn import_function<'tcx>(
 impl<'tcx, M: Module> FunctionCx<'_, 'tcx, M> {
     /// Instance must be monomorphized
     pub(crate) fn get_function_ref(&mut self, inst: Instance<'tcx>) -> FuncRef {
-        let func_id = import_function(self.tcx, &mut self.cx.module, inst);
+        let func_id = import_function(
+            self.tcx,
+            &mut self.cx.module,
+            self.cx.symbol_prefix.as_deref(),
+            inst,
+        );
         let func_ref = self
             .cx
             .module
@@ -423,19 +432,25 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         fx.cold_blocks.insert(current_block);
     }
 
-    // Unpack arguments tuple for closures
+    // Unpack arguments tuple for closures. The last argument of a "rust-call" function is a tuple
+    // whose fields are passed as separate arguments. It is preceded by `self` for closures and
+    // `Fn*` trait methods, but not for `extern "rust-call"` functions taking only the tuple.
     let args = if fn_sig.abi == Abi::RustCall {
-        assert_eq!(args.len(), 2, "rust-call abi requires two arguments");
-        let self_arg = codegen_operand(fx, &args[0]);
-        let pack_arg = codegen_operand(fx, &args[1]);
+        let (pack_arg, first_args) = args
+            .split_last()
+            .expect("rust-call abi requires a tupled argument");
+        let mut args = first_args
+            .iter()
+            .map(|arg| codegen_operand(fx, arg))
+            .collect::<Vec<_>>();
+        let pack_arg = codegen_operand(fx, pack_arg);
 
         let tupled_arguments = match pack_arg.layout().ty.kind() {
             ty::Tuple(ref tupled_arguments) => tupled_arguments,
             _ => bug!("argument to function with \"rust-call\" ABI is not a tuple"),
         };
 
-        let mut args = Vec::with_capacity(1 + tupled_arguments.len());
-        args.push(self_arg);
+        args.reserve(tupled_arguments.len());
         for i in 0..tupled_arguments.len() {
             args.push(pack_arg.value_field(fx, mir::Field::new(i)));
         }
@@ -540,6 +555,12 @@ pub(crate) fn codegen_terminator_call<'tcx>(
         },
     );
 
+    if let (Some(instance), None) = (instance, func_ref) {
+        if crate::optimize::inline::is_inline_candidate(fx.tcx, fx.instance, instance) {
+            fx.inline_calls.push((call_inst, instance));
+        }
+    }
+
     // FIXME find a cleaner way to support varargs
     if fn_sig.c_variadic {
         if fn_sig.abi != Abi::C {
@@ -6,20 +6,41 @@
 use rustc_ast::expand::allocator::{AllocatorKind, AllocatorTy, ALLOCATOR_METHODS};
 use rustc_span::symbol::sym;
 
+/// Returns the kind of allocator shim that [`codegen`] would create, if any
+pub(crate) fn shim_kind(tcx: TyCtxt<'_>) -> Option<AllocatorKind> {
+    let any_dynamic_crate = tcx.dependency_formats(LOCAL_CRATE).iter().any(|(_, list)| {
+        use rustc_middle::middle::dependency_format::Linkage;
+        list.iter().any(|&linkage| linkage == Linkage::Dynamic)
+    });
+    if any_dynamic_crate {
+        None
+    } else {
+        tcx.allocator_kind()
+    }
+}
+
+/// Returns whether to create the size optimized variant of the allocator shim, see
+/// [`crate::BackendConfig::small_allocator_shim`].
+pub(crate) fn use_small_shim(tcx: TyCtxt<'_>, config: &crate::BackendConfig) -> bool {
+    use rustc_session::config::OptLevel;
+    config
+        .small_allocator_shim
+        .unwrap_or_else(|| matches!(tcx.sess.opts.optimize, OptLevel::Size | OptLevel::SizeMin))
+}
+
 /// Returns whether an allocator shim was created
+///
+/// The `__rust_*` functions of the allocator shim forward to the global allocator, which must not
+/// unwind. When `small` is set no unwind info is emitted for them. `__rust_alloc_error_handler`
+/// always keeps its unwind info, as the alloc error handler may panic.
 pub(crate) fn codegen(
     tcx: TyCtxt<'_>,
     module: &mut impl Module,
     unwind_context: &mut UnwindContext<'_>,
+    small: bool,
 ) -> bool {
-    let any_dynamic_crate = tcx.dependency_formats(LOCAL_CRATE).iter().any(|(_, list)| {
-        use rustc_middle::middle::dependency_format::Linkage;
-        list.iter().any(|&linkage| linkage == Linkage::Dynamic)
-    });
-    if any_dynamic_crate {
-        false
-    } else if let Some(kind) = tcx.allocator_kind() {
-        codegen_inner(module, unwind_context, kind);
+    if let Some(kind) = shim_kind(tcx) {
+        codegen_inner(module, unwind_context, kind, small);
         true
     } else {
         false
@@ -30,6 +51,7 @@ fn codegen_inner(
     module: &mut impl Module,
     unwind_context: &mut UnwindContext<'_>,
     kind: AllocatorKind,
+    small: bool,
 ) {
     let usize_ty = module.target_config().pointer_type();
 
@@ -102,7 +124,9 @@ fn codegen_inner(
                 &mut cranelift_codegen::binemit::NullTrapSink {},
             )
             .unwrap();
-        unwind_context.add_function(func_id, &ctx, module.isa());
+        if !small {
+            unwind_context.add_function(func_id, &ctx, module.isa());
+        }
     }
 
     let sig = Signature {
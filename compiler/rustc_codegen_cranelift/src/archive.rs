@@ -95,13 +95,16 @@ fn add_file(&mut self, file: &Path) {
 
     fn add_native_library(&mut self, name: rustc_span::symbol::Symbol) {
         let location = find_library(name, &self.lib_search_paths, self.sess);
+        // Static native libraries of an rlib are bundled into it, so they get linked into every
+        // crate using the rlib without the library itself having to be available there.
+        let sess = self.sess;
         self.add_archive(location.clone(), |_| false)
-            .unwrap_or_else(|e| {
-                panic!(
+            .unwrap_or_else(|err| {
+                sess.fatal(&format!(
                     "failed to add native library {}: {}",
-                    location.to_string_lossy(),
-                    e
-                );
+                    location.display(),
+                    err
+                ));
             });
     }
 
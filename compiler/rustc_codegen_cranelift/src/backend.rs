@@ -5,7 +5,7 @@
 use rustc_data_structures::fx::FxHashMap;
 use rustc_session::Session;
 
-use cranelift_module::FuncId;
+use cranelift_module::{DataId, FuncId};
 
 use object::write::*;
 use object::{RelocationEncoding, RelocationKind, SectionKind, SymbolFlags};
@@ -45,7 +45,14 @@ fn add_rustc_section(&mut self, symbol_name: String, data: Vec<u8>, _is_like_osx
 pub(crate) trait WriteDebugInfo {
     type SectionId: Copy;
 
-    fn add_debug_section(&mut self, name: SectionId, data: Vec<u8>) -> Self::SectionId;
+    /// Adds a debug section. `compressed` indicates that `data` was compressed using
+    /// [`compress_elf_section`].
+    fn add_debug_section(
+        &mut self,
+        name: SectionId,
+        data: Vec<u8>,
+        compressed: bool,
+    ) -> Self::SectionId;
     fn add_debug_reloc(
         &mut self,
         section_map: &FxHashMap<SectionId, Self::SectionId>,
@@ -61,6 +68,7 @@ fn add_debug_section(
         &mut self,
         id: SectionId,
         data: Vec<u8>,
+        compressed: bool,
     ) -> (object::write::SectionId, object::write::SymbolId) {
         let name = if self.object.format() == object::BinaryFormat::MachO {
             id.name().replace('.', "__") // machO expects __debug_info instead of .debug_info
@@ -80,9 +88,17 @@ fn add_debug_section(
                 SectionKind::Debug
             },
         );
-        self.object
-            .section_mut(section_id)
-            .set_data(data, if id == SectionId::EhFrame { 8 } else { 1 });
+        let align = if id == SectionId::EhFrame || compressed {
+            8
+        } else {
+            1
+        };
+        self.object.section_mut(section_id).set_data(data, align);
+        if compressed {
+            self.object.section_mut(section_id).flags = SectionFlags::Elf {
+                sh_flags: object::elf::SHF_COMPRESSED.into(),
+            };
+        }
         let symbol_id = self.object.section_symbol(section_id);
         (section_id, symbol_id)
     }
@@ -161,16 +177,145 @@ fn add_constructor(&mut self, func_id: FuncId) {
     }
 }
 
-pub(crate) fn with_object(sess: &Session, name: &str, f: impl FnOnce(&mut Object)) -> Vec<u8> {
-    let triple = crate::build_isa(sess).triple().clone();
+pub(crate) trait RetainData {
+    /// Prevent the linker from garbage collecting the section containing the given data object.
+    /// Everything else in the section is retained too, so the data object should have a section
+    /// of its own.
+    fn retain_data(&mut self, data_id: DataId, writable: bool, tls: bool);
+}
 
-    let binary_format = match triple.binary_format {
-        target_lexicon::BinaryFormat::Elf => object::BinaryFormat::Elf,
-        target_lexicon::BinaryFormat::Coff => object::BinaryFormat::Coff,
-        target_lexicon::BinaryFormat::Macho => object::BinaryFormat::MachO,
-        binary_format => sess.fatal(&format!("binary format {} is unsupported", binary_format)),
-    };
-    let architecture = match triple.architecture {
+impl RetainData for ObjectProduct {
+    fn retain_data(&mut self, data_id: DataId, writable: bool, tls: bool) {
+        // Not yet defined by `object`. Supported since binutils 2.36 and lld 13.
+        const SHF_GNU_RETAIN: u64 = 1 << 21;
+
+        let symbol = self.data_symbol(data_id);
+        let section_id = match self.object.symbol(symbol).section {
+            SymbolSection::Section(section_id) => section_id,
+            _ => return,
+        };
+        let format = self.object.format();
+        let section = self.object.section_mut(section_id);
+        // Setting format specific flags overrides the flags `object` derives from the section
+        // kind, so they have to be recomputed here.
+        section.flags = match format {
+            object::BinaryFormat::Elf => {
+                let mut sh_flags = u64::from(object::elf::SHF_ALLOC) | SHF_GNU_RETAIN;
+                // Read-only data with relocations is placed in the writable `.data.rel.ro`.
+                if writable
+                    || section
+                        .name()
+                        .map_or(false, |name| name.starts_with(".data.rel.ro"))
+                {
+                    sh_flags |= u64::from(object::elf::SHF_WRITE);
+                }
+                if tls {
+                    sh_flags |= u64::from(object::elf::SHF_TLS);
+                }
+                SectionFlags::Elf { sh_flags }
+            }
+            object::BinaryFormat::MachO if !tls => {
+                // dyld only calls the function pointers in sections with the right type. ELF
+                // linkers recognize init and fini sections by their name instead.
+                let section_type = if section.is_bss() {
+                    object::macho::S_ZEROFILL
+                } else if section.name() == Some("__mod_init_func") {
+                    object::macho::S_MOD_INIT_FUNC_POINTERS
+                } else if section.name() == Some("__mod_term_func") {
+                    object::macho::S_MOD_TERM_FUNC_POINTERS
+                } else {
+                    object::macho::S_REGULAR
+                };
+                SectionFlags::MachO {
+                    flags: section_type | object::macho::S_ATTR_NO_DEAD_STRIP,
+                }
+            }
+            // link.exe only removes COMDAT sections when using /OPT:REF, which are never emitted.
+            _ => return,
+        };
+    }
+}
+
+pub(crate) trait MakeFunctionWeak {
+    /// Turn the definition of the given function into a weak definition, so that multiple object
+    /// files can define it. The linker picks an arbitrary definition, so they must be identical.
+    /// Functions which are not defined by Cranelift, like naked functions, are left alone.
+    fn make_function_weak(&mut self, func_id: FuncId);
+}
+
+impl MakeFunctionWeak for ObjectProduct {
+    fn make_function_weak(&mut self, func_id: FuncId) {
+        if let Some((symbol, true)) = self.functions[func_id] {
+            self.object.symbol_mut(symbol).weak = true;
+        }
+    }
+}
+
+pub(crate) trait FunctionSectionRenames {
+    /// Returns the new names of the sections of the defined functions whose symbol names have an
+    /// entry in `function_sections`, keyed by the current name of their section. Every function
+    /// must have a section of its own.
+    fn function_section_renames(
+        &self,
+        function_sections: &FxHashMap<String, String>,
+    ) -> FxHashMap<String, String>;
+}
+
+impl FunctionSectionRenames for ObjectProduct {
+    fn function_section_renames(
+        &self,
+        function_sections: &FxHashMap<String, String>,
+    ) -> FxHashMap<String, String> {
+        self.functions
+            .values()
+            .filter_map(|&symbol| {
+                let symbol = match symbol? {
+                    (symbol, true) => self.object.symbol(symbol),
+                    (_, false) => return None,
+                };
+                let new_name = function_sections.get(std::str::from_utf8(&symbol.name).ok()?)?;
+                let section_id = match symbol.section {
+                    SymbolSection::Section(section_id) => section_id,
+                    _ => return None,
+                };
+                let old_name = self.object.section(section_id).name()?;
+                Some((old_name.to_string(), new_name.clone()))
+            })
+            .collect()
+    }
+}
+
+pub(crate) trait StripLocalSymbolNames {
+    /// Remove the names of all functions and data objects which are local to the object file.
+    fn strip_local_symbol_names(&mut self);
+}
+
+impl StripLocalSymbolNames for ObjectProduct {
+    fn strip_local_symbol_names(&mut self) {
+        // Relocations between local functions and data objects refer to their symbols, so the
+        // symbol table entries have to be kept. Only the names, which make up most of the size of
+        // the symbol table, are removed. Debuginfo references functions relative to their section,
+        // so it is not affected.
+        let symbols = self
+            .functions
+            .values()
+            .chain(self.data_objects.values())
+            .filter_map(|symbol| symbol.map(|(symbol, _defined)| symbol))
+            .collect::<Vec<_>>();
+        for symbol in symbols {
+            let symbol = self.object.symbol_mut(symbol);
+            if symbol.scope == object::SymbolScope::Compilation {
+                symbol.name.clear();
+            }
+        }
+    }
+}
+
+pub(crate) fn object_architecture(
+    sess: &Session,
+    triple: &target_lexicon::Triple,
+) -> object::Architecture {
+    match triple.architecture {
         target_lexicon::Architecture::X86_32(_) => object::Architecture::I386,
         target_lexicon::Architecture::X86_64 => object::Architecture::X86_64,
         target_lexicon::Architecture::Arm(_) => object::Architecture::Arm,
@@ -179,7 +324,254 @@ pub(crate) fn with_object(sess: &Session, name: &str, f: impl FnOnce(&mut Object
             "target architecture {:?} is unsupported",
             architecture,
         )),
+    }
+}
+
+/// Adds a section with the given contents. The section isn't referenced by anything, so it is not
+/// loaded into memory on ELF targets to ensure that the linker doesn't garbage collect it. Tools
+/// like `objcopy --dump-section` can be used to read it.
+pub(crate) fn add_custom_section(object: &mut Object, section_name: &str, data: &[u8]) {
+    let segment = object.segment_name(StandardSegment::Data).to_vec();
+    let section_id = object.add_section(
+        segment,
+        section_name.as_bytes().to_vec(),
+        SectionKind::Other,
+    );
+    object.append_section_data(section_id, data, 1);
+}
+
+/// Adds a `.note.gnu.build-id` section containing `build_id` to an ELF object. Linkers keep the
+/// note when they don't generate a build-id themselves.
+pub(crate) fn add_build_id_note(sess: &Session, object: &mut Object, build_id: &[u8]) {
+    add_note(
+        sess,
+        object,
+        b".note.gnu.build-id",
+        b"GNU\0",
+        object::elf::NT_GNU_BUILD_ID,
+        build_id,
+    );
+}
+
+/// The note type of the crate hash note. Note types are specific to the owner of the note.
+const NT_RUSTC_CRATE_HASH: u32 = 1;
+
+/// Adds a `.note.rustc.crate-hash` section to an ELF object. The crate hash is stored big endian,
+/// so that the descriptor shown by `readelf -n` reads like the hex representation of the hash.
+pub(crate) fn add_crate_hash_note(sess: &Session, object: &mut Object, crate_hash: u64) {
+    add_note(
+        sess,
+        object,
+        b".note.rustc.crate-hash",
+        b"rustc\0",
+        NT_RUSTC_CRATE_HASH,
+        &crate_hash.to_be_bytes(),
+    );
+}
+
+/// Adds an allocated ELF note section containing a single note.
+fn add_note(
+    sess: &Session,
+    object: &mut Object,
+    section_name: &[u8],
+    owner: &[u8],
+    note_type: u32,
+    desc: &[u8],
+) {
+    let big_endian = crate::target_triple(sess).endianness() == Ok(target_lexicon::Endianness::Big);
+    let write_u32 = |note: &mut Vec<u8>, val: u32| {
+        if big_endian {
+            note.extend_from_slice(&val.to_be_bytes());
+        } else {
+            note.extend_from_slice(&val.to_le_bytes());
+        }
+    };
+
+    let mut note = Vec::new();
+    write_u32(&mut note, owner.len().try_into().unwrap()); // n_namesz
+    write_u32(&mut note, desc.len().try_into().unwrap()); // n_descsz
+    write_u32(&mut note, note_type); // n_type
+    note.extend_from_slice(owner);
+    // The name and the descriptor are both padded to a multiple of 4 bytes.
+    note.resize((note.len() + 3) & !3, 0);
+    note.extend_from_slice(desc);
+    note.resize((note.len() + 3) & !3, 0);
+
+    let section_id = object.add_section(Vec::new(), section_name.to_vec(), SectionKind::Note);
+    object.section_mut(section_id).flags = SectionFlags::Elf {
+        sh_flags: object::elf::SHF_ALLOC.into(),
+    };
+    object.append_section_data(section_id, &note, 4);
+}
+
+/// Compresses the contents of an ELF section using zlib and prepends the compression header, such
+/// that the section can be marked with `SHF_COMPRESSED`. Relocations still apply to the offsets in
+/// the uncompressed contents, so this must only be used for targets storing the addend in the
+/// relocation rather than in the section contents.
+pub(crate) fn compress_elf_section(data: &[u8], big_endian: bool, is_64: bool) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    let mut compressed = Vec::new();
+    let mut write_uint = |val: u64, size: usize| {
+        let bytes = if big_endian {
+            val.to_be_bytes()[8 - size..].to_vec()
+        } else {
+            val.to_le_bytes()[..size].to_vec()
+        };
+        compressed.extend_from_slice(&bytes);
+    };
+    // `Elf64_Chdr` has a reserved field after `ch_type`, `Elf32_Chdr` doesn't.
+    let word_size = if is_64 { 8 } else { 4 };
+    write_uint(object::elf::ELFCOMPRESS_ZLIB.into(), 4); // ch_type
+    if is_64 {
+        write_uint(0, 4); // ch_reserved
+    }
+    write_uint(data.len() as u64, word_size); // ch_size
+    write_uint(1, word_size); // ch_addralign
+
+    let mut encoder = ZlibEncoder::new(compressed, flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Sets the `EI_OSABI` byte of the ELF object file `obj`, which `object` always writes as
+/// `ELFOSABI_NONE`.
+pub(crate) fn set_elf_os_abi(obj: &mut [u8], os_abi: u8) {
+    assert_eq!(&obj[..4], &object::elf::ELFMAG);
+    obj[object::elf::EI_OSABI] = os_abi;
+}
+
+/// Renames sections of the ELF object file `obj` for which `rename` returns a new name. The section
+/// header string table with the new names is written after the existing contents of the file,
+/// followed by a copy of the section headers.
+///
+/// `object` doesn't allow changing the name of a section once it has been added, which for
+/// functions happens when Cranelift defines them, so this has to be done on the written file.
+pub(crate) fn rename_elf_sections(
+    obj: Vec<u8>,
+    mut rename: impl FnMut(&str) -> Option<String>,
+) -> Vec<u8> {
+    let is_64 = obj[object::elf::EI_CLASS] == object::elf::ELFCLASS64;
+    let big_endian = obj[object::elf::EI_DATA] == object::elf::ELFDATA2MSB;
+    let read = |obj: &[u8], offset: usize, size: usize| -> usize {
+        let bytes = &obj[offset..offset + size];
+        let mut val = 0;
+        for i in 0..size {
+            let byte = if big_endian {
+                bytes[i]
+            } else {
+                bytes[size - 1 - i]
+            };
+            val = (val << 8) | usize::from(byte);
+        }
+        val
+    };
+    let write = |obj: &mut [u8], offset: usize, size: usize, val: usize| {
+        for i in 0..size {
+            let byte = (val >> (8 * i)) as u8;
+            if big_endian {
+                obj[offset + size - 1 - i] = byte;
+            } else {
+                obj[offset + i] = byte;
+            }
+        }
+    };
+
+    // Offsets of the used fields of the file header and the section headers.
+    let (e_shoff, e_shentsize, e_shnum, e_shstrndx) = if is_64 {
+        (0x28, 0x3a, 0x3c, 0x3e)
+    } else {
+        (0x20, 0x2e, 0x30, 0x32)
+    };
+    let (sh_offset, sh_size, word_size) = if is_64 {
+        (0x18, 0x20, 8)
+    } else {
+        (0x10, 0x14, 4)
+    };
+
+    let shoff = read(&obj, e_shoff, word_size);
+    let shentsize = read(&obj, e_shentsize, 2);
+    let shnum = read(&obj, e_shnum, 2);
+    let shstrndx = read(&obj, e_shstrndx, 2);
+    assert!(shnum != 0 && shstrndx != usize::from(object::elf::SHN_XINDEX));
+
+    let shstrtab_header = shoff + shstrndx * shentsize;
+    let shstrtab_offset = read(&obj, shstrtab_header + sh_offset, word_size);
+    let shstrtab_size = read(&obj, shstrtab_header + sh_size, word_size);
+    let shstrtab = &obj[shstrtab_offset..shstrtab_offset + shstrtab_size];
+
+    let mut new_shstrtab = shstrtab.to_vec();
+    let mut renamed = Vec::new();
+    for index in 0..shnum {
+        let name_offset = read(&obj, shoff + index * shentsize, 4);
+        let name = &shstrtab[name_offset..];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+        if let Some(new_name) = std::str::from_utf8(name).ok().and_then(&mut rename) {
+            renamed.push((index, new_shstrtab.len()));
+            new_shstrtab.extend_from_slice(new_name.as_bytes());
+            new_shstrtab.push(0);
+        }
+    }
+    if renamed.is_empty() {
+        return obj;
+    }
+
+    let section_headers = obj[shoff..shoff + shnum * shentsize].to_vec();
+    let mut obj = obj;
+    // `object` writes the section headers at the end of the file, so they can be overwritten.
+    if shoff + section_headers.len() == obj.len() {
+        obj.truncate(shoff);
+    }
+    let new_shstrtab_offset = obj.len();
+    obj.extend_from_slice(&new_shstrtab);
+    obj.resize((obj.len() + word_size - 1) & !(word_size - 1), 0);
+    let new_shoff = obj.len();
+    obj.extend_from_slice(&section_headers);
+
+    write(&mut obj, e_shoff, word_size, new_shoff);
+    let shstrtab_header = new_shoff + shstrndx * shentsize;
+    write(
+        &mut obj,
+        shstrtab_header + sh_offset,
+        word_size,
+        new_shstrtab_offset,
+    );
+    write(
+        &mut obj,
+        shstrtab_header + sh_size,
+        word_size,
+        new_shstrtab.len(),
+    );
+    for (index, name_offset) in renamed {
+        write(&mut obj, new_shoff + index * shentsize, 4, name_offset);
+    }
+    obj
+}
+
+/// Adds an empty `.note.GNU-stack` section to ELF objects. Without it linkers assume that the
+/// object needs an executable stack and mark the stack of the whole executable as executable.
+pub(crate) fn add_gnu_stack_note(object: &mut Object) {
+    if object.format() != object::BinaryFormat::Elf {
+        return;
+    }
+
+    let section_id =
+        object.add_section(Vec::new(), b".note.GNU-stack".to_vec(), SectionKind::Other);
+    // Not `SHF_EXECINSTR`, so the stack is not executable.
+    object.section_mut(section_id).flags = SectionFlags::Elf { sh_flags: 0 };
+}
+
+pub(crate) fn with_object(sess: &Session, name: &str, f: impl FnOnce(&mut Object)) -> Vec<u8> {
+    let triple = crate::build_isa(sess, false, false).triple().clone();
+
+    let binary_format = match triple.binary_format {
+        target_lexicon::BinaryFormat::Elf => object::BinaryFormat::Elf,
+        target_lexicon::BinaryFormat::Coff => object::BinaryFormat::Coff,
+        target_lexicon::BinaryFormat::Macho => object::BinaryFormat::MachO,
+        binary_format => sess.fatal(&format!("binary format {} is unsupported", binary_format)),
     };
+    let architecture = object_architecture(sess, &triple);
     let endian = match triple.endianness().unwrap() {
         target_lexicon::Endianness::Little => object::Endianness::Little,
         target_lexicon::Endianness::Big => object::Endianness::Big,
@@ -188,19 +580,27 @@ pub(crate) fn with_object(sess: &Session, name: &str, f: impl FnOnce(&mut Object
     let mut metadata_object = object::write::Object::new(binary_format, architecture, endian);
     metadata_object.add_file_symbol(name.as_bytes().to_vec());
     f(&mut metadata_object);
+    add_gnu_stack_note(&mut metadata_object);
     metadata_object.write().unwrap()
 }
 
-pub(crate) fn make_module(sess: &Session, name: String) -> ObjectModule {
+pub(crate) fn make_module(
+    sess: &Session,
+    name: String,
+    per_function_section: bool,
+    enable_verifier: bool,
+) -> ObjectModule {
     let mut builder = ObjectBuilder::new(
-        crate::build_isa(sess),
+        crate::build_isa(sess, false, enable_verifier),
         name + ".o",
         cranelift_module::default_libcall_names(),
     )
     .unwrap();
     // Unlike cg_llvm, cg_clif defaults to disabling -Zfunction-sections. For cg_llvm binary size
     // is important, while cg_clif cares more about compilation times. Enabling -Zfunction-sections
-    // can easily double the amount of time necessary to perform linking.
-    builder.per_function_section(sess.opts.debugging_opts.function_sections.unwrap_or(false));
+    // can easily double the amount of time necessary to perform linking. In both cases references
+    // from vtables and function pointers are relocations against the function symbol, which keep
+    // the section of the function alive with `--gc-sections`.
+    builder.per_function_section(per_function_section);
     ObjectModule::new(builder)
 }
@@ -1,6 +1,7 @@
 //! Codegen of a single function
 
 use rustc_index::vec::IndexVec;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::ty::adjustment::PointerCast;
 use rustc_middle::ty::layout::FnAbiExt;
 use rustc_target::abi::call::FnAbi;
@@ -18,83 +19,62 @@ pub(crate) fn codegen_fn<'tcx>(
         crate::PrintOnPanic(|| format!("{:?} {}", instance, tcx.symbol_name(instance).name));
     debug_assert!(!instance.substs.needs_infer());
 
-    let mir = tcx.instance_mir(instance.def);
+    if tcx
+        .codegen_fn_attrs(instance.def_id())
+        .flags
+        .contains(CodegenFnAttrFlags::NAKED)
+    {
+        crate::inline_asm::codegen_naked_fn(cx, instance, linkage);
+        return;
+    }
+
+    let mir = instance_mir(cx, instance);
 
     // Declare function
-    let name = tcx.symbol_name(instance).name.to_string();
+    let name = crate::linkage::symbol_name(tcx, cx.symbol_prefix.as_deref(), instance);
     let sig = get_function_sig(tcx, cx.module.isa().triple(), instance);
     let func_id = cx.module.declare_function(&name, linkage, &sig).unwrap();
 
+    if cx
+        .test_skip_definition
+        .as_ref()
+        .map_or(false, |filter| name.contains(&**filter))
+    {
+        return;
+    }
+
+    let coverage_counters = crate::coverage::declare_counters(cx, &name, mir);
+
     cx.cached_context.clear();
 
-    // Make the FunctionBuilder
-    let mut func_ctx = FunctionBuilderContext::new();
     let mut func = std::mem::replace(&mut cx.cached_context.func, Function::new());
     func.name = ExternalName::user(0, func_id.as_u32());
     func.signature = sig;
     func.collect_debug_info();
 
-    let mut bcx = FunctionBuilder::new(&mut func, &mut func_ctx);
-
-    // Predefine blocks
-    let start_block = bcx.create_block();
-    let block_map: IndexVec<BasicBlock, Block> = (0..mir.basic_blocks().len())
-        .map(|_| bcx.create_block())
-        .collect();
-
-    // Make FunctionCx
-    let pointer_type = cx.module.target_config().pointer_type();
-    let clif_comments = crate::pretty_clif::CommentWriter::new(tcx, instance);
-
-    let mut fx = FunctionCx {
-        cx,
-        tcx,
-        pointer_type,
-
-        instance,
-        mir,
-        fn_abi: Some(FnAbi::of_instance(&RevealAllLayoutCx(tcx), instance, &[])),
-
-        bcx,
-        block_map,
-        local_map: IndexVec::with_capacity(mir.local_decls.len()),
-        caller_location: None, // set by `codegen_fn_prelude`
-        cold_blocks: EntitySet::new(),
-
-        clif_comments,
-        source_info_set: indexmap::IndexSet::new(),
-        next_ssa_var: 0,
-
-        inline_asm_index: 0,
-    };
-
-    let arg_uninhabited = fx.mir.args_iter().any(|arg| {
-        fx.layout_of(fx.monomorphize(&fx.mir.local_decls[arg].ty))
-            .abi
-            .is_uninhabited()
-    });
-
-    if arg_uninhabited {
-        fx.bcx
-            .append_block_params_for_function_params(fx.block_map[START_BLOCK]);
-        fx.bcx.switch_to_block(fx.block_map[START_BLOCK]);
-        crate::trap::trap_unreachable(&mut fx, "function has uninhabited argument");
-    } else {
-        tcx.sess.time("codegen clif ir", || {
-            tcx.sess.time("codegen prelude", || {
-                crate::abi::codegen_fn_prelude(&mut fx, start_block)
-            });
-            codegen_fn_content(&mut fx);
+    let FnBody {
+        mut clif_comments,
+        source_info_set,
+        local_map,
+        cold_blocks,
+        inline_calls,
+    } = codegen_fn_body(cx, instance, mir, &mut func, coverage_counters);
+
+    // Inline calls to `#[inline(always)]` functions recorded while codegening the function body
+    if !inline_calls.is_empty() {
+        crate::driver::profile(tcx, "inline calls", || {
+            for (call_inst, callee) in inline_calls {
+                if !cx.inline_cache.contains_key(&callee) {
+                    let callee_func = codegen_inline_callee(cx, callee);
+                    cx.inline_cache.insert(callee, callee_func);
+                }
+                if let Some(callee_func) = &cx.inline_cache[&callee] {
+                    crate::optimize::inline::inline_call(&mut func, call_inst, callee_func);
+                }
+            }
         });
     }
 
-    // Recover all necessary data from fx, before accessing func will prevent future access to it.
-    let instance = fx.instance;
-    let mut clif_comments = fx.clif_comments;
-    let source_info_set = fx.source_info_set;
-    let local_map = fx.local_map;
-    let cold_blocks = fx.cold_blocks;
-
     // Store function in context
     let context = &mut cx.cached_context;
     context.func = func;
@@ -102,10 +82,10 @@ pub(crate) fn codegen_fn<'tcx>(
     crate::pretty_clif::write_clif_file(tcx, "unopt", None, instance, &context, &clif_comments);
 
     // Verify function
-    verify_func(tcx, &clif_comments, &context.func);
+    verify_func(tcx, &name, None, &clif_comments, &context.func);
 
     // Perform rust specific optimizations
-    tcx.sess.time("optimize clif ir", || {
+    crate::driver::profile(tcx, "optimize clif ir", || {
         crate::optimize::optimize_function(
             tcx,
             instance,
@@ -125,19 +105,91 @@ pub(crate) fn codegen_fn<'tcx>(
     // invalidate it when it would change.
     context.domtree.clear();
 
-    context.want_disasm = crate::pretty_clif::should_write_ir(tcx);
+    if cx
+        .test_break_ir
+        .as_ref()
+        .map_or(false, |filter| name.contains(&**filter))
+    {
+        break_func(&mut context.func);
+    }
+
+    if cx.verify_ir {
+        verify_func(
+            tcx,
+            &name,
+            Some(cx.module.isa()),
+            &clif_comments,
+            &context.func,
+        );
+    }
+
+    context.want_disasm = crate::pretty_clif::should_write_ir(tcx) || cx.asm_output.is_some();
+
+    // Functions can only be cached when nothing but the machine code has to be emitted for them.
+    let cache_key = match &cx.function_cache {
+        Some(_)
+            if !context.want_disasm
+                && cx.debug_context.is_none()
+                && !cx.unwind_context.emits_unwind_info() =>
+        {
+            crate::function_cache::FunctionCache::cache_key(&context.func)
+        }
+        _ => None,
+    };
+
+    // Reuse the machine code of the previous session if the function didn't change
+    if let (Some(function_cache), Some(hash)) = (&mut cx.function_cache, cache_key) {
+        if let Some(code) = function_cache.lookup(&name, hash) {
+            let compiled_function = cx.module.define_function_bytes(func_id, code).unwrap();
+            cx.undefined_functions.remove(&func_id);
+            cx.stats.functions += 1;
+            cx.stats.cached_functions += 1;
+            cx.stats.code_bytes += u64::from(compiled_function.size);
+            if let Some(code_sizes) = &mut cx.code_sizes {
+                code_sizes.insert(func_id, compiled_function.size);
+            }
+            context.clear();
+            return;
+        }
+    }
+
+    // Only redefine functions of a kept JIT module when their IR changed since the last session
+    if let Some((function_hashes, prepare_redefine)) = &mut cx.jit_redefine {
+        let hash = crate::function_cache::ir_hash(&context.func);
+        match function_hashes.insert(name.clone(), hash) {
+            Some(prev_hash) if prev_hash == hash => {
+                cx.undefined_functions.remove(&func_id);
+                context.clear();
+                return;
+            }
+            Some(_) => prepare_redefine(&mut cx.module, func_id),
+            None => {}
+        }
+    }
 
     // Define function
     let module = &mut cx.module;
-    tcx.sess.time("define function", || {
-        module
-            .define_function(
-                func_id,
-                context,
-                &mut cranelift_codegen::binemit::NullTrapSink {},
-            )
-            .unwrap()
+    let function_cache = &mut cx.function_cache;
+    let compiled_function = crate::driver::profile(tcx, "define function", || {
+        if let (Some(function_cache), Some(hash)) = (function_cache, cache_key) {
+            return function_cache.compile_and_insert(module, func_id, context, &name, hash);
+        }
+        module.define_function(
+            func_id,
+            context,
+            &mut cranelift_codegen::binemit::NullTrapSink {},
+        )
     });
+    let compiled_function = match compiled_function {
+        Ok(compiled_function) => compiled_function,
+        Err(err) => report_compile_error(tcx, instance, &name, err),
+    };
+    cx.undefined_functions.remove(&func_id);
+    cx.stats.functions += 1;
+    cx.stats.code_bytes += u64::from(compiled_function.size);
+    if let Some(code_sizes) = &mut cx.code_sizes {
+        code_sizes.insert(func_id, compiled_function.size);
+    }
 
     // Write optimized function to file for debugging
     crate::pretty_clif::write_clif_file(
@@ -149,13 +201,30 @@ pub(crate) fn codegen_fn<'tcx>(
         &clif_comments,
     );
 
+    if let Some(ir_output) = &mut cx.ir_output {
+        ir_output.push_str(&format!(
+            "; {}\n{}\n",
+            name,
+            crate::pretty_clif::clif_with_comments(
+                tcx,
+                Some(cx.module.isa()),
+                &context,
+                &clif_comments,
+            )
+        ));
+    }
+
     if let Some(mach_compile_result) = &context.mach_compile_result {
         if let Some(disasm) = &mach_compile_result.disasm {
             crate::pretty_clif::write_ir_file(
                 tcx,
                 &format!("{}.vcode", tcx.symbol_name(instance).name),
                 |file| file.write_all(disasm.as_bytes()),
-            )
+            );
+
+            if let Some(asm_output) = &mut cx.asm_output {
+                asm_output.push_str(&format!("{}:\n{}\n", name, disasm));
+            }
         }
     }
 
@@ -163,7 +232,7 @@ pub(crate) fn codegen_fn<'tcx>(
     let isa = cx.module.isa();
     let debug_context = &mut cx.debug_context;
     let unwind_context = &mut cx.unwind_context;
-    tcx.sess.time("generate debug info", || {
+    crate::driver::profile(tcx, "generate debug info", || {
         if let Some(debug_context) = debug_context {
             debug_context.define_function(
                 instance,
@@ -182,25 +251,209 @@ pub(crate) fn codegen_fn<'tcx>(
     context.clear();
 }
 
+/// Everything besides the Cranelift IR that is produced while codegening the body of a function.
+struct FnBody<'tcx> {
+    clif_comments: crate::pretty_clif::CommentWriter,
+    source_info_set: indexmap::IndexSet<SourceInfo>,
+    local_map: IndexVec<Local, CPlace<'tcx>>,
+    cold_blocks: EntitySet<Block>,
+    inline_calls: Vec<(Inst, Instance<'tcx>)>,
+}
+
+/// Codegens the body of `instance` into `func`, which must already have the signature of
+/// `instance`.
+fn codegen_fn_body<'tcx>(
+    cx: &mut crate::CodegenCx<'tcx, impl Module>,
+    instance: Instance<'tcx>,
+    mir: &'tcx Body<'tcx>,
+    func: &mut Function,
+    coverage_counters: Option<DataId>,
+) -> FnBody<'tcx> {
+    let tcx = cx.tcx;
+
+    // Make the FunctionBuilder
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(func, &mut func_ctx);
+
+    // Predefine blocks
+    let start_block = bcx.create_block();
+    let block_map: IndexVec<BasicBlock, Block> = (0..mir.basic_blocks().len())
+        .map(|_| bcx.create_block())
+        .collect();
+
+    // Make FunctionCx
+    let pointer_type = cx.module.target_config().pointer_type();
+    let clif_comments = crate::pretty_clif::CommentWriter::new(tcx, instance);
+
+    let mut fx = FunctionCx {
+        cx,
+        tcx,
+        pointer_type,
+
+        instance,
+        mir,
+        fn_abi: Some(FnAbi::of_instance(&RevealAllLayoutCx(tcx), instance, &[])),
+
+        bcx,
+        block_map,
+        local_map: IndexVec::with_capacity(mir.local_decls.len()),
+        caller_location: None, // set by `codegen_fn_prelude`
+        cold_blocks: EntitySet::new(),
+
+        clif_comments,
+        source_info_set: indexmap::IndexSet::new(),
+        current_span: mir.span,
+        next_ssa_var: 0,
+
+        inline_asm_index: 0,
+
+        coverage_counters,
+        inline_calls: Vec::new(),
+    };
+
+    let arg_uninhabited = fx.mir.args_iter().any(|arg| {
+        fx.layout_of(fx.monomorphize(&fx.mir.local_decls[arg].ty))
+            .abi
+            .is_uninhabited()
+    });
+
+    if arg_uninhabited {
+        fx.bcx
+            .append_block_params_for_function_params(fx.block_map[START_BLOCK]);
+        fx.bcx.switch_to_block(fx.block_map[START_BLOCK]);
+        crate::trap::trap_unreachable(&mut fx, "function has uninhabited argument");
+    } else {
+        crate::driver::profile(tcx, "codegen clif ir", || {
+            crate::driver::profile(tcx, "codegen prelude", || {
+                crate::abi::codegen_fn_prelude(&mut fx, start_block)
+            });
+            codegen_fn_content(&mut fx);
+        });
+    }
+
+    FnBody {
+        clif_comments: fx.clif_comments,
+        source_info_set: fx.source_info_set,
+        local_map: fx.local_map,
+        cold_blocks: fx.cold_blocks,
+        inline_calls: fx.inline_calls,
+    }
+}
+
+/// Returns the MIR to codegen for `instance`, after applying [`crate::MirTransform`] if any.
+fn instance_mir<'tcx>(
+    cx: &crate::CodegenCx<'tcx, impl Module>,
+    instance: Instance<'tcx>,
+) -> &'tcx Body<'tcx> {
+    let tcx = cx.tcx;
+    let mir = tcx.instance_mir(instance.def);
+    match &cx.mir_transform {
+        Some(transform) => match (transform.0)(tcx, instance, mir) {
+            Some(body) => tcx.arena.alloc(body),
+            None => mir,
+        },
+        None => mir,
+    }
+}
+
+/// Codegens the body of `callee` on its own to inline it into a caller. Returns `None` when the
+/// callee can't be inlined, see [`crate::optimize::inline`].
+fn codegen_inline_callee<'tcx>(
+    cx: &mut crate::CodegenCx<'tcx, impl Module>,
+    callee: Instance<'tcx>,
+) -> Option<Function> {
+    let tcx = cx.tcx;
+    let mir = instance_mir(cx, callee);
+
+    // The callee is codegened using a scratch module, so that the functions, data objects and
+    // constants it references are never added to the codegen unit of the caller. The callee isn't
+    // inlined when it references any of them anyway.
+    let mut scratch_cx = crate::CodegenCx::new(
+        tcx,
+        crate::backend::make_module(tcx.sess, "inline_scratch".to_string(), false, false),
+        false,
+        true,
+    );
+    scratch_cx.intrinsic_overrides = cx.intrinsic_overrides.clone();
+    scratch_cx.symbol_prefix = cx.symbol_prefix.clone();
+
+    let mut func = Function::new();
+    func.signature = get_function_sig(tcx, cx.module.isa().triple(), callee);
+    // Only functions without calls are inlined, so the calls recorded for the callee don't matter.
+    let _ = codegen_fn_body(&mut scratch_cx, callee, mir, &mut func, None);
+
+    if crate::optimize::inline::is_inlinable(&func) {
+        Some(func)
+    } else {
+        None
+    }
+}
+
+/// Reports a failure of Cranelift to compile a function together with the MIR of the function, as
+/// the error itself rarely contains enough information to reproduce it.
+fn report_compile_error<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    instance: Instance<'tcx>,
+    symbol_name: &str,
+    err: cranelift_module::ModuleError,
+) -> ! {
+    let mut diag = tcx.sess.struct_fatal(&format!(
+        "failed to compile `{}` using Cranelift: {}",
+        symbol_name, err
+    ));
+    diag.note(&format!("while codegenning {:?}", instance));
+    // Shims are generated by rustc and can't be printed by `write_mir_pretty`.
+    if let InstanceDef::Item(def) = instance.def {
+        let mut mir = Vec::new();
+        rustc_mir::util::write_mir_pretty(tcx, Some(def.did), &mut mir).unwrap();
+        diag.note(&format!(
+            "MIR of the function:\n{}",
+            String::from_utf8_lossy(&mir)
+        ));
+    }
+    diag.emit();
+    rustc_errors::FatalError.raise()
+}
+
+/// Appends an instruction after the terminator of the last block, which the verifier rejects. Used
+/// to test the `verify_ir` option.
+fn break_func(func: &mut Function) {
+    use cranelift_codegen::cursor::{Cursor, FuncCursor};
+
+    let last_block = func.layout.last_block().unwrap();
+    let mut cursor = FuncCursor::new(func).at_bottom(last_block);
+    cursor.ins().iconst(types::I32, 0);
+}
+
+/// Runs the Cranelift verifier on `func`. When `isa` is given the function is verified against the
+/// flags of the target isa, which also checks the encodings of already legalized instructions.
 pub(crate) fn verify_func(
     tcx: TyCtxt<'_>,
+    symbol_name: &str,
+    isa: Option<&dyn isa::TargetIsa>,
     writer: &crate::pretty_clif::CommentWriter,
     func: &Function,
 ) {
-    tcx.sess.time("verify clif ir", || {
+    crate::driver::profile(tcx, "verify clif ir", || {
         let flags = cranelift_codegen::settings::Flags::new(cranelift_codegen::settings::builder());
-        match cranelift_codegen::verify_function(&func, &flags) {
+        let res = match isa {
+            Some(isa) => cranelift_codegen::verify_function(&func, isa),
+            None => cranelift_codegen::verify_function(&func, &flags),
+        };
+        match res {
             Ok(_) => {}
             Err(err) => {
                 tcx.sess.err(&format!("{:?}", err));
                 let pretty_error = cranelift_codegen::print_errors::pretty_verifier_error(
                     &func,
-                    None,
+                    isa,
                     Some(Box::new(writer)),
                     err,
                 );
-                tcx.sess
-                    .fatal(&format!("cranelift verify error:\n{}", pretty_error));
+                tcx.sess.fatal(&format!(
+                    "cranelift verify error in `{}`:\n{}",
+                    symbol_name, pretty_error
+                ));
             }
         }
     });
@@ -223,6 +476,7 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, impl Module>) {
         }
 
         fx.bcx.ins().nop();
+        crate::coverage::increment_counter(fx, bb);
         for stmt in &bb_data.statements {
             fx.set_debug_loc(stmt.source_info);
             codegen_stmt(fx, block, stmt);
@@ -242,6 +496,28 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, impl Module>) {
 
         fx.set_debug_loc(bb_data.terminator().source_info);
 
+        if let Some(max_function_size) = fx.cx.max_function_size {
+            let function_size = fx.bcx.func.dfg.num_insts();
+            if function_size > max_function_size as usize {
+                fx.tcx.sess.span_fatal(
+                    fx.mir.span,
+                    &format!(
+                        "function `{}` exceeds the maximum function size of {} instructions \
+                         with {} instructions after codegening {} of {} basic blocks",
+                        crate::linkage::symbol_name(
+                            fx.tcx,
+                            fx.cx.symbol_prefix.as_deref(),
+                            fx.instance,
+                        ),
+                        max_function_size,
+                        function_size,
+                        bb.index() + 1,
+                        fx.mir.basic_blocks().len(),
+                    ),
+                );
+            }
+        }
+
         match &bb_data.terminator().kind {
             TerminatorKind::Goto { target } => {
                 if let TerminatorKind::Return = fx.mir[*target].terminator().kind {
@@ -274,6 +550,11 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, impl Module>) {
                 target,
                 cleanup: _,
             } => {
+                // MIR only contains overflow checks when `-Coverflow-checks` is enabled for the
+                // crate the MIR comes from, or the function is marked with
+                // `#[rustc_inherit_overflow_checks]`, like the arithmetic operator impls of libcore.
+                // The latter have to follow `-Coverflow-checks` of the crate they are codegened
+                // in. Negation has no checked operation, so the assertion is skipped instead.
                 if !fx.tcx.sess.overflow_checks() {
                     if let mir::AssertKind::OverflowNeg(_) = *msg {
                         let target = fx.get_block(*target);
@@ -381,7 +662,7 @@ fn codegen_fn_content(fx: &mut FunctionCx<'_, '_, impl Module>) {
                 cleanup: _,
                 from_hir_call: _,
             } => {
-                fx.tcx.sess.time("codegen call", || {
+                crate::driver::profile(fx.tcx, "codegen call", || {
                     crate::abi::codegen_terminator_call(
                         fx,
                         *fn_span,
@@ -505,6 +786,9 @@ fn codegen_stmt<'tcx>(
                     let lhs = codegen_operand(fx, lhs);
                     let rhs = codegen_operand(fx, rhs);
 
+                    // Overflow checks inherited using `#[rustc_inherit_overflow_checks]` by a
+                    // crate without `-Coverflow-checks`. The assertion using the overflow flag
+                    // is kept, so the flag is set to false to never panic.
                     let res = if !fx.tcx.sess.overflow_checks() {
                         let val =
                             crate::num::codegen_int_binop(fx, bin_op, lhs, rhs).load_scalar(fx);
@@ -918,7 +1202,10 @@ fn is_fat_ptr<'tcx>(
                     .span_fatal(stmt.source_info.span, "Inline assembly is not supported"),
             }
         }
-        StatementKind::Coverage { .. } => fx.tcx.sess.fatal("-Zcoverage is unimplemented"),
+        StatementKind::Coverage { .. } => fx
+            .tcx
+            .sess
+            .span_fatal(stmt.source_info.span, "-Zcoverage is unimplemented"),
     }
 }
 
@@ -1056,7 +1343,7 @@ pub(crate) fn codegen_panic_inner<'tcx>(
         .unwrap_or_else(|s| fx.tcx.sess.span_fatal(span, &s));
 
     let instance = Instance::mono(fx.tcx, def_id).polymorphize(fx.tcx);
-    let symbol_name = fx.tcx.symbol_name(instance).name;
+    let symbol_name = crate::linkage::symbol_name(fx.tcx, fx.cx.symbol_prefix.as_deref(), instance);
 
     fx.lib_call(
         &*symbol_name,
@@ -45,7 +45,7 @@ fn main() {
     let mut callbacks = CraneliftPassesCallbacks::default();
     rustc_driver::install_ice_hook();
     let exit_code = rustc_driver::catch_with_exit_code(|| {
-        let args = std::env::args_os()
+        let mut args = std::env::args_os()
             .enumerate()
             .map(|(i, arg)| {
                 arg.into_string().unwrap_or_else(|arg| {
@@ -56,6 +56,16 @@ fn main() {
                 })
             })
             .collect::<Vec<_>>();
+        // The capabilities of cg_clif are printed by the backend for `target-features`.
+        let mut after_print = false;
+        for arg in &mut args {
+            if arg == "--print=cg-clif-features" {
+                *arg = "--print=target-features".to_string();
+            } else if after_print && arg == "cg-clif-features" {
+                *arg = "target-features".to_string();
+            }
+            after_print = arg == "--print";
+        }
         let mut run_compiler = rustc_driver::RunCompiler::new(&args, &mut callbacks);
         run_compiler.set_make_codegen_backend(Some(Box::new(move |_| {
             Box::new(rustc_codegen_cranelift::CraneliftCodegenBackend { config: None })
@@ -0,0 +1,185 @@
+//! Test driver running multiple JIT sessions in a single process, all sharing the same JIT module
+//! using [`BackendConfig::jit_keep_module`]. Used by `scripts/tests.sh`.
+//!
+//! Usage: `cg_clif_jit_sessions [--no-keep-module] [--after-analysis] [--code-size] <rustc args>
+//! [--next <rustc args>]...`
+//!
+//! Every session has to compile an executable defining
+//! `#[no_mangle] extern "C" fn jit_session_entry() -> extern "C" fn() -> u32`. Once all sessions
+//! are done, the functions returned by every session are called and their return values printed,
+//! one per line. The options passed using `-Cllvm-args` are applied to every session as usual.
+//! Unless `-Cllvm-args=mode=...` is passed, the crate is JIT compiled.
+//!
+//! With `--no-keep-module` only a single session is run without keeping the JIT module. The
+//! process exits once the entry returned, so the return value of the returned function is printed
+//! right away.
+//!
+//! With `--after-analysis` the crate is JIT compiled by calling [`run_jit_entry`] once the crate
+//! has been analyzed, instead of by the codegen backend. Errors returned by it are printed to
+//! stderr using their `Debug` representation, after which the process exits with code 1.
+//!
+//! With `--code-size` the size of the machine code of `jit_session_entry` returned by
+//! [`jit_function_code`] is printed to stderr before calling it.
+//!
+//! Imports of the JIT compiled code are resolved using a [`JitSymbolResolver`] providing
+//! `jit_session_host_value`, which returns 42, before falling back to the symbols of the process.
+
+#![feature(rustc_private, once_cell)]
+
+extern crate rustc_driver;
+extern crate rustc_interface;
+extern crate rustc_target;
+
+use std::lazy::SyncLazy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use rustc_codegen_cranelift::{
+    jit_function_code, run_jit_entry, BackendConfig, CodegenMode, CraneliftCodegenBackend,
+    JitEntry, JitSymbolResolver,
+};
+use rustc_driver::Compilation;
+use rustc_interface::{interface, Queries};
+use rustc_target::spec::PanicStrategy;
+
+/// The functions returned by the entry of every session so far.
+static RESULTS: SyncLazy<Mutex<Vec<extern "C" fn() -> u32>>> =
+    SyncLazy::new(|| Mutex::new(Vec::new()));
+
+static KEEP_MODULE: AtomicBool = AtomicBool::new(true);
+static PRINT_CODE_SIZE: AtomicBool = AtomicBool::new(false);
+
+struct JitSessionCallbacks {
+    llvm_args: Vec<String>,
+    after_analysis: bool,
+}
+
+impl rustc_driver::Callbacks for JitSessionCallbacks {
+    fn config(&mut self, config: &mut interface::Config) {
+        config.opts.cg.panic = Some(PanicStrategy::Abort);
+        config.opts.maybe_sysroot = Some(config.opts.maybe_sysroot.clone().unwrap_or_else(|| {
+            std::env::current_exe()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_owned()
+        }));
+    }
+
+    fn after_analysis<'tcx>(
+        &mut self,
+        _compiler: &interface::Compiler,
+        queries: &'tcx Queries<'tcx>,
+    ) -> Compilation {
+        if !self.after_analysis {
+            return Compilation::Continue;
+        }
+
+        let config = session_config(&self.llvm_args);
+        queries.global_ctxt().unwrap().peek_mut().enter(|tcx| {
+            if let Err(err) = run_jit_entry(tcx, &config) {
+                eprintln!("{:?}", err);
+                std::process::exit(1);
+            }
+        });
+        Compilation::Stop
+    }
+}
+
+/// The config of every session, given the options passed using `-Cllvm-args`.
+fn session_config(llvm_args: &[String]) -> BackendConfig {
+    let mut config = BackendConfig::from_opts(llvm_args).unwrap();
+    if !llvm_args.iter().any(|arg| arg.starts_with("mode=")) {
+        config.codegen_mode = CodegenMode::Jit;
+    }
+    config.jit_entry = Some(JitEntry {
+        symbol: "jit_session_entry".to_string(),
+        run: run_session_entry,
+    });
+    // `host_value` lives as long as the process.
+    config.jit_symbol_resolver = Some(unsafe { JitSymbolResolver::new(resolve_host_symbol) });
+    config.jit_keep_module = KEEP_MODULE.load(Ordering::SeqCst);
+    config
+}
+
+extern "C" fn host_value() -> u32 {
+    42
+}
+
+fn resolve_host_symbol(name: &str) -> Option<*const u8> {
+    match name {
+        "jit_session_host_value" => Some(host_value as *const u8),
+        _ => None,
+    }
+}
+
+fn run_session_entry(entry: *const u8) -> i32 {
+    if PRINT_CODE_SIZE.load(Ordering::SeqCst) {
+        // The JIT module is alive while the entry runs.
+        let code = unsafe { jit_function_code("jit_session_entry") };
+        eprintln!(
+            "jit_session_entry: {} bytes",
+            code.expect("`jit_session_entry` wasn't JIT compiled").len()
+        );
+    }
+    let entry: extern "C" fn() -> extern "C" fn() -> u32 = unsafe { std::mem::transmute(entry) };
+    let get = entry();
+    if KEEP_MODULE.load(Ordering::SeqCst) {
+        RESULTS.lock().unwrap().push(get);
+    } else {
+        println!("{}", get());
+    }
+    0
+}
+
+fn main() {
+    rustc_driver::init_rustc_env_logger();
+    rustc_driver::install_ice_hook();
+    let mut args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(|arg| &**arg) == Some("--no-keep-module") {
+        KEEP_MODULE.store(false, Ordering::SeqCst);
+        args.remove(1);
+    }
+    let after_analysis = args.get(1).map(|arg| &**arg) == Some("--after-analysis");
+    if after_analysis {
+        args.remove(1);
+    }
+    if args.get(1).map(|arg| &**arg) == Some("--code-size") {
+        PRINT_CODE_SIZE.store(true, Ordering::SeqCst);
+        args.remove(1);
+    }
+    for session_args in args[1..].split(|arg| arg == "--next") {
+        let rustc_args = std::iter::once(args[0].clone())
+            .chain(session_args.iter().cloned())
+            .collect::<Vec<_>>();
+        let llvm_args = session_args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("-Cllvm-args="))
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+        let mut callbacks = JitSessionCallbacks {
+            llvm_args: llvm_args.clone(),
+            after_analysis,
+        };
+        let exit_code = rustc_driver::catch_with_exit_code(|| {
+            let mut run_compiler = rustc_driver::RunCompiler::new(&rustc_args, &mut callbacks);
+            // `BackendConfig` isn't `Send`, so it has to be created by the closure itself.
+            let llvm_args = llvm_args.clone();
+            run_compiler.set_make_codegen_backend(Some(Box::new(move |_| {
+                Box::new(CraneliftCodegenBackend {
+                    config: Some(session_config(&llvm_args)),
+                })
+            })));
+            run_compiler.run()
+        });
+        if exit_code != 0 {
+            std::process::exit(exit_code);
+        }
+    }
+
+    for get in RESULTS.lock().unwrap().iter() {
+        println!("{}", get());
+    }
+}
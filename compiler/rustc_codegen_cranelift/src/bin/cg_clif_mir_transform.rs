@@ -0,0 +1,82 @@
+//! Test driver compiling a crate in AOT mode with a [`MirTransform`] set. Used by
+//! `scripts/tests.sh`.
+//!
+//! Usage: `cg_clif_mir_transform <rustc args>`
+//!
+//! The transform replaces the MIR of every local function named `replace_me` with the MIR of the
+//! local function named `replacement` and prints the path of every replaced function to stderr.
+//! The options passed using `-Cllvm-args` are applied as usual.
+
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+extern crate rustc_hir;
+extern crate rustc_interface;
+extern crate rustc_target;
+
+use rustc_codegen_cranelift::{BackendConfig, CodegenMode, CraneliftCodegenBackend, MirTransform};
+use rustc_hir::def_id::{DefId, LOCAL_CRATE};
+use rustc_interface::interface;
+use rustc_target::spec::PanicStrategy;
+
+struct MirTransformCallbacks;
+
+impl rustc_driver::Callbacks for MirTransformCallbacks {
+    fn config(&mut self, config: &mut interface::Config) {
+        config.opts.cg.panic = Some(PanicStrategy::Abort);
+        config.opts.maybe_sysroot = Some(config.opts.maybe_sysroot.clone().unwrap_or_else(|| {
+            std::env::current_exe()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_owned()
+        }));
+    }
+}
+
+fn main() {
+    rustc_driver::init_rustc_env_logger();
+    rustc_driver::install_ice_hook();
+    let args = std::env::args().collect::<Vec<_>>();
+    let llvm_args = args
+        .iter()
+        .filter_map(|arg| arg.strip_prefix("-Cllvm-args="))
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>();
+    let exit_code = rustc_driver::catch_with_exit_code(|| {
+        let mut run_compiler = rustc_driver::RunCompiler::new(&args, &mut MirTransformCallbacks);
+        // `BackendConfig` isn't `Send`, so it has to be created by the closure itself.
+        run_compiler.set_make_codegen_backend(Some(Box::new(move |_| {
+            let mut config = BackendConfig::from_opts(&llvm_args).unwrap();
+            config.codegen_mode = CodegenMode::Aot;
+            config.mir_transform = Some(MirTransform::new(|tcx, instance, _mir| {
+                // Closures and anonymous constants don't have a name.
+                let is_named = |def_id: DefId, name: &str| {
+                    def_id.is_local()
+                        && tcx
+                            .opt_item_name(def_id)
+                            .map_or(false, |ident| ident.as_str() == name)
+                };
+                let def_id = instance.def_id();
+                if !is_named(def_id, "replace_me") {
+                    return None;
+                }
+                let replacement = tcx
+                    .mir_keys(LOCAL_CRATE)
+                    .iter()
+                    .map(|def_id| def_id.to_def_id())
+                    .find(|&def_id| is_named(def_id, "replacement"))
+                    .expect("no function named `replacement`");
+                eprintln!("replaced the MIR of `{}`", tcx.def_path_str(def_id));
+                Some(tcx.optimized_mir(replacement).clone())
+            }));
+            Box::new(CraneliftCodegenBackend {
+                config: Some(config),
+            })
+        })));
+        run_compiler.run()
+    });
+    std::process::exit(exit_code);
+}
@@ -0,0 +1,68 @@
+//! Test driver compiling a crate in AOT mode with an [`ObjectSink`] set. Used by
+//! `scripts/tests.sh`.
+//!
+//! Usage: `cg_clif_object_sink <sink dir> <rustc args>`
+//!
+//! The sink writes every object file it receives to `<sink dir>/<module name>.sink.o` and returns
+//! that path, so that rustc links the crate using the object files written by the sink. The
+//! options passed using `-Cllvm-args` are applied as usual.
+
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+extern crate rustc_interface;
+extern crate rustc_target;
+
+use std::path::PathBuf;
+
+use rustc_codegen_cranelift::{BackendConfig, CodegenMode, CraneliftCodegenBackend, ObjectSink};
+use rustc_interface::interface;
+use rustc_target::spec::PanicStrategy;
+
+struct ObjectSinkCallbacks;
+
+impl rustc_driver::Callbacks for ObjectSinkCallbacks {
+    fn config(&mut self, config: &mut interface::Config) {
+        config.opts.cg.panic = Some(PanicStrategy::Abort);
+        config.opts.maybe_sysroot = Some(config.opts.maybe_sysroot.clone().unwrap_or_else(|| {
+            std::env::current_exe()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .parent()
+                .unwrap()
+                .to_owned()
+        }));
+    }
+}
+
+fn main() {
+    rustc_driver::init_rustc_env_logger();
+    rustc_driver::install_ice_hook();
+    let mut args = std::env::args().collect::<Vec<_>>();
+    let sink_dir = PathBuf::from(args.remove(1));
+    let llvm_args = args
+        .iter()
+        .filter_map(|arg| arg.strip_prefix("-Cllvm-args="))
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>();
+    let exit_code = rustc_driver::catch_with_exit_code(|| {
+        let mut run_compiler = rustc_driver::RunCompiler::new(&args, &mut ObjectSinkCallbacks);
+        // `BackendConfig` isn't `Send`, so it has to be created by the closure itself.
+        run_compiler.set_make_codegen_backend(Some(Box::new(move |_| {
+            let mut config = BackendConfig::from_opts(&llvm_args).unwrap();
+            config.codegen_mode = CodegenMode::Aot;
+            let sink_dir = sink_dir.clone();
+            config.object_sink = Some(ObjectSink::new(move |name, obj| {
+                let path = sink_dir.join(format!("{}.sink.o", name));
+                std::fs::write(&path, obj).unwrap();
+                path
+            }));
+            Box::new(CraneliftCodegenBackend {
+                config: Some(config),
+            })
+        })));
+        run_compiler.run()
+    });
+    std::process::exit(exit_code);
+}
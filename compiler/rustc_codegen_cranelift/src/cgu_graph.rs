@@ -0,0 +1,107 @@
+//! Dumping of the partitioning of the crate into codegen units as Graphviz DOT graph or as map
+//! from symbols to codegen units
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use rustc_middle::mir::mono::{CodegenUnit, MonoItem};
+
+use crate::prelude::*;
+
+/// Write an undirected graph with a node for every codegen unit to `path`. Every node shows the
+/// number of mono items and the estimated size of the codegen unit. Codegen units sharing mono
+/// items, like `#[inline]` functions which are instantiated in every codegen unit using them, are
+/// connected by an edge labeled with the number of shared mono items.
+pub(crate) fn write_cgu_graph(tcx: TyCtxt<'_>, path: &Path, cgus: &[CodegenUnit<'_>]) {
+    let mut cgus = cgus.iter().collect::<Vec<_>>();
+    cgus.sort_by_cached_key(|cgu| cgu.name().as_str().to_string());
+
+    let mut cgus_of_item = FxHashMap::<MonoItem<'_>, Vec<usize>>::default();
+    for (index, cgu) in cgus.iter().enumerate() {
+        for &mono_item in cgu.items().keys() {
+            cgus_of_item.entry(mono_item).or_default().push(index);
+        }
+    }
+
+    let mut shared_items = BTreeMap::<(usize, usize), usize>::new();
+    for cgu_indices in cgus_of_item.values() {
+        for (i, &a) in cgu_indices.iter().enumerate() {
+            for &b in &cgu_indices[i + 1..] {
+                *shared_items.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+    }
+
+    let mut graph = String::new();
+    writeln!(graph, "graph \"{}\" {{", tcx.crate_name(LOCAL_CRATE)).unwrap();
+    writeln!(graph, "    node [shape=box];").unwrap();
+    for cgu in &cgus {
+        writeln!(
+            graph,
+            "    \"{name}\" [label=\"{name}\\n{items} items\\nsize estimate {size}\"];",
+            name = cgu.name(),
+            items = cgu.items().len(),
+            size = cgu.size_estimate(),
+        )
+        .unwrap();
+    }
+    for ((a, b), count) in shared_items {
+        writeln!(
+            graph,
+            "    \"{}\" -- \"{}\" [label=\"{}\"];",
+            cgus[a].name(),
+            cgus[b].name(),
+            count,
+        )
+        .unwrap();
+    }
+    writeln!(graph, "}}").unwrap();
+
+    if let Err(err) = std::fs::write(path, graph) {
+        tcx.sess.fatal(&format!(
+            "error writing codegen unit graph {}: {}",
+            path.display(),
+            err
+        ));
+    }
+}
+
+/// Write a line `<symbol> <codegen unit>` for every function and static of every codegen unit to
+/// `path`, sorted by symbol name. Items instantiated in multiple codegen units, like `#[inline]`
+/// functions, get a line for every codegen unit.
+pub(crate) fn write_symbol_map(
+    tcx: TyCtxt<'_>,
+    symbol_prefix: Option<&str>,
+    path: &Path,
+    cgus: &[CodegenUnit<'_>],
+) {
+    let mut symbols = Vec::new();
+    for cgu in cgus {
+        for (mono_item, _) in cgu.items_in_deterministic_order(tcx) {
+            let instance = match mono_item {
+                MonoItem::Fn(instance) => instance,
+                MonoItem::Static(def_id) => Instance::mono(tcx, def_id),
+                MonoItem::GlobalAsm(_) => continue,
+            };
+            symbols.push((
+                crate::linkage::symbol_name(tcx, symbol_prefix, instance),
+                cgu.name().as_str().to_string(),
+            ));
+        }
+    }
+    symbols.sort();
+
+    let mut map = String::new();
+    for (symbol, cgu_name) in symbols {
+        writeln!(map, "{} {}", symbol, cgu_name).unwrap();
+    }
+
+    if let Err(err) = std::fs::write(path, map) {
+        tcx.sess.fatal(&format!(
+            "error writing symbol map {}: {}",
+            path.display(),
+            err
+        ));
+    }
+}
@@ -309,11 +309,19 @@ pub(crate) struct FunctionCx<'clif, 'tcx, M: Module> {
 
     pub(crate) clif_comments: crate::pretty_clif::CommentWriter,
     pub(crate) source_info_set: indexmap::IndexSet<SourceInfo>,
+    /// Span of the statement or terminator currently being codegened.
+    pub(crate) current_span: Span,
 
     /// This should only be accessed by `CPlace::new_var`.
     pub(crate) next_ssa_var: u32,
 
     pub(crate) inline_asm_index: u32,
+
+    /// Basic block counters when coverage instrumentation is enabled.
+    pub(crate) coverage_counters: Option<DataId>,
+
+    /// Calls to inline once the function is codegened, see [`crate::optimize::inline`].
+    pub(crate) inline_calls: Vec<(Inst, Instance<'tcx>)>,
 }
 
 impl<'tcx, M: Module> LayoutOf for FunctionCx<'_, 'tcx, M> {
@@ -381,6 +389,7 @@ pub(crate) fn get_local_place(&mut self, local: Local) -> CPlace<'tcx> {
 
     pub(crate) fn set_debug_loc(&mut self, source_info: mir::SourceInfo) {
         let (index, _) = self.source_info_set.insert_full(source_info);
+        self.current_span = source_info.span;
         self.bcx.set_srcloc(SourceLoc::new(index as u32));
     }
 
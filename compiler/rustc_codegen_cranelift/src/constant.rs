@@ -28,11 +28,29 @@ enum TodoItem {
 }
 
 impl ConstantCx {
-    pub(crate) fn finalize(mut self, tcx: TyCtxt<'_>, module: &mut impl Module) {
+    /// Defines all data objects referenced so far. This declares the functions they point to.
+    pub(crate) fn define_pending(
+        &mut self,
+        tcx: TyCtxt<'_>,
+        module: &mut impl Module,
+        symbol_prefix: Option<&str>,
+    ) {
+        define_all_allocs(tcx, module, symbol_prefix, self);
+    }
+
+    /// Returns the number of defined data objects
+    pub(crate) fn finalize(
+        mut self,
+        tcx: TyCtxt<'_>,
+        module: &mut impl Module,
+        symbol_prefix: Option<&str>,
+    ) -> usize {
         //println!("todo {:?}", self.todo);
-        define_all_allocs(tcx, module, &mut self);
+        define_all_allocs(tcx, module, symbol_prefix, &mut self);
         //println!("done {:?}", self.done);
+        let data_objects = self.done.len();
         self.done.clear();
+        data_objects
     }
 }
 
@@ -80,7 +98,13 @@ pub(crate) fn codegen_tls_ref<'tcx>(
     def_id: DefId,
     layout: TyAndLayout<'tcx>,
 ) -> CValue<'tcx> {
-    let data_id = data_id_for_static(fx.tcx, &mut fx.cx.module, def_id, false);
+    let data_id = data_id_for_static(
+        fx.tcx,
+        &mut fx.cx.module,
+        fx.cx.symbol_prefix.as_deref(),
+        def_id,
+        false,
+    );
     let local_data_id = fx.cx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
     #[cfg(debug_assertions)]
     fx.add_comment(local_data_id, format!("tls {:?}", def_id));
@@ -93,7 +117,13 @@ fn codegen_static_ref<'tcx>(
     def_id: DefId,
     layout: TyAndLayout<'tcx>,
 ) -> CPlace<'tcx> {
-    let data_id = data_id_for_static(fx.tcx, &mut fx.cx.module, def_id, false);
+    let data_id = data_id_for_static(
+        fx.tcx,
+        &mut fx.cx.module,
+        fx.cx.symbol_prefix.as_deref(),
+        def_id,
+        false,
+    );
     let local_data_id = fx.cx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
     #[cfg(debug_assertions)]
     fx.add_comment(local_data_id, format!("{:?}", def_id));
@@ -202,16 +232,25 @@ pub(crate) fn codegen_const_value<'tcx>(
                             fx.bcx.ins().global_value(fx.pointer_type, local_data_id)
                         }
                         Some(GlobalAlloc::Function(instance)) => {
-                            let func_id =
-                                crate::abi::import_function(fx.tcx, &mut fx.cx.module, instance);
+                            let func_id = crate::abi::import_function(
+                                fx.tcx,
+                                &mut fx.cx.module,
+                                fx.cx.symbol_prefix.as_deref(),
+                                instance,
+                            );
                             let local_func_id =
                                 fx.cx.module.declare_func_in_func(func_id, &mut fx.bcx.func);
                             fx.bcx.ins().func_addr(fx.pointer_type, local_func_id)
                         }
                         Some(GlobalAlloc::Static(def_id)) => {
                             assert!(fx.tcx.is_static(def_id));
-                            let data_id =
-                                data_id_for_static(fx.tcx, &mut fx.cx.module, def_id, false);
+                            let data_id = data_id_for_static(
+                                fx.tcx,
+                                &mut fx.cx.module,
+                                fx.cx.symbol_prefix.as_deref(),
+                                def_id,
+                                false,
+                            );
                             let local_data_id =
                                 fx.cx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
                             #[cfg(debug_assertions)]
@@ -282,6 +321,7 @@ fn data_id_for_alloc_id(
 fn data_id_for_static(
     tcx: TyCtxt<'_>,
     module: &mut impl Module,
+    symbol_prefix: Option<&str>,
     def_id: DefId,
     definition: bool,
 ) -> DataId {
@@ -297,7 +337,7 @@ fn data_id_for_static(
     };
 
     let instance = Instance::mono(tcx, def_id).polymorphize(tcx);
-    let symbol_name = tcx.symbol_name(instance).name;
+    let symbol_name = crate::linkage::symbol_name(tcx, symbol_prefix, instance);
     let ty = instance.ty(tcx, ParamEnv::reveal_all());
     let is_mutable = if tcx.is_mutable_static(def_id) {
         true
@@ -356,9 +396,72 @@ fn data_id_for_static(
     }
 }
 
-fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut ConstantCx) {
+/// Returns whether the runtime calls the function pointers in the section `section_name` before
+/// `main` or at exit, like for `.init_array`. This includes sections with a priority suffix like
+/// `.init_array.00100` and the Mach-O `__mod_init_func` and `__mod_term_func` sections.
+pub(crate) fn is_init_fini_section(section_name: &str) -> bool {
+    if let Some((_segment_name, section_name)) = section_name.split_once(',') {
+        let section_name = section_name.split(',').next().unwrap();
+        return section_name == "__mod_init_func" || section_name == "__mod_term_func";
+    }
+
+    [
+        ".preinit_array",
+        ".init_array",
+        ".fini_array",
+        ".ctors",
+        ".dtors",
+    ]
+    .iter()
+    .any(|prefix| {
+        section_name
+            .strip_prefix(prefix)
+            .map_or(false, |suffix| suffix.is_empty() || suffix.starts_with('.'))
+    })
+}
+
+/// Every pointer-sized entry of an init or fini section is called by the runtime, so anything but
+/// function pointers in it would crash the program before `main` or at exit.
+fn check_init_fini_static(tcx: TyCtxt<'_>, def_id: DefId, alloc: &Allocation, section_name: &str) {
+    let ptr_size = tcx.data_layout.pointer_size.bytes();
+    let relocations = alloc.relocations();
+    let only_fn_ptrs = alloc.len() as u64 % ptr_size == 0
+        && (0..alloc.len() as u64 / ptr_size).all(|i| {
+            match relocations.get(&Size::from_bytes(i * ptr_size)) {
+                Some(&(_tag, alloc_id)) => matches!(
+                    tcx.get_global_alloc(alloc_id),
+                    Some(GlobalAlloc::Function(_))
+                ),
+                None => false,
+            }
+        });
+    if !only_fn_ptrs {
+        tcx.sess.span_err(
+            tcx.def_span(def_id),
+            &format!(
+                "statics in the `{}` section must only contain function pointers",
+                section_name
+            ),
+        );
+    }
+}
+
+/// Defines all allocations and statics in `cx.todo`, together with the allocations they reference.
+///
+/// This is compatible with full RELRO (`-z relro -z now`): read-only data objects containing
+/// pointers are placed in `.data.rel.ro` by `cranelift_object`, which the linker makes read-only
+/// after the dynamic loader applied all relocations. No dynamic relocations are ever emitted
+/// against read-only sections, so no text relocations are necessary. With `-Crelocation-model=pic`
+/// code references imported functions and data objects through the GOT, which the loader fills at
+/// startup with `-z now` too.
+fn define_all_allocs(
+    tcx: TyCtxt<'_>,
+    module: &mut impl Module,
+    symbol_prefix: Option<&str>,
+    cx: &mut ConstantCx,
+) {
     while let Some(todo_item) = cx.todo.pop() {
-        let (data_id, alloc, section_name) = match todo_item {
+        let (data_id, alloc, section_name, used) = match todo_item {
             TodoItem::Alloc(alloc_id) => {
                 //println!("alloc_id {}", alloc_id);
                 let alloc = match tcx.get_global_alloc(alloc_id).unwrap() {
@@ -366,7 +469,7 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
                     GlobalAlloc::Function(_) | GlobalAlloc::Static(_) => unreachable!(),
                 };
                 let data_id = data_id_for_alloc_id(module, alloc_id, alloc.mutability);
-                (data_id, alloc, None)
+                (data_id, alloc, None, false)
             }
             TodoItem::Static(def_id) => {
                 //println!("static {:?}", def_id);
@@ -375,11 +478,20 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
                     .codegen_fn_attrs(def_id)
                     .link_section
                     .map(|s| s.as_str());
+                let used = tcx
+                    .codegen_fn_attrs(def_id)
+                    .flags
+                    .contains(CodegenFnAttrFlags::USED);
 
                 let alloc = tcx.eval_static_initializer(def_id).unwrap();
+                if let Some(section_name) = &section_name {
+                    if is_init_fini_section(section_name) {
+                        check_init_fini_static(tcx, def_id, alloc, section_name);
+                    }
+                }
 
-                let data_id = data_id_for_static(tcx, module, def_id, true);
-                (data_id, alloc, section_name)
+                let data_id = data_id_for_static(tcx, module, symbol_prefix, def_id, true);
+                (data_id, alloc, section_name, used)
             }
         };
 
@@ -392,8 +504,34 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
         data_ctx.set_align(alloc.align.bytes());
 
         if let Some(section_name) = section_name {
-            // FIXME set correct segment for Mach-O files
-            data_ctx.set_segment_section("", &*section_name);
+            let (segment_name, section_name) = if tcx.sess.target.is_like_osx {
+                // Mach-O section names have the form `segment,section[,attributes]`.
+                if let Some((segment_name, section_name)) = section_name.split_once(',') {
+                    let section_name = section_name.split(',').next().unwrap();
+                    (segment_name, section_name)
+                } else {
+                    tcx.sess.fatal(&format!(
+                        "#[link_section = \"{}\"] is not valid for macos target: must be segment and section separated by comma",
+                        section_name
+                    ));
+                }
+            } else {
+                ("", &*section_name)
+            };
+            data_ctx.set_segment_section(segment_name, section_name);
+        } else if used {
+            // Retaining a section retains everything in it, so `#[used]` statics get a section of
+            // their own. `cranelift_object` doesn't support custom sections for TLS.
+            let decl = module.declarations().get_data_decl(data_id);
+            if !decl.tls {
+                let (segment_name, section_name) = used_static_section(
+                    tcx,
+                    &decl.name,
+                    decl.writable,
+                    !alloc.relocations().is_empty(),
+                );
+                data_ctx.set_segment_section(segment_name, &section_name);
+            }
         }
 
         let bytes = alloc
@@ -416,7 +554,7 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
             let data_id = match reloc_target_alloc {
                 GlobalAlloc::Function(instance) => {
                     assert_eq!(addend, 0);
-                    let func_id = crate::abi::import_function(tcx, module, instance);
+                    let func_id = crate::abi::import_function(tcx, module, symbol_prefix, instance);
                     let local_func_id = module.declare_func_in_data(func_id, &mut data_ctx);
                     data_ctx.write_function_addr(offset.bytes() as u32, local_func_id);
                     continue;
@@ -440,7 +578,7 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
                     // Don't push a `TodoItem::Static` here, as it will cause statics used by
                     // multiple crates to be duplicated between them. It isn't necessary anyway,
                     // as it will get pushed by `codegen_static` when necessary.
-                    data_id_for_static(tcx, module, def_id, false)
+                    data_id_for_static(tcx, module, symbol_prefix, def_id, false)
                 }
             };
 
@@ -456,6 +594,35 @@ fn define_all_allocs(tcx: TyCtxt<'_>, module: &mut impl Module, cx: &mut Constan
     assert!(cx.todo.is_empty(), "{:?}", cx.todo);
 }
 
+/// Returns the segment and section name for the `#[used]` static `symbol_name`. Every call to
+/// `define_data` with a custom section creates a new section, even if the name is already used.
+fn used_static_section(
+    tcx: TyCtxt<'_>,
+    symbol_name: &str,
+    writable: bool,
+    has_relocations: bool,
+) -> (&'static str, String) {
+    if tcx.sess.target.is_like_osx {
+        match (writable, has_relocations) {
+            (true, _) => ("__DATA", "__data".to_string()),
+            (false, true) => ("__DATA", "__const".to_string()),
+            (false, false) => ("__TEXT", "__const".to_string()),
+        }
+    } else if tcx.sess.target.is_like_windows {
+        // link.exe only removes COMDAT sections with `/OPT:REF`, which `cranelift_object` never
+        // emits, so a section of its own is enough to retain the static.
+        let section_name = if writable { ".data" } else { ".rdata" };
+        ("", section_name.to_string())
+    } else {
+        let section_name = match (writable, has_relocations) {
+            (true, _) => ".data",
+            (false, true) => ".data.rel.ro",
+            (false, false) => ".rodata",
+        };
+        ("", format!("{}.{}", section_name, symbol_name))
+    }
+}
+
 pub(crate) fn mir_operand_get_const_val<'tcx>(
     fx: &FunctionCx<'_, 'tcx, impl Module>,
     operand: &Operand<'tcx>,
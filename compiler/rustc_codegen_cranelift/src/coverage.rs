@@ -0,0 +1,160 @@
+//! Simple basic block coverage counters, enabled using `-Cllvm-args=coverage=1`.
+//!
+//! Every codegened function gets a zero initialized array of `u64` counters with one counter per
+//! MIR basic block. The counter of a basic block is incremented every time the block is entered.
+//! The counter arrays are placed in the `__cg_clif_cov` section, so they can be found at runtime
+//! using the `__start___cg_clif_cov` and `__stop___cg_clif_cov` symbols on ELF targets.
+//!
+//! The mapping from counters to source locations is placed in the `__cg_clif_covmap` section. It
+//! contains one JSON object per line for every instrumented function:
+//!
+//! ```json
+//! {"function":"<symbol>","counters":"__cg_clif_cov_<symbol>","blocks":[{"file":"src/lib.rs","line":1,"col":1}]}
+//! ```
+//!
+//! The n-th entry of `blocks` describes the n-th counter. As the sections of all object files are
+//! concatenated by the linker, the map of the final executable is still one object per line. Nothing
+//! references the coverage map, so its section is marked as retained to survive `--gc-sections`.
+//! Dumping the counters at exit is left to the program.
+//!
+//! Note that the counters are not incremented atomically.
+
+use std::fmt::Write;
+
+use crate::prelude::*;
+
+const COUNTERS_SECTION: &str = "__cg_clif_cov";
+const MAP_SECTION: &str = "__cg_clif_covmap";
+
+/// The coverage map of all functions in a codegen unit.
+#[derive(Default)]
+pub(crate) struct CoverageMap {
+    map: String,
+}
+
+/// Declares and defines the counter array for `mir` and records the source location of every
+/// basic block in the coverage map.
+pub(crate) fn declare_counters<'tcx>(
+    cx: &mut crate::CodegenCx<'tcx, impl Module>,
+    symbol_name: &str,
+    mir: &Body<'tcx>,
+) -> Option<DataId> {
+    let coverage = cx.coverage.as_mut()?;
+
+    let counters_name = format!("__cg_clif_cov_{}", symbol_name);
+    let source_map = cx.tcx.sess.source_map();
+    write!(
+        coverage.map,
+        "{{\"function\":{},\"counters\":{},\"blocks\":[",
+        json_string(symbol_name),
+        json_string(&counters_name),
+    )
+    .unwrap();
+    for (bb, bb_data) in mir.basic_blocks().iter_enumerated() {
+        let span = bb_data
+            .statements
+            .first()
+            .map_or(bb_data.terminator().source_info.span, |stmt| {
+                stmt.source_info.span
+            });
+        let loc = source_map.lookup_char_pos(span.lo());
+        if bb != START_BLOCK {
+            coverage.map.push(',');
+        }
+        write!(
+            coverage.map,
+            "{{\"file\":{},\"line\":{},\"col\":{}}}",
+            json_string(&loc.file.name.to_string()),
+            loc.line,
+            loc.col.0 + 1,
+        )
+        .unwrap();
+    }
+    coverage.map.push_str("]}\n");
+
+    let mut data_ctx = DataContext::new();
+    data_ctx.define_zeroinit(mir.basic_blocks().len() * 8);
+    data_ctx.set_align(8);
+    data_ctx.set_segment_section(data_segment(cx.tcx), COUNTERS_SECTION);
+    let data_id = cx
+        .module
+        .declare_data(&counters_name, Linkage::Local, true, false)
+        .unwrap();
+    cx.module.define_data(data_id, &data_ctx).unwrap();
+
+    Some(data_id)
+}
+
+/// Increments the counter of `bb`. Must be called at the start of the block.
+pub(crate) fn increment_counter(fx: &mut FunctionCx<'_, '_, impl Module>, bb: BasicBlock) {
+    let counters = match fx.coverage_counters {
+        Some(counters) => counters,
+        None => return,
+    };
+
+    let local_data_id = fx
+        .cx
+        .module
+        .declare_data_in_func(counters, &mut fx.bcx.func);
+    let counters = fx.bcx.ins().global_value(fx.pointer_type, local_data_id);
+    let offset = i32::try_from(bb.as_usize() * 8).unwrap();
+    let count = fx
+        .bcx
+        .ins()
+        .load(types::I64, MemFlags::trusted(), counters, offset);
+    let count = fx.bcx.ins().iadd_imm(count, 1);
+    fx.bcx
+        .ins()
+        .store(MemFlags::trusted(), count, counters, offset);
+}
+
+/// Defines the coverage map of a codegen unit. The returned data object must be retained using
+/// [`RetainData`](crate::backend::RetainData).
+pub(crate) fn define_coverage_map(
+    tcx: TyCtxt<'_>,
+    module: &mut impl Module,
+    cgu_name: &str,
+    coverage: CoverageMap,
+) -> Option<DataId> {
+    if coverage.map.is_empty() {
+        return None;
+    }
+
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(coverage.map.into_bytes().into_boxed_slice());
+    data_ctx.set_segment_section(data_segment(tcx), MAP_SECTION);
+    let data_id = module
+        .declare_data(
+            &format!("__cg_clif_covmap_{}", cgu_name),
+            Linkage::Local,
+            false,
+            false,
+        )
+        .unwrap();
+    module.define_data(data_id, &data_ctx).unwrap();
+
+    Some(data_id)
+}
+
+fn data_segment(tcx: TyCtxt<'_>) -> &'static str {
+    if tcx.sess.target.is_like_osx {
+        "__DATA"
+    } else {
+        ""
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(res, "\\u{:04x}", c as u32).unwrap(),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
@@ -10,7 +10,9 @@
 use super::DebugContext;
 
 impl DebugContext<'_> {
-    pub(crate) fn emit<P: WriteDebugInfo>(&mut self, product: &mut P) {
+    /// Writes the debuginfo sections to `product`, compressing them when `compress` is set. See
+    /// [`crate::BackendConfig::compress_debug_sections`].
+    pub(crate) fn emit<P: WriteDebugInfo>(&mut self, product: &mut P, compress: bool) {
         let unit_range_list_id = self.dwarf.unit.ranges.add(self.unit_range_list.clone());
         let root = self.dwarf.unit.root();
         let root = self.dwarf.unit.get_mut(root);
@@ -22,10 +24,18 @@ pub(crate) fn emit<P: WriteDebugInfo>(&mut self, product: &mut P) {
         let mut sections = Sections::new(WriterRelocate::new(self.endian));
         self.dwarf.write(&mut sections).unwrap();
 
+        let big_endian = self.endian == RunTimeEndian::Big;
+        let is_64 = self.dwarf.unit.encoding().address_size == 8;
         let mut section_map = FxHashMap::default();
         let _: Result<()> = sections.for_each_mut(|id, section| {
             if !section.writer.slice().is_empty() {
-                let section_id = product.add_debug_section(id, section.writer.take());
+                let data = section.writer.take();
+                let section_id = if compress {
+                    let data = crate::backend::compress_elf_section(&data, big_endian, is_64);
+                    product.add_debug_section(id, data, true)
+                } else {
+                    product.add_debug_section(id, data, false)
+                };
                 section_map.insert(id, section_id);
             }
             Ok(())
@@ -7,6 +7,7 @@
 use crate::prelude::*;
 
 use rustc_index::vec::IndexVec;
+use rustc_middle::ty::print::with_no_trimmed_paths;
 
 use cranelift_codegen::entity::EntityRef;
 use cranelift_codegen::ir::{StackSlots, ValueLabel, ValueLoc};
@@ -20,7 +21,9 @@
 use gimli::{Encoding, Format, LineEncoding, RunTimeEndian, X86_64};
 
 pub(crate) use emit::{DebugReloc, DebugRelocName};
-pub(crate) use unwind::UnwindContext;
+#[cfg(feature = "jit")]
+pub(crate) use unwind::UnwindRegistry;
+pub(crate) use unwind::{emit_unwind_tables, UnwindContext};
 
 fn target_endian(tcx: TyCtxt<'_>) -> RunTimeEndian {
     use rustc_target::abi::Endian;
@@ -43,8 +46,30 @@ pub(crate) struct DebugContext<'tcx> {
     types: FxHashMap<Ty<'tcx>, UnitEntryId>,
 }
 
+/// Returns whether DWARF debuginfo is usable on the current target. MSVC targets expect CodeView
+/// debuginfo in PDB files, which isn't supported.
+fn is_debuginfo_supported(tcx: TyCtxt<'_>) -> bool {
+    match crate::target_triple(tcx.sess).binary_format {
+        target_lexicon::BinaryFormat::Elf | target_lexicon::BinaryFormat::Macho => true,
+        target_lexicon::BinaryFormat::Coff => !tcx.sess.target.is_like_msvc,
+        _ => false,
+    }
+}
+
 impl<'tcx> DebugContext<'tcx> {
-    pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Self {
+    /// Returns `None` and warns once when debuginfo is not supported for the current target.
+    pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Option<Self> {
+        if !is_debuginfo_supported(tcx) {
+            static WARN_UNSUPPORTED: std::sync::Once = std::sync::Once::new();
+            WARN_UNSUPPORTED.call_once(|| {
+                tcx.sess.warn(&format!(
+                    "debuginfo is not supported for target `{}` by cg_clif, it will be omitted",
+                    tcx.sess.opts.target_triple
+                ));
+            });
+            return None;
+        }
+
         let encoding = Encoding {
             format: Format::Dwarf32,
             // TODO: this should be configurable
@@ -107,7 +132,7 @@ pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Self {
             );
         }
 
-        DebugContext {
+        Some(DebugContext {
             tcx,
 
             endian: target_endian(tcx),
@@ -117,7 +142,7 @@ pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa) -> Self {
 
             clif_types: FxHashMap::default(),
             types: FxHashMap::default(),
-        }
+        })
     }
 
     fn dwarf_ty_for_clif_ty(&mut self, ty: Type) -> UnitEntryId {
@@ -281,14 +306,22 @@ pub(crate) fn define_function(
         // FIXME: add to appropriate scope instead of root
         let scope = self.dwarf.unit.root();
 
+        // Debuggers show DW_AT_name in backtraces and accept it for breakpoints, so use the path of
+        // the function including generic arguments, like `foo::<u32>` or `main::{closure#0}`.
+        let readable_name = with_no_trimmed_paths(|| {
+            self.tcx
+                .def_path_str_with_substs(instance.def_id(), instance.substs)
+        });
+
         let entry_id = self.dwarf.unit.add(scope, gimli::DW_TAG_subprogram);
         let entry = self.dwarf.unit.get_mut(entry_id);
-        let name_id = self.dwarf.strings.add(name);
+        let name_id = self.dwarf.strings.add(readable_name);
+        let linkage_name_id = self.dwarf.strings.add(name);
         // Gdb requires DW_AT_name. Otherwise the DW_TAG_subprogram is skipped.
         entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
         entry.set(
             gimli::DW_AT_linkage_name,
-            AttributeValue::StringRef(name_id),
+            AttributeValue::StringRef(linkage_name_id),
         );
 
         let end =
@@ -18,7 +18,9 @@ impl<'tcx> UnwindContext<'tcx> {
     pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa, pic_eh_frame: bool) -> Self {
         let mut frame_table = FrameTable::default();
 
-        let cie_id = if let Some(mut cie) = isa.create_systemv_cie() {
+        let cie_id = if !emit_unwind_tables(tcx.sess) {
+            None
+        } else if let Some(mut cie) = isa.create_systemv_cie() {
             if pic_eh_frame {
                 cie.fde_address_encoding =
                     gimli::DwEhPe(gimli::DW_EH_PE_pcrel.0 | gimli::DW_EH_PE_sdata4.0);
@@ -35,7 +37,18 @@ pub(crate) fn new(tcx: TyCtxt<'tcx>, isa: &dyn TargetIsa, pic_eh_frame: bool) ->
         }
     }
 
+    /// Returns whether unwind info is emitted for functions added to this context.
+    pub(crate) fn emits_unwind_info(&self) -> bool {
+        self.cie_id.is_some()
+    }
+
     pub(crate) fn add_function(&mut self, func_id: FuncId, context: &Context, isa: &dyn TargetIsa) {
+        let cie_id = if let Some(cie_id) = self.cie_id {
+            cie_id
+        } else {
+            return;
+        };
+
         let unwind_info = if let Some(unwind_info) = context.create_unwind_info(isa).unwrap() {
             unwind_info
         } else {
@@ -45,7 +58,7 @@ pub(crate) fn add_function(&mut self, func_id: FuncId, context: &Context, isa: &
         match unwind_info {
             UnwindInfo::SystemV(unwind_info) => {
                 self.frame_table.add_fde(
-                    self.cie_id.unwrap(),
+                    cie_id,
                     unwind_info.to_fde(Address::Symbol {
                         symbol: func_id.as_u32() as usize,
                         addend: 0,
@@ -67,7 +80,7 @@ pub(crate) fn emit<P: WriteDebugInfo>(self, product: &mut P) {
 
         if !eh_frame.0.writer.slice().is_empty() {
             let id = eh_frame.id();
-            let section_id = product.add_debug_section(id, eh_frame.0.writer.into_vec());
+            let section_id = product.add_debug_section(id, eh_frame.0.writer.into_vec(), false);
             let mut section_map = FxHashMap::default();
             section_map.insert(id, section_id);
 
@@ -137,6 +150,12 @@ pub(crate) unsafe fn register_jit(
     }
 }
 
+/// Whether to emit unwind tables. Like cg_llvm, they are omitted with `-Cpanic=abort` unless the
+/// target requires them or `-Cforce-unwind-tables=yes` is passed.
+pub(crate) fn emit_unwind_tables(sess: &rustc_session::Session) -> bool {
+    sess.must_emit_unwind_tables()
+}
+
 /// Represents a registry of function unwind information for System V ABI.
 pub(crate) struct UnwindRegistry {
     _frame_table: Vec<u8>,
@@ -1,14 +1,24 @@
 //! The AOT driver uses [`cranelift_object`] to write object files suitable for linking into a
 //! standalone executable.
 
-use std::path::PathBuf;
+use std::hash::Hash;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use rustc_ast::expand::allocator::AllocatorKind;
 use rustc_codegen_ssa::back::linker::LinkerInfo;
+use rustc_codegen_ssa::back::symbol_export;
 use rustc_codegen_ssa::{CodegenResults, CompiledModule, CrateInfo, ModuleKind};
+use rustc_data_structures::fingerprint::Fingerprint;
 use rustc_data_structures::stable_hasher::{HashStable, StableHasher};
+use rustc_hir::def_id::CrateNum;
 use rustc_middle::dep_graph::{WorkProduct, WorkProductId};
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::middle::cstore::EncodedMetadata;
-use rustc_middle::mir::mono::{CodegenUnit, MonoItem};
+use rustc_middle::middle::dependency_format::Linkage as DependencyLinkage;
+use rustc_middle::middle::exported_symbols::ExportedSymbol;
+use rustc_middle::mir::mono::{CodegenUnit, Linkage as RLinkage, MonoItem, Visibility};
 use rustc_session::cgu_reuse_tracker::CguReuse;
 use rustc_session::config::{DebugInfo, OutputType};
 
@@ -16,326 +26,1899 @@
 
 use crate::prelude::*;
 
-use crate::backend::AddConstructor;
+use super::Progress;
+use crate::backend::{
+    AddConstructor, FunctionSectionRenames, MakeFunctionWeak, RetainData, StripLocalSymbolNames,
+};
+use crate::BackendConfig;
 
-fn new_module(tcx: TyCtxt<'_>, name: String) -> ObjectModule {
-    let module = crate::backend::make_module(tcx.sess, name);
+fn new_module(tcx: TyCtxt<'_>, config: &BackendConfig, name: String) -> ObjectModule {
+    // Renaming the section of a function requires every function to have its own section.
+    let per_function_section = tcx
+        .sess
+        .opts
+        .debugging_opts
+        .function_sections
+        .unwrap_or(false)
+        || !config.function_section_rules.is_empty();
+    let module = crate::backend::make_module(
+        tcx.sess,
+        name,
+        per_function_section,
+        config.test_break_ir.is_some(),
+    );
     assert_eq!(pointer_ty(tcx), module.target_config().pointer_type());
     module
 }
 
-struct ModuleCodegenResult(CompiledModule, Option<(WorkProductId, WorkProduct)>);
+fn temp_path(tcx: TyCtxt<'_>, config: &BackendConfig, flavor: OutputType, name: &str) -> PathBuf {
+    let path = tcx
+        .output_filenames(LOCAL_CRATE)
+        .temp_path(flavor, Some(name));
+    if let Some(temp_dir) = &config.temp_dir {
+        temp_dir.join(path.file_name().unwrap())
+    } else {
+        path
+    }
+}
+
+fn set_file_flags(tcx: TyCtxt<'_>, config: &BackendConfig, object: &mut object::write::Object) {
+    if let Some(e_flags) = config.elf_e_flags {
+        if object.format() != object::BinaryFormat::Elf {
+            tcx.sess
+                .fatal("the `elf_e_flags` option is only supported for ELF targets");
+        }
+        object.flags = object::FileFlags::Elf { e_flags };
+    }
+}
+
+/// Checks that the `compress_debug_sections` option is supported for the target. Compressed
+/// sections are only supported by ELF. Relocations in them can't have an implicit addend, as it
+/// would be written into the compressed contents.
+fn check_compress_debug_sections(tcx: TyCtxt<'_>) {
+    use target_lexicon::{Architecture, BinaryFormat};
+
+    let target_triple = crate::target_triple(tcx.sess);
+    if target_triple.binary_format != BinaryFormat::Elf {
+        tcx.sess
+            .fatal("the `compress_debug_sections` option is only supported for ELF targets");
+    }
+    match target_triple.architecture {
+        Architecture::X86_64 | Architecture::Aarch64(_) | Architecture::Riscv64(_) => {}
+        arch => tcx.sess.fatal(&format!(
+            "the `compress_debug_sections` option is not supported for the `{}` architecture",
+            arch
+        )),
+    }
+}
+
+/// Returns the `EI_OSABI` byte of emitted ELF object files, unless it is the `ELFOSABI_NONE`
+/// written by `object`.
+fn elf_os_abi(tcx: TyCtxt<'_>, config: &BackendConfig) -> Option<u8> {
+    if let Some(os_abi) = config.elf_os_abi {
+        if crate::target_triple(tcx.sess).binary_format != target_lexicon::BinaryFormat::Elf {
+            tcx.sess
+                .fatal("the `elf_os_abi` option is only supported for ELF targets");
+        }
+        return Some(os_abi);
+    }
+    match &*tcx.sess.target.os {
+        "freebsd" => Some(object::elf::ELFOSABI_FREEBSD),
+        "solaris" | "illumos" => Some(object::elf::ELFOSABI_SOLARIS),
+        _ => None,
+    }
+}
+
+/// The last field contains the modules of the functions emitted into object files of their own
+/// when the `object_per_function` option is enabled.
+struct ModuleCodegenResult(
+    CompiledModule,
+    Option<(WorkProductId, WorkProduct)>,
+    Vec<CompiledModule>,
+);
 
 impl<HCX> HashStable<HCX> for ModuleCodegenResult {
     fn hash_stable(&self, _: &mut HCX, _: &mut StableHasher) {
-        // do nothing
+        // Only needed for the result fingerprint of the `CompileCodegenUnit` dep node, which is
+        // never compared. Whether a codegen unit can be reused is decided by marking its dep node
+        // green based on its dependencies, after which the cached work product is used. The dep
+        // node is never re-executed to check whether its result changed.
     }
 }
 
 fn emit_module(
     tcx: TyCtxt<'_>,
+    config: &BackendConfig,
     name: String,
     kind: ModuleKind,
     module: ObjectModule,
     debug: Option<DebugContext<'_>>,
     unwind_context: UnwindContext<'_>,
+    skip_if_empty: bool,
+    function_sections: &FxHashMap<String, String>,
     map_product: impl FnOnce(ObjectProduct) -> ObjectProduct,
 ) -> ModuleCodegenResult {
     let mut product = module.finish();
+    let tmp_file = temp_path(tcx, config, OutputType::Object, &name);
+
+    // Don't emit object files without any content, for example for codegen units consisting only
+    // of items that don't need to be codegened. Such modules have no object file, which is
+    // recorded in the work product too, so reusing the codegen unit doesn't produce one either.
+    let defines_anything = product
+        .functions
+        .values()
+        .chain(product.data_objects.values())
+        .any(|symbol| matches!(symbol, Some((_, true))));
+    // When the user requested object files, every codegen unit needs one, as rustc copies the
+    // object file of a single codegen unit to the final `--emit obj` output.
+    let user_wants_objects = tcx.sess.opts.output_types.contains_key(&OutputType::Object);
+    if skip_if_empty && !defines_anything && !user_wants_objects {
+        // Make sure that a stale object file from a previous compilation isn't picked up.
+        if config.object_sink.is_none() {
+            let _ = std::fs::remove_file(&tmp_file);
+        }
+        return compiled_module_result(tcx, config, name, kind, None);
+    }
 
     if let Some(mut debug) = debug {
-        debug.emit(&mut product);
+        debug.emit(&mut product, config.compress_debug_sections);
     }
 
     unwind_context.emit(&mut product);
 
-    let product = map_product(product);
+    let mut product = map_product(product);
+    set_file_flags(tcx, config, &mut product.object);
+    // Determined before the names of local symbols are stripped.
+    let section_renames = if product.object.format() == object::BinaryFormat::Elf {
+        product.function_section_renames(function_sections)
+    } else {
+        FxHashMap::default()
+    };
+    if config.strip_local_symbols {
+        if product.object.format() != object::BinaryFormat::Elf {
+            tcx.sess
+                .fatal("the `strip_local_symbols` option is only supported for ELF targets");
+        }
+        product.strip_local_symbol_names();
+    }
+    crate::backend::add_gnu_stack_note(&mut product.object);
+
+    let mut obj = product.object.write().unwrap_or_else(|err| {
+        tcx.sess.fatal(&format!(
+            "failed to write object file for `{}`: {}",
+            name, err
+        ))
+    });
+
+    // Move functions to the sections determined by `function_section_names`.
+    if !section_renames.is_empty() {
+        obj = crate::backend::rename_elf_sections(obj, |section_name| {
+            section_renames.get(section_name).cloned()
+        });
+    }
+
+    let tmp_file = write_object(tcx, config, &name, tmp_file, &mut obj);
+
+    compiled_module_result(tcx, config, name, kind, Some(tmp_file))
+}
+
+/// Write the object file `obj` of the module `name` to `tmp_file` or hand it to the object sink
+/// when one is set. Returns the path of the object file. Header fields which `object` can't set
+/// are patched in `obj` first.
+fn write_object(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    name: &str,
+    tmp_file: PathBuf,
+    obj: &mut [u8],
+) -> PathBuf {
+    if let Some(os_abi) = elf_os_abi(tcx, config) {
+        crate::backend::set_elf_os_abi(obj, os_abi);
+    }
+
+    if let Some(object_sink) = &config.object_sink {
+        return (object_sink.0)(name, obj);
+    }
+
+    if let Err(err) = std::fs::write(&tmp_file, obj) {
+        tcx.sess.fatal(&format!(
+            "error writing object file {}: {}",
+            tmp_file.display(),
+            err
+        ));
+    }
+    tmp_file
+}
+
+/// Create the [`ModuleCodegenResult`] for a module, given the path of its object file if it has
+/// one.
+fn compiled_module_result(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    name: String,
+    kind: ModuleKind,
+    tmp_file: Option<PathBuf>,
+) -> ModuleCodegenResult {
+    let work_product = if config.disable_incr_cache {
+        None
+    } else {
+        rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(tcx.sess, &name, &tmp_file)
+    };
+
+    ModuleCodegenResult(
+        CompiledModule {
+            name,
+            kind,
+            object: tmp_file,
+            dwarf_object: None,
+            bytecode: None,
+        },
+        work_product,
+        Vec::new(),
+    )
+}
+
+/// Write the disassembly for `--emit asm` and the Cranelift IR for `--emit llvm-ir` of a module
+/// next to its object file. They are combined into the requested output files by
+/// [`write_text_outputs`] once all modules are done. Cranelift can't produce assembly that can be
+/// assembled again, so the disassembly is only meant for inspection of the generated code.
+fn emit_text_outputs(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    name: &str,
+    asm_output: Option<String>,
+    ir_output: Option<String>,
+    global_asm: &str,
+) {
+    let outputs = [
+        (OutputType::Assembly, asm_output),
+        (OutputType::LlvmAssembly, ir_output),
+    ];
+    for (output_type, output) in outputs.iter() {
+        let output = match output {
+            Some(output) => output,
+            None => continue,
+        };
+        let path = temp_path(tcx, config, *output_type, name);
+        let res: std::io::Result<()> = try {
+            let mut file = std::fs::File::create(&path)?;
+            file.write_all(output.as_bytes())?;
+            if *output_type == OutputType::Assembly && !global_asm.is_empty() {
+                file.write_all(b"\n/* global_asm! */\n")?;
+                file.write_all(global_asm.as_bytes())?;
+            }
+        };
+        if let Err(err) = res {
+            tcx.sess.fatal(&format!(
+                "error writing output file {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+}
+
+/// Concatenates the `--emit asm` and `--emit llvm-ir` outputs written by [`emit_text_outputs`] for
+/// every module into the output files requested by the user. The files of the individual modules
+/// are removed unless `-Csave-temps` is passed.
+fn write_text_outputs(tcx: TyCtxt<'_>, config: &BackendConfig, modules: &[CompiledModule]) {
+    let outputs = tcx.output_filenames(LOCAL_CRATE);
+    for &output_type in &[OutputType::Assembly, OutputType::LlvmAssembly] {
+        if !tcx.sess.opts.output_types.contains_key(&output_type) {
+            continue;
+        }
+        let path = outputs.path(output_type);
+        let res: std::io::Result<()> = try {
+            let mut file = std::fs::File::create(&path)?;
+            for module in modules {
+                let module_path = temp_path(tcx, config, output_type, &module.name);
+                let contents = match std::fs::read(&module_path) {
+                    // Modules like the atomic mutex don't have any text output.
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    res => res?,
+                };
+                if output_type == OutputType::Assembly {
+                    writeln!(file, "/* module {} */", module.name)?;
+                } else {
+                    writeln!(file, "; module {}", module.name)?;
+                }
+                file.write_all(&contents)?;
+                writeln!(file)?;
+                if !tcx.sess.opts.cg.save_temps {
+                    std::fs::remove_file(&module_path)?;
+                }
+            }
+        };
+        if let Err(err) = res {
+            tcx.sess.fatal(&format!(
+                "error writing output file {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+}
+
+fn reuse_workproduct_for_cgu(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    cgu: &CodegenUnit<'_>,
+    work_products: &mut FxHashMap<WorkProductId, WorkProduct>,
+) -> Vec<CompiledModule> {
+    let module = reuse_workproduct(
+        tcx,
+        config,
+        cgu.work_product_id(),
+        cgu.work_product(tcx),
+        ModuleKind::Regular,
+        work_products,
+    );
+    if config.object_per_function {
+        unpack_function_objects(tcx, config, module)
+    } else {
+        vec![module]
+    }
+}
+
+fn reuse_workproduct(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    work_product_id: WorkProductId,
+    work_product: WorkProduct,
+    kind: ModuleKind,
+    work_products: &mut FxHashMap<WorkProductId, WorkProduct>,
+) -> CompiledModule {
+    let incr_comp_session_dir = tcx.sess.incr_comp_session_dir();
+    let mut object = None;
+    if let Some(saved_file) = &work_product.saved_file {
+        let obj_out = temp_path(tcx, config, OutputType::Object, &work_product.cgu_name);
+        object = Some(obj_out.clone());
+        let source_file = rustc_incremental::in_incr_comp_dir(&incr_comp_session_dir, &saved_file);
+        if let Err(err) = link_or_copy_with_retry(&source_file, &obj_out) {
+            tcx.sess.fatal(&format!(
+                "unable to copy {} to {}: {}",
+                source_file.display(),
+                obj_out.display(),
+                err
+            ));
+        }
+    }
+
+    let name = work_product.cgu_name.clone();
+    work_products.insert(work_product_id, work_product);
+
+    CompiledModule {
+        name,
+        kind,
+        object,
+        dwarf_object: None,
+        bytecode: None,
+    }
+}
+
+/// Hardlinking or copying a file can fail spuriously on network and overlay filesystems, so retry
+/// a couple of times before falling back to a plain copy.
+fn link_or_copy_with_retry(source: &Path, dest: &Path) -> std::io::Result<()> {
+    const ATTEMPTS: u32 = 3;
+
+    // Makes it possible to test the retry logic without a flaky filesystem.
+    let simulated_failures = std::env::var("CG_CLIF_TEST_FAILING_COPIES")
+        .ok()
+        .and_then(|failures| failures.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    for attempt in 0..ATTEMPTS {
+        let res = if attempt < simulated_failures {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "simulated failure",
+            ))
+        } else {
+            rustc_fs_util::link_or_copy(source, dest).map(|_| ())
+        };
+        match res {
+            Ok(()) => return Ok(()),
+            Err(_) if attempt + 1 < ATTEMPTS => {
+                std::thread::sleep(Duration::from_millis(10 << attempt))
+            }
+            Err(_) => {}
+        }
+    }
+
+    if simulated_failures > ATTEMPTS {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "simulated failure",
+        ));
+    }
+
+    // `link_or_copy` may leave a partially written file behind when copying failed.
+    let _ = std::fs::remove_file(dest);
+    std::fs::copy(source, dest).map(|_| ())
+}
+
+fn module_codegen(
+    tcx: TyCtxt<'_>,
+    (config, cgu_name, progress): (&BackendConfig, rustc_span::Symbol, Option<&Progress>),
+) -> ModuleCodegenResult {
+    let _cgu_guard = crate::PrintOnPanic(|| format!("while codegenning CGU {}", cgu_name));
+    let cgu = tcx.codegen_unit(cgu_name);
+    let mut mono_items = cgu.items_in_deterministic_order(tcx);
+    let mut local_functions = FxHashSet::default();
+    if config.object_per_function {
+        // Items local to the codegen unit are referenced from other object files now. Every
+        // codegen unit using a function like an `#[inline]` function has a local copy of it, so
+        // these copies are made weak to not clash with each other.
+        for (mono_item, linkage) in &mut mono_items {
+            if *linkage == (RLinkage::Internal, Visibility::Default) {
+                *linkage = (RLinkage::External, Visibility::Hidden);
+                if let MonoItem::Fn(_) = mono_item {
+                    local_functions.insert(*mono_item);
+                }
+            }
+        }
+    }
+
+    let mut module = new_module(tcx, config, cgu_name.as_str().to_string());
+
+    // Initialize the global atomic mutex using a constructor for proc-macros.
+    // FIXME implement atomic instructions in Cranelift.
+    let mut init_atomics_mutex_from_constructor = None;
+    if tcx
+        .sess
+        .crate_types()
+        .contains(&rustc_session::config::CrateType::ProcMacro)
+    {
+        if mono_items.iter().any(|(mono_item, _)| match mono_item {
+            rustc_middle::mir::mono::MonoItem::Static(def_id) => crate::linkage::symbol_name(
+                tcx,
+                config.symbol_prefix.as_deref(),
+                Instance::mono(tcx, *def_id),
+            )
+            .contains("__rustc_proc_macro_decls_"),
+            _ => false,
+        }) {
+            init_atomics_mutex_from_constructor =
+                Some(crate::atomic_shim::init_global_lock_constructor(
+                    &mut module,
+                    &format!("{}_init_atomics_mutex", cgu_name.as_str()),
+                ));
+        }
+    }
+
+    let function_sections = function_section_names(tcx, config, &mono_items);
+
+    // The object files of a codegen unit split by `object_per_function` are saved together by
+    // `save_function_objects` instead.
+    let config_without_incr_cache;
+    let emit_config = if config.object_per_function {
+        config_without_incr_cache = BackendConfig {
+            disable_incr_cache: true,
+            ..config.clone()
+        };
+        &config_without_incr_cache
+    } else {
+        config
+    };
+
+    // `#[used]` statics must not be removed by the linker even when they are never referenced.
+    // Neither must statics in init and fini sections, as the runtime calls the functions they
+    // point to.
+    let used_statics = mono_items
+        .iter()
+        .filter_map(|&(mono_item, _)| match mono_item {
+            rustc_middle::mir::mono::MonoItem::Static(def_id)
+                if tcx
+                    .codegen_fn_attrs(def_id)
+                    .flags
+                    .contains(CodegenFnAttrFlags::USED)
+                    || tcx.codegen_fn_attrs(def_id).link_section.map_or(
+                        false,
+                        |section_name| {
+                            crate::constant::is_init_fini_section(&section_name.as_str())
+                        },
+                    ) =>
+            {
+                Some(crate::linkage::symbol_name(
+                    tcx,
+                    config.symbol_prefix.as_deref(),
+                    Instance::mono(tcx, def_id),
+                ))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let (function_items, mono_items): (Vec<_>, Vec<_>) = if config.object_per_function {
+        mono_items
+            .into_iter()
+            .partition(|(mono_item, _)| matches!(mono_item, MonoItem::Fn(_)))
+    } else {
+        (Vec::new(), mono_items)
+    };
+
+    let mut cx = new_codegen_cx(tcx, config, module);
+    if config.function_cache && !config.disable_incr_cache {
+        cx.function_cache = crate::function_cache::FunctionCache::load(tcx, &cgu.name().as_str());
+    }
+    if config.codegen_roots.is_empty() {
+        super::predefine_mono_items(&mut cx, &mono_items);
+        for (mono_item, (linkage, visibility)) in mono_items {
+            let linkage = crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
+            super::codegen_mono_item(&mut cx, mono_item, linkage);
+            if let Some(progress) = progress {
+                progress.items_done(1);
+            }
+        }
+    } else {
+        codegen_reachable_mono_items(&mut cx, config, mono_items, progress);
+    }
+    let asm_output = cx.asm_output.take();
+    let ir_output = cx.ir_output.take();
+    let coverage = cx.coverage.take();
+    let function_cache = cx.function_cache.take();
+    let local_asm_symbols = std::mem::take(&mut cx.local_asm_symbols);
+    let (mut module, global_asm, debug, mut unwind_context, stats) =
+        tcx.sess.time("finalize CodegenCx", || cx.finalize());
+    let coverage_map = coverage.and_then(|coverage| {
+        crate::coverage::define_coverage_map(tcx, &mut module, &cgu.name().as_str(), coverage)
+    });
+    if let Some(function_cache) = function_cache {
+        function_cache.save(tcx);
+    }
+    if !config.disable_entry_wrapper {
+        crate::main_shim::maybe_create_entry_wrapper(
+            tcx,
+            &mut module,
+            config.symbol_prefix.as_deref(),
+            &mut unwind_context,
+            false,
+        );
+    }
+    let used_statics = used_statics
+        .iter()
+        .map(|name| match module.get_name(name) {
+            Some(cranelift_module::FuncOrDataId::Data(data_id)) => {
+                let decl = module.declarations().get_data_decl(data_id);
+                (data_id, decl.writable, decl.tls)
+            }
+            _ => unreachable!("`#[used]` static {} was not defined", name),
+        })
+        .collect::<Vec<_>>();
+
+    let mut codegen_result = emit_module(
+        tcx,
+        emit_config,
+        cgu.name().as_str().to_string(),
+        ModuleKind::Regular,
+        module,
+        debug,
+        unwind_context,
+        // The object file is still needed to link the global asm into.
+        global_asm.is_empty(),
+        &function_sections,
+        |mut product| {
+            if let Some(func_id) = init_atomics_mutex_from_constructor {
+                product.add_constructor(func_id);
+            }
+            for (data_id, writable, tls) in used_statics {
+                product.retain_data(data_id, writable, tls);
+            }
+            if let Some(data_id) = coverage_map {
+                product.retain_data(data_id, false, false);
+            }
+
+            product
+        },
+    );
+
+    codegen_global_asm(
+        tcx,
+        config,
+        &cgu.name().as_str(),
+        &global_asm,
+        &local_asm_symbols,
+    );
+
+    if super::should_display_cg_time() {
+        super::print_stats(
+            tcx,
+            &cgu.name().as_str(),
+            stats,
+            codegen_result.0.object.as_deref(),
+        );
+    }
+
+    emit_text_outputs(
+        tcx,
+        config,
+        &cgu.name().as_str(),
+        asm_output,
+        ir_output,
+        &global_asm,
+    );
+
+    if config.object_per_function {
+        codegen_result.2 = function_items
+            .into_iter()
+            .enumerate()
+            .map(|(i, (mono_item, linkage))| {
+                let name = format!("{}.fn{}", cgu.name().as_str(), i);
+                let module = codegen_function_object(
+                    tcx,
+                    emit_config,
+                    name,
+                    mono_item,
+                    linkage,
+                    local_functions.contains(&mono_item),
+                    &function_sections,
+                );
+                if let Some(progress) = progress {
+                    progress.items_done(1);
+                }
+                module
+            })
+            .collect();
+
+        if !config.disable_incr_cache {
+            codegen_result.1 = save_function_objects(
+                tcx,
+                config,
+                &cgu.name().as_str(),
+                &codegen_result.0,
+                &codegen_result.2,
+            );
+        }
+    }
+
+    codegen_result
+}
+
+/// Packs the object file of a codegen unit and those of its functions, which are emitted into
+/// object files of their own when the `object_per_function` option is enabled, into an archive.
+/// The archive is saved as the work product of the codegen unit, as a work product can only hold
+/// a single file. [`reuse_workproduct_for_cgu`] unpacks it again.
+fn save_function_objects(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    cgu_name: &str,
+    cgu_module: &CompiledModule,
+    function_modules: &[CompiledModule],
+) -> Option<(WorkProductId, WorkProduct)> {
+    let modules = std::iter::once(cgu_module)
+        .chain(function_modules)
+        .filter_map(|module| Some((&module.name, module.object.as_ref()?)))
+        .collect::<Vec<_>>();
+    if modules.is_empty() {
+        return rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
+            tcx.sess, cgu_name, &None,
+        );
+    }
+
+    let archive_path = temp_path(
+        tcx,
+        config,
+        OutputType::Object,
+        &format!("{}.functions", cgu_name),
+    );
+    let res: std::io::Result<()> = try {
+        let mut builder = ar::Builder::new(std::fs::File::create(&archive_path)?);
+        for (name, path) in modules {
+            let data = std::fs::read(path)?;
+            let header = ar::Header::new(name.as_bytes().to_vec(), data.len() as u64);
+            builder.append(&header, &mut &*data)?;
+        }
+    };
+    if let Err(err) = res {
+        tcx.sess.fatal(&format!(
+            "error writing archive {}: {}",
+            archive_path.display(),
+            err
+        ));
+    }
+
+    let work_product = rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
+        tcx.sess,
+        cgu_name,
+        &Some(archive_path.clone()),
+    );
+    if !tcx.sess.opts.cg.save_temps {
+        let _ = std::fs::remove_file(&archive_path);
+    }
+    work_product
+}
+
+/// Unpacks the archive saved by [`save_function_objects`] as the work product of a codegen unit
+/// into the object files of the codegen unit and its functions.
+fn unpack_function_objects(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    module: CompiledModule,
+) -> Vec<CompiledModule> {
+    let archive_path = match &module.object {
+        Some(archive_path) => archive_path,
+        None => return vec![module],
+    };
+    let res: std::io::Result<Vec<CompiledModule>> = try {
+        // The archive was copied to the path of the object file of the codegen unit, which is
+        // unpacked to the same path. It may also be hardlinked to the incremental cache, so it has
+        // to be removed rather than overwritten.
+        let data = std::fs::read(archive_path)?;
+        std::fs::remove_file(archive_path)?;
+        let mut archive = ar::Archive::new(&*data);
+        let mut modules = Vec::new();
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry?;
+            let name = String::from_utf8(entry.header().identifier().to_vec())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            let path = temp_path(tcx, config, OutputType::Object, &name);
+            std::io::copy(&mut entry, &mut std::fs::File::create(&path)?)?;
+            modules.push(CompiledModule {
+                name,
+                kind: ModuleKind::Regular,
+                object: Some(path),
+                dwarf_object: None,
+                bytecode: None,
+            });
+        }
+        modules
+    };
+    res.unwrap_or_else(|err| {
+        tcx.sess.fatal(&format!(
+            "error unpacking archive {}: {}",
+            archive_path.display(),
+            err
+        ))
+    })
+}
+
+fn new_codegen_cx<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    module: ObjectModule,
+) -> crate::CodegenCx<'tcx, ObjectModule> {
+    let mut cx = crate::CodegenCx::new(
+        tcx,
+        module,
+        tcx.sess.opts.debuginfo != DebugInfo::None,
+        true,
+    );
+    if config.coverage {
+        cx.coverage = Some(Default::default());
+    }
+    cx.verify_ir = config.verify_ir;
+    cx.max_function_size = config.max_function_size;
+    cx.intrinsic_overrides = config.intrinsic_overrides.iter().cloned().collect();
+    cx.mir_transform = config.mir_transform.clone();
+    cx.symbol_prefix = config.symbol_prefix.clone();
+    cx.test_break_ir = config.test_break_ir.clone();
+    cx.test_skip_definition = config.test_skip_definition.clone();
+    cx
+}
+
+/// Codegens the function `mono_item` into an object file of its own. Used when the
+/// `object_per_function` option is enabled. All other functions and data objects it references
+/// are imported from the other object files, except for constants, which are duplicated. When
+/// `weak` is true, the function is defined as weak symbol.
+fn codegen_function_object<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    name: String,
+    mono_item: MonoItem<'tcx>,
+    (linkage, visibility): (RLinkage, Visibility),
+    weak: bool,
+    function_sections: &FxHashMap<String, String>,
+) -> CompiledModule {
+    let mut cx = new_codegen_cx(tcx, config, new_module(tcx, config, name.clone()));
+    super::predefine_mono_items(&mut cx, &[(mono_item, (linkage, visibility))]);
+    let linkage = crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
+    super::codegen_mono_item(&mut cx, mono_item, linkage);
+
+    let asm_output = cx.asm_output.take();
+    let ir_output = cx.ir_output.take();
+    let coverage = cx.coverage.take();
+    let local_asm_symbols = std::mem::take(&mut cx.local_asm_symbols);
+    let (mut module, global_asm, debug, unwind_context, _stats) = cx.finalize();
+    let coverage_map = coverage.and_then(|coverage| {
+        crate::coverage::define_coverage_map(tcx, &mut module, &name, coverage)
+    });
+    let weak_func_id = match mono_item {
+        MonoItem::Fn(instance) if weak => {
+            let symbol_name =
+                crate::linkage::symbol_name(tcx, config.symbol_prefix.as_deref(), instance);
+            match module.get_name(&symbol_name) {
+                Some(cranelift_module::FuncOrDataId::Func(func_id)) => Some(func_id),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+
+    let ModuleCodegenResult(compiled_module, _, _) = emit_module(
+        tcx,
+        config,
+        name.clone(),
+        ModuleKind::Regular,
+        module,
+        debug,
+        unwind_context,
+        // Naked functions are defined using global asm.
+        global_asm.is_empty(),
+        function_sections,
+        |mut product| {
+            if let Some(data_id) = coverage_map {
+                product.retain_data(data_id, false, false);
+            }
+            if let Some(func_id) = weak_func_id {
+                product.make_function_weak(func_id);
+            }
+            product
+        },
+    );
+    codegen_global_asm(tcx, config, &name, &global_asm, &local_asm_symbols);
+    emit_text_outputs(tcx, config, &name, asm_output, ir_output, &global_asm);
+    compiled_module
+}
+
+/// Env var containing the name of the codegen unit to codegen, which is set for the child
+/// processes spawned by [`spawn_isolated_worker`].
+const ISOLATED_CGU_ENV_VAR: &str = "CG_CLIF_ISOLATED_CGU";
+
+/// A child process codegening a CGU, such that a crash of cg_clif or Cranelift only fails this CGU
+/// instead of taking down the whole compiler. Used when the `isolate_cgus` option is enabled.
+///
+/// The child is the compiler itself, invoked again with the arguments returned by
+/// [`isolated_worker_args`], which analyzes the crate once more and then only codegens the CGU
+/// given by [`ISOLATED_CGU_ENV_VAR`] before exiting. It writes the object file to the same location as [`module_codegen`] would. All other
+/// state changes of the child, including the dependencies it recorded in the dep graph, are lost.
+/// For this reason the codegen unit is not part of the dep graph and won't be reused by the next
+/// incremental session.
+struct IsolatedWorker {
+    child: std::process::Child,
+    cgu_name: rustc_span::Symbol,
+}
+
+fn isolated_worker_config(config: &BackendConfig) -> BackendConfig {
+    BackendConfig {
+        disable_incr_cache: true,
+        ..config.clone()
+    }
+}
+
+/// Returns the arguments for the child processes of [`spawn_isolated_worker`]. Incremental
+/// compilation is disabled, as the incremental session of the parent can't be shared, and lints
+/// are capped to avoid reporting all warnings once per codegen unit. The metadata and dep-info
+/// outputs and the artifact notifications are left to the parent, so the children don't write
+/// them again while the parent or another child may be writing or reading them.
+fn isolated_worker_args() -> Vec<std::ffi::OsString> {
+    let mut args = Vec::new();
+    let mut orig_args = std::env::args_os().skip(1);
+    while let Some(arg) = orig_args.next() {
+        let arg_str = arg.to_string_lossy().into_owned();
+        if arg_str == "-C" || arg_str == "--codegen" || arg_str == "--cap-lints" {
+            let value = orig_args.next().unwrap_or_default();
+            if arg_str != "--cap-lints" && !value.to_string_lossy().starts_with("incremental=") {
+                args.push(arg);
+                args.push(value);
+            }
+        } else if arg_str == "--emit" || arg_str == "--json" {
+            let value = orig_args.next().unwrap_or_default();
+            if let Some(value) = filter_worker_outputs(&arg_str, &value.to_string_lossy()) {
+                args.push(arg);
+                args.push(value.into());
+            }
+        } else if arg_str.starts_with("--emit=") || arg_str.starts_with("--json=") {
+            let (flag, value) = arg_str.split_once('=').unwrap();
+            if let Some(value) = filter_worker_outputs(flag, value) {
+                args.push(format!("{}={}", flag, value).into());
+            }
+        } else if !arg_str.starts_with("-Cincremental=")
+            && !arg_str.starts_with("--codegen=incremental=")
+            && !arg_str.starts_with("--cap-lints=")
+        {
+            args.push(arg);
+        }
+    }
+    args.push("--cap-lints=allow".into());
+    args
+}
+
+/// Removes the metadata and dep-info outputs from the value of an `--emit` argument and the
+/// artifact notifications from the value of a `--json` argument. Returns `None` if nothing
+/// remains, in which case the argument is dropped.
+fn filter_worker_outputs(flag: &str, value: &str) -> Option<String> {
+    let removed: &[&str] = if flag == "--emit" {
+        &["metadata", "dep-info"]
+    } else {
+        &["artifacts"]
+    };
+    let remaining = value
+        .split(',')
+        .filter(|kind| !removed.contains(&kind.split('=').next().unwrap()))
+        .collect::<Vec<_>>();
+    if remaining.is_empty() {
+        None
+    } else {
+        Some(remaining.join(","))
+    }
+}
+
+/// Spawns a child process codegening `cgu_name`. [`finish_isolated_worker`] must be called for the
+/// returned worker to get the codegened module.
+fn spawn_isolated_worker(tcx: TyCtxt<'_>, cgu_name: rustc_span::Symbol) -> IsolatedWorker {
+    let child = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(isolated_worker_args())
+            .env(ISOLATED_CGU_ENV_VAR, &*cgu_name.as_str())
+            .spawn()
+    });
+    match child {
+        Ok(child) => IsolatedWorker { child, cgu_name },
+        Err(err) => tcx
+            .sess
+            .fatal(&format!("failed to spawn codegen worker: {}", err)),
+    }
+}
+
+/// Codegens the codegen unit given by [`ISOLATED_CGU_ENV_VAR`] and exits. This is the part of
+/// [`run_aot`] running in the child processes spawned by [`spawn_isolated_worker`].
+fn run_isolated_worker(tcx: TyCtxt<'_>, config: &BackendConfig, cgu_name: &str) -> ! {
+    let cgu_name = rustc_span::Symbol::intern(cgu_name);
+    module_codegen(tcx, (&isolated_worker_config(config), cgu_name, None));
+    tcx.sess.abort_if_errors();
+    std::process::exit(0);
+}
+
+/// Waits for `worker` to exit and returns the module it codegened.
+fn finish_isolated_worker(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    worker: IsolatedWorker,
+    progress: Option<&Progress>,
+) -> ModuleCodegenResult {
+    let IsolatedWorker {
+        mut child,
+        cgu_name,
+    } = worker;
+
+    let status = child.wait().unwrap_or_else(|err| {
+        tcx.sess
+            .fatal(&format!("failed to wait for codegen worker: {}", err))
+    });
+    if !status.success() {
+        // Errors reported by the child have already been printed.
+        tcx.sess.err(&format!(
+            "codegen worker for codegen unit {} failed with {}",
+            cgu_name, status
+        ));
+        rustc_errors::FatalError.raise();
+    }
+
+    if let Some(progress) = progress {
+        progress.items_done(tcx.codegen_unit(cgu_name).items().len());
+    }
+
+    let name = cgu_name.as_str().to_string();
+    let tmp_file = temp_path(tcx, config, OutputType::Object, &name);
+    // No object file is written for empty codegen units.
+    let tmp_file = if tmp_file.exists() {
+        Some(tmp_file)
+    } else {
+        None
+    };
+    compiled_module_result(
+        tcx,
+        &isolated_worker_config(config),
+        name,
+        ModuleKind::Regular,
+        tmp_file,
+    )
+}
+
+/// Records the result of codegening a single codegen unit. Failed codegen units are reported
+/// together once all codegen units are done.
+fn record_cgu_result(
+    tcx: TyCtxt<'_>,
+    cgu_name: rustc_span::Symbol,
+    err_count: usize,
+    res: Result<ModuleCodegenResult, ()>,
+    modules: &mut Vec<CompiledModule>,
+    work_products: &mut FxHashMap<WorkProductId, WorkProduct>,
+    failed_cgus: &mut Vec<rustc_span::Symbol>,
+) {
+    if res.is_err() || tcx.sess.err_count() > err_count {
+        failed_cgus.push(cgu_name);
+    }
+    if let Ok(ModuleCodegenResult(module, work_product, function_modules)) = res {
+        if let Some((id, product)) = work_product {
+            work_products.insert(id, product);
+        }
+        modules.push(module);
+        modules.extend(function_modules);
+    }
+}
+
+pub(super) fn run_aot(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    metadata: EncodedMetadata,
+    need_metadata_module: bool,
+) -> Box<(CodegenResults, FxHashMap<WorkProductId, WorkProduct>)> {
+    if let Some(cgu_name) = std::env::var_os(ISOLATED_CGU_ENV_VAR) {
+        run_isolated_worker(tcx, config, &cgu_name.to_string_lossy());
+    }
+
+    let mut work_products = FxHashMap::default();
+
+    if config.object_per_function && config.isolate_cgus {
+        tcx.sess
+            .fatal("the `object_per_function` and `isolate_cgus` options can't be combined");
+    }
+    if config.max_parallel_cgus.is_some() && !config.isolate_cgus {
+        tcx.sess.warn(
+            "the `max_parallel_cgus` option is ignored without `isolate_cgus`, all codegen units \
+             are codegened one after another",
+        );
+    }
+    if config.compress_debug_sections {
+        check_compress_debug_sections(tcx);
+    }
+    // These options can only be set programmatically, so they were most likely meant to be used
+    // together with `CodegenMode::Jit`.
+    for (name, is_set) in [
+        ("jit_symbols", config.jit_symbols.is_some()),
+        ("jit_symbol_resolver", config.jit_symbol_resolver.is_some()),
+        ("jit_entry", config.jit_entry.is_some()),
+        ("jit_error_handler", config.jit_error_handler.is_some()),
+        ("jit_keep_module", config.jit_keep_module),
+    ]
+    .iter()
+    {
+        if *is_set {
+            tcx.sess
+                .warn(&format!("the `{}` option is ignored in AOT mode", name));
+        }
+    }
+    if config.object_per_function && !config.codegen_roots.is_empty() {
+        tcx.sess
+            .fatal("the `object_per_function` and `codegen_roots` options can't be combined");
+    }
+    // With `-Clink-dead-code` rustc collects every function as mono item and doesn't pass
+    // `--gc-sections` to the linker, so that unused functions end up in the linked binary. All
+    // collected items are always codegened, except when using `codegen_roots`.
+    if !config.codegen_roots.is_empty() && tcx.sess.link_dead_code() {
+        tcx.sess
+            .fatal("the `codegen_roots` option can't be combined with `-Clink-dead-code`");
+    }
+
+    // The incremental cache copies the object files from and to the filesystem. Whether a function
+    // is codegened when using `codegen_roots` isn't tracked by it either.
+    let config_without_incr_cache;
+    let config = if config.object_sink.is_some() || !config.codegen_roots.is_empty() {
+        // These options read the object files back.
+        if config.object_sink.is_some()
+            && (config.isolate_cgus
+                || config.build_id
+                || config.dump_symbols
+                || config.check_duplicate_symbols
+                || config.size_report)
+        {
+            tcx.sess.fatal(
+                "the `isolate_cgus`, `build_id`, `dump_symbols`, `check_duplicate_symbols` and \
+                 `size_report` options are not supported when object files are handed to an \
+                 object sink",
+            );
+        }
+        // These options write files of their own.
+        let output_types = &tcx.sess.opts.output_types;
+        if config.object_sink.is_some()
+            && (config.module_list.is_some()
+                || config.cgu_graph.is_some()
+                || config.function_cache
+                || output_types.contains_key(&OutputType::Assembly)
+                || output_types.contains_key(&OutputType::LlvmAssembly))
+        {
+            tcx.sess.fatal(
+                "the `module_list`, `cgu_graph` and `function_cache` options, `--emit asm` and \
+                 `--emit llvm-ir` are not supported when object files are handed to an object \
+                 sink",
+            );
+        }
+        config_without_incr_cache = BackendConfig {
+            disable_incr_cache: true,
+            ..config.clone()
+        };
+        &config_without_incr_cache
+    } else {
+        config
+    };
+
+    if config.disable_incr_cache && tcx.dep_graph.is_fully_enabled() {
+        tcx.sess.note_without_error(
+            "the incremental cache of rustc_codegen_cranelift is disabled, all codegen units will \
+             be recompiled",
+        );
+    }
+
+    if let Some(temp_dir) = &config.temp_dir {
+        if let Err(err) = std::fs::create_dir_all(temp_dir) {
+            tcx.sess.fatal(&format!(
+                "error creating temp dir {}: {}",
+                temp_dir.display(),
+                err
+            ));
+        }
+    }
+
+    let cgus = if tcx.sess.opts.output_types.should_codegen() {
+        super::collect_and_partition_mono_items(tcx)
+    } else {
+        // If only `--emit metadata` is used, we shouldn't perform any codegen.
+        // Also `tcx.collect_and_partition_mono_items` may panic in that case.
+        &[]
+    };
+
+    super::check_duplicate_mono_item_symbols(tcx, config.symbol_prefix.as_deref(), cgus);
+
+    // Whether a function is reachable from the roots is only known within a codegen unit.
+    if !config.codegen_roots.is_empty() && cgus.len() > 1 {
+        tcx.sess.fatal(
+            "the `codegen_roots` option requires a single codegen unit, use \
+             `-Ccodegen-units=1` without incremental compilation",
+        );
+    }
+
+    if let Some(cgu_graph) = &config.cgu_graph {
+        super::time(tcx, "write codegen unit graph", || {
+            crate::cgu_graph::write_cgu_graph(tcx, cgu_graph, cgus)
+        });
+    }
+
+    if let Some(symbol_map) = &config.symbol_map {
+        super::time(tcx, "write symbol map", || {
+            crate::cgu_graph::write_symbol_map(
+                tcx,
+                config.symbol_prefix.as_deref(),
+                symbol_map,
+                cgus,
+            )
+        });
+    }
+
+    if tcx.dep_graph.is_fully_enabled() {
+        for cgu in &*cgus {
+            tcx.ensure().codegen_unit(cgu.name());
+        }
+    }
+
+    let progress = Progress::new(tcx, config, cgus.iter().map(|cgu| cgu.items().len()).sum());
+    let mut failed_cgus = Vec::new();
+    let mut modules = Vec::new();
+    super::time(tcx, "codegen mono items", || {
+        let mut workers = std::collections::VecDeque::new();
+        let max_workers = config.max_parallel_cgus.unwrap_or(1) as usize;
+
+        for cgu in cgus {
+            let cgu_reuse = determine_cgu_reuse(tcx, config, cgu);
+            tcx.sess
+                .cgu_reuse_tracker
+                .set_actual_reuse(&cgu.name().as_str(), cgu_reuse);
+
+            match cgu_reuse {
+                _ if config.disable_incr_cache => {}
+                CguReuse::No => {}
+                CguReuse::PreLto => {
+                    if let Some(progress) = &progress {
+                        progress.items_done(cgu.items().len());
+                    }
+                    modules.extend(reuse_workproduct_for_cgu(
+                        tcx,
+                        config,
+                        &*cgu,
+                        &mut work_products,
+                    ));
+                    continue;
+                }
+                CguReuse::PostLto => unreachable!(),
+            }
+
+            if config.isolate_cgus {
+                // Workers are waited for in the order they were spawned.
+                if workers.len() >= max_workers {
+                    let worker: IsolatedWorker = workers.pop_front().unwrap();
+                    let cgu_name = worker.cgu_name;
+                    let err_count = tcx.sess.err_count();
+                    let res = catch_fatal_error(|| {
+                        finish_isolated_worker(tcx, config, worker, progress.as_ref())
+                    });
+                    record_cgu_result(
+                        tcx,
+                        cgu_name,
+                        err_count,
+                        res,
+                        &mut modules,
+                        &mut work_products,
+                        &mut failed_cgus,
+                    );
+                }
+                workers.push_back(spawn_isolated_worker(tcx, cgu.name()));
+                continue;
+            }
+
+            let dep_node = cgu.codegen_dep_node(tcx);
+            let codegen_cgu = || {
+                tcx.dep_graph
+                    .with_task(
+                        dep_node,
+                        tcx,
+                        (config, cgu.name(), progress.as_ref()),
+                        module_codegen,
+                        rustc_middle::dep_graph::hash_result,
+                    )
+                    .0
+            };
+            let err_count = tcx.sess.err_count();
+            let res = if config.keep_going {
+                catch_fatal_error(codegen_cgu)
+            } else {
+                Ok(codegen_cgu())
+            };
+            record_cgu_result(
+                tcx,
+                cgu.name(),
+                err_count,
+                res,
+                &mut modules,
+                &mut work_products,
+                &mut failed_cgus,
+            );
+        }
+
+        for worker in workers {
+            let cgu_name = worker.cgu_name;
+            let err_count = tcx.sess.err_count();
+            let res = catch_fatal_error(|| {
+                finish_isolated_worker(tcx, config, worker, progress.as_ref())
+            });
+            record_cgu_result(
+                tcx,
+                cgu_name,
+                err_count,
+                res,
+                &mut modules,
+                &mut work_products,
+                &mut failed_cgus,
+            );
+        }
+    });
+
+    if (config.keep_going || config.isolate_cgus) && !failed_cgus.is_empty() {
+        tcx.sess.err(&format!(
+            "failed to codegen {} of {} codegen units: {}",
+            failed_cgus.len(),
+            cgus.len(),
+            failed_cgus
+                .iter()
+                .map(|cgu_name| cgu_name.as_str().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+
+    tcx.sess.abort_if_errors();
+
+    let allocator_module = codegen_allocator_module(tcx, config, &mut work_products);
+
+    if !config.custom_sections.is_empty() {
+        modules.push(super::time(tcx, "codegen custom sections", || {
+            codegen_custom_sections_module(tcx, config)
+        }));
+    }
+
+    if needs_atomic_mutex_module(tcx) {
+        modules.push(super::time(tcx, "codegen atomic mutex", || {
+            codegen_atomic_mutex_module(tcx, config)
+        }));
+    }
+
+    if config.crate_hash_note {
+        modules.push(super::time(tcx, "codegen crate hash note", || {
+            codegen_crate_hash_module(tcx, config)
+        }));
+    }
+
+    // Every object file of an rlib or staticlib ends up in the artifact linking it, which may only
+    // have a single build-id.
+    let is_linked_artifact = !tcx.sess.crate_types().iter().any(|crate_type| {
+        matches!(
+            crate_type,
+            rustc_session::config::CrateType::Rlib | rustc_session::config::CrateType::Staticlib
+        )
+    });
+    if config.build_id && !is_linked_artifact {
+        tcx.sess
+            .warn("the `build_id` option is ignored for rlibs and staticlibs");
+    } else if config.build_id {
+        let build_id_module = super::time(tcx, "codegen build-id", || {
+            codegen_build_id_module(tcx, config, modules.iter().chain(allocator_module.iter()))
+        });
+        modules.push(build_id_module);
+    }
+
+    // The order of the modules determines the order of the object files on the linker command
+    // line, which can influence symbol resolution. Sort them by name to keep the order stable,
+    // independent of the order in which codegen units are partitioned or codegened.
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+
+    write_text_outputs(tcx, config, &modules);
+
+    let metadata_module = if need_metadata_module {
+        Some(super::time(tcx, "codegen crate metadata", || {
+            codegen_metadata_module(tcx, config, &metadata, &mut work_products)
+        }))
+    } else {
+        None
+    };
+
+    if config.check_duplicate_symbols {
+        super::time(tcx, "check for duplicate symbols", || {
+            crate::symbol_dump::check_duplicate_symbols(
+                tcx,
+                modules
+                    .iter()
+                    .chain(allocator_module.iter())
+                    .chain(metadata_module.iter()),
+            )
+        });
+    }
+
+    if config.dump_symbols {
+        super::time(tcx, "dump symbol tables", || {
+            crate::symbol_dump::write_symbol_tables(
+                tcx,
+                modules
+                    .iter()
+                    .chain(allocator_module.iter())
+                    .chain(metadata_module.iter()),
+            )
+        });
+    }
+
+    if config.size_report {
+        super::time(tcx, "write size report", || {
+            crate::size_report::write_size_report(
+                tcx,
+                modules
+                    .iter()
+                    .chain(allocator_module.iter())
+                    .chain(metadata_module.iter()),
+            )
+        });
+    }
+
+    if let Some(module_list) = &config.module_list {
+        super::time(tcx, "write module list", || {
+            write_module_list(
+                tcx,
+                module_list,
+                modules
+                    .iter()
+                    .chain(allocator_module.iter())
+                    .chain(metadata_module.iter()),
+            )
+        });
+    }
+
+    if config.exports_file.is_some() || config.version_script.is_some() {
+        super::time(tcx, "write exported symbols", || {
+            let exported_symbols = exported_symbols(tcx, config.symbol_prefix.as_deref());
+            if let Some(exports_file) = &config.exports_file {
+                write_exports(tcx, exports_file, &exported_symbols, false);
+            }
+            if let Some(version_script) = &config.version_script {
+                write_exports(tcx, version_script, &exported_symbols, true);
+            }
+        });
+    }
+
+    Box::new((
+        CodegenResults {
+            crate_name: tcx.crate_name(LOCAL_CRATE),
+            modules,
+            allocator_module,
+            metadata_module,
+            metadata,
+            windows_subsystem: None, // Windows is not yet supported
+            linker_info: LinkerInfo::new(tcx),
+            // Contains the native libraries of the crate and its dependencies, including static
+            // libraries declared using `#[link(kind = "static")]`, which rustc passes to the
+            // linker.
+            crate_info: CrateInfo::new(tcx),
+        },
+        work_products,
+    ))
+}
+
+/// Write the object file of every module to `path`, one per line prefixed by the kind of the module
+/// (`regular`, `metadata` or `allocator`) and a tab. Modules without object file are skipped.
+fn write_module_list<'a>(
+    tcx: TyCtxt<'_>,
+    path: &Path,
+    modules: impl Iterator<Item = &'a CompiledModule>,
+) {
+    let mut module_list = String::new();
+    for module in modules {
+        let kind = match module.kind {
+            ModuleKind::Regular => "regular",
+            ModuleKind::Metadata => "metadata",
+            ModuleKind::Allocator => "allocator",
+        };
+        if let Some(object) = &module.object {
+            module_list.push_str(&format!("{}\t{}\n", kind, object.display()));
+        }
+    }
+    if let Err(err) = std::fs::write(path, module_list) {
+        tcx.sess.fatal(&format!(
+            "error writing module list {}: {}",
+            path.display(),
+            err
+        ));
+    }
+}
+
+/// Returns the sorted names of all symbols rustc would export from the linked artifact: the
+/// symbols exported by the local crate at the export level of the crate types being built, which
+/// includes the metadata symbol and the allocator shim of a dylib, and those of every upstream
+/// crate which is linked statically.
+fn exported_symbols(tcx: TyCtxt<'_>, symbol_prefix: Option<&str>) -> Vec<String> {
+    if let Some(exports) = &tcx.sess.target.override_export_symbols {
+        return exports.clone();
+    }
+
+    let threshold = symbol_export::crates_export_threshold(&tcx.sess.crate_types());
+
+    let mut exported_symbols = Vec::new();
+    for &(symbol, level) in tcx.exported_symbols(LOCAL_CRATE).iter() {
+        if !level.is_below_threshold(threshold) {
+            continue;
+        }
+        exported_symbols.push(match symbol {
+            // Use the same name as the definition, which may have the symbol prefix applied.
+            ExportedSymbol::NonGeneric(def_id) => {
+                crate::linkage::symbol_name(tcx, symbol_prefix, Instance::mono(tcx, def_id))
+            }
+            ExportedSymbol::Generic(def_id, substs) => {
+                crate::linkage::symbol_name(tcx, symbol_prefix, Instance::new(def_id, substs))
+            }
+            _ => symbol_export::symbol_name_for_instance_in_crate(tcx, symbol, LOCAL_CRATE),
+        });
+    }
+
+    for (crate_type, deps) in tcx.dependency_formats(LOCAL_CRATE).iter() {
+        if !tcx.sess.crate_types().contains(crate_type) {
+            continue;
+        }
+        for (index, dep_format) in deps.iter().enumerate() {
+            let cnum = CrateNum::new(index + 1);
+            if *dep_format != DependencyLinkage::Static {
+                continue;
+            }
+            for &(symbol, level) in tcx.exported_symbols(cnum).iter() {
+                if level.is_below_threshold(threshold) {
+                    exported_symbols.push(symbol_export::symbol_name_for_instance_in_crate(
+                        tcx, symbol, cnum,
+                    ));
+                }
+            }
+        }
+    }
+
+    exported_symbols.sort();
+    exported_symbols.dedup();
+    exported_symbols
+}
+
+/// Write `exported_symbols` to `path`, either one per line or as linker version script.
+fn write_exports(tcx: TyCtxt<'_>, path: &Path, exported_symbols: &[String], version_script: bool) {
+    let mut exports = String::new();
+    if version_script {
+        exports.push_str("{\n");
+        if !exported_symbols.is_empty() {
+            exports.push_str("  global:\n");
+            for symbol in exported_symbols {
+                exports.push_str(&format!("    {};\n", symbol));
+            }
+        }
+        exports.push_str("  local:\n    *;\n};\n");
+    } else {
+        for symbol in exported_symbols {
+            exports.push_str(symbol);
+            exports.push('\n');
+        }
+    }
+    if let Err(err) = std::fs::write(path, exports) {
+        tcx.sess.fatal(&format!(
+            "error writing exported symbols {}: {}",
+            path.display(),
+            err
+        ));
+    }
+}
+
+/// Runs `f`, turning a fatal error reported while running it into `Err`. Other panics, like ICEs,
+/// are propagated.
+fn catch_fatal_error<R>(f: impl FnOnce() -> R) -> Result<R, ()> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        if !payload.is::<rustc_errors::FatalErrorMarker>() {
+            std::panic::resume_unwind(payload);
+        }
+    })
+}
+
+fn codegen_allocator_module(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    work_products: &mut FxHashMap<WorkProductId, WorkProduct>,
+) -> Option<CompiledModule> {
+    if config.disable_allocator_shim {
+        return None;
+    }
+    let kind = crate::allocator::shim_kind(tcx)?;
+    let small = crate::allocator::use_small_shim(tcx, config);
+
+    // The allocator shim only depends on the allocator kind, whether the small variant is used and
+    // the options affecting codegen. Unlike codegen units it isn't tracked by the dep graph, so a
+    // hash of them is included in the name of the work product to check if the cached allocator
+    // shim of the previous session can be reused.
+    let name = "allocator_shim".to_string();
+    let shim_hash: Fingerprint = {
+        let mut hasher = StableHasher::new();
+        match kind {
+            AllocatorKind::Global => "global",
+            AllocatorKind::Default => "default",
+        }
+        .hash(&mut hasher);
+        small.hash(&mut hasher);
+        tcx.sess.opts.dep_tracking_hash().hash(&mut hasher);
+        config.elf_e_flags.hash(&mut hasher);
+        config.elf_os_abi.hash(&mut hasher);
+        hasher.finish()
+    };
+    let work_product_id = WorkProductId::from_cgu_name(&name);
+    let work_product_name = format!("{}-{}", name, shim_hash);
 
-    let tmp_file = tcx
-        .output_filenames(LOCAL_CRATE)
-        .temp_path(OutputType::Object, Some(&name));
-    let obj = product.object.write().unwrap();
-    if let Err(err) = std::fs::write(&tmp_file, obj) {
-        tcx.sess
-            .fatal(&format!("error writing object file: {}", err));
+    if tcx.dep_graph.is_fully_enabled() && !config.disable_incr_cache {
+        if let Some(work_product) = tcx.dep_graph.previous_work_product(&work_product_id) {
+            if work_product.saved_file.is_some() && work_product.cgu_name == work_product_name {
+                return Some(reuse_workproduct(
+                    tcx,
+                    config,
+                    work_product_id,
+                    work_product,
+                    ModuleKind::Allocator,
+                    work_products,
+                ));
+            }
+            // The saved file of the outdated work product won't be garbage collected by rustc as
+            // the new work product uses the same id.
+            rustc_incremental::delete_workproduct_files(tcx.sess, &work_product);
+        }
     }
 
-    let work_product = if std::env::var("CG_CLIF_INCR_CACHE_DISABLED").is_ok() {
-        None
-    } else {
-        rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
-            tcx.sess,
-            &name,
-            &Some(tmp_file.clone()),
-        )
-    };
+    let module = super::time(tcx, "codegen allocator shim", || {
+        let mut allocator_module = new_module(tcx, config, work_product_name.clone());
+        let mut allocator_unwind_context = UnwindContext::new(tcx, allocator_module.isa(), true);
+        let created_alloc_shim = crate::allocator::codegen(
+            tcx,
+            &mut allocator_module,
+            &mut allocator_unwind_context,
+            small,
+        );
+        assert!(created_alloc_shim);
 
-    ModuleCodegenResult(
-        CompiledModule {
-            name,
-            kind,
-            object: Some(tmp_file),
-            dwarf_object: None,
-            bytecode: None,
-        },
-        work_product,
-    )
+        let ModuleCodegenResult(module, work_product, _) = emit_module(
+            tcx,
+            config,
+            work_product_name,
+            ModuleKind::Allocator,
+            allocator_module,
+            None,
+            allocator_unwind_context,
+            false,
+            &FxHashMap::default(),
+            |product| product,
+        );
+        if let Some((_, work_product)) = work_product {
+            work_products.insert(work_product_id, work_product);
+        }
+        module
+    });
+    Some(module)
 }
 
-fn reuse_workproduct_for_cgu(
+fn codegen_metadata_module(
     tcx: TyCtxt<'_>,
-    cgu: &CodegenUnit<'_>,
+    config: &BackendConfig,
+    metadata: &EncodedMetadata,
     work_products: &mut FxHashMap<WorkProductId, WorkProduct>,
 ) -> CompiledModule {
-    let incr_comp_session_dir = tcx.sess.incr_comp_session_dir();
-    let mut object = None;
-    let work_product = cgu.work_product(tcx);
-    if let Some(saved_file) = &work_product.saved_file {
-        let obj_out = tcx
-            .output_filenames(LOCAL_CRATE)
-            .temp_path(OutputType::Object, Some(&cgu.name().as_str()));
-        object = Some(obj_out.clone());
-        let source_file = rustc_incremental::in_incr_comp_dir(&incr_comp_session_dir, &saved_file);
-        if let Err(err) = rustc_fs_util::link_or_copy(&source_file, &obj_out) {
-            tcx.sess.err(&format!(
-                "unable to copy {} to {}: {}",
-                source_file.display(),
-                obj_out.display(),
-                err
-            ));
+    use rustc_middle::mir::mono::CodegenUnitNameBuilder;
+
+    let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
+    let metadata_cgu_name = cgu_name_builder
+        .build_cgu_name(LOCAL_CRATE, &["crate"], Some("metadata"))
+        .as_str()
+        .to_string();
+    let tmp_file = temp_path(tcx, config, OutputType::Metadata, &metadata_cgu_name);
+
+    // The metadata object only depends on the encoded metadata and the object file flags. Including
+    // a hash of them in the name of the work product makes it possible to check if the cached
+    // metadata object of the previous session can be reused.
+    let metadata_hash: Fingerprint = {
+        let mut hasher = StableHasher::new();
+        metadata.raw_data.hash(&mut hasher);
+        config.elf_e_flags.hash(&mut hasher);
+        config.elf_os_abi.hash(&mut hasher);
+        hasher.finish()
+    };
+    let work_product_id = WorkProductId::from_cgu_name(&metadata_cgu_name);
+    let work_product_name = format!("{}-{}", metadata_cgu_name, metadata_hash);
+
+    if tcx.dep_graph.is_fully_enabled() && !config.disable_incr_cache {
+        if let Some(work_product) = tcx.dep_graph.previous_work_product(&work_product_id) {
+            match &work_product.saved_file {
+                Some(saved_file) if work_product.cgu_name == work_product_name => {
+                    let source_file = rustc_incremental::in_incr_comp_dir(
+                        &tcx.sess.incr_comp_session_dir(),
+                        saved_file,
+                    );
+                    if let Err(err) = link_or_copy_with_retry(&source_file, &tmp_file) {
+                        tcx.sess.fatal(&format!(
+                            "unable to copy {} to {}: {}",
+                            source_file.display(),
+                            tmp_file.display(),
+                            err
+                        ));
+                    }
+                    work_products.insert(work_product_id, work_product);
+
+                    return CompiledModule {
+                        name: metadata_cgu_name,
+                        kind: ModuleKind::Metadata,
+                        object: Some(tmp_file),
+                        dwarf_object: None,
+                        bytecode: None,
+                    };
+                }
+                // The saved file of the outdated work product won't be garbage collected by rustc
+                // as the new work product uses the same id.
+                _ => rustc_incremental::delete_workproduct_files(tcx.sess, &work_product),
+            }
         }
     }
 
-    work_products.insert(cgu.work_product_id(), work_product);
+    let tmp_file = super::time(tcx, "write compressed metadata", || {
+        let mut obj = crate::backend::with_object(tcx.sess, &metadata_cgu_name, |object| {
+            set_file_flags(tcx, config, object);
+            crate::metadata::write_metadata(tcx, object);
+        });
+
+        write_object(tcx, config, &metadata_cgu_name, tmp_file, &mut obj)
+    });
+
+    if !config.disable_incr_cache {
+        if let Some((_, work_product)) =
+            rustc_incremental::copy_cgu_workproduct_to_incr_comp_cache_dir(
+                tcx.sess,
+                &work_product_name,
+                &Some(tmp_file.clone()),
+            )
+        {
+            work_products.insert(work_product_id, work_product);
+        }
+    }
 
     CompiledModule {
-        name: cgu.name().to_string(),
-        kind: ModuleKind::Regular,
-        object,
+        name: metadata_cgu_name,
+        kind: ModuleKind::Metadata,
+        object: Some(tmp_file),
         dwarf_object: None,
         bytecode: None,
     }
 }
 
-fn module_codegen(tcx: TyCtxt<'_>, cgu_name: rustc_span::Symbol) -> ModuleCodegenResult {
-    let cgu = tcx.codegen_unit(cgu_name);
-    let mono_items = cgu.items_in_deterministic_order(tcx);
+/// Writes the sections of `config.custom_sections` to an object file. They are not part of any
+/// regular codegen unit, so they are only emitted once per crate.
+fn codegen_custom_sections_module(tcx: TyCtxt<'_>, config: &BackendConfig) -> CompiledModule {
+    use rustc_middle::mir::mono::CodegenUnitNameBuilder;
 
-    let mut module = new_module(tcx, cgu_name.as_str().to_string());
+    let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
+    let custom_sections_cgu_name = cgu_name_builder
+        .build_cgu_name(LOCAL_CRATE, &["crate"], Some("custom_sections"))
+        .as_str()
+        .to_string();
+    let tmp_file = temp_path(tcx, config, OutputType::Object, &custom_sections_cgu_name);
 
-    // Initialize the global atomic mutex using a constructor for proc-macros.
-    // FIXME implement atomic instructions in Cranelift.
-    let mut init_atomics_mutex_from_constructor = None;
-    if tcx
-        .sess
-        .crate_types()
-        .contains(&rustc_session::config::CrateType::ProcMacro)
-    {
-        if mono_items.iter().any(|(mono_item, _)| match mono_item {
-            rustc_middle::mir::mono::MonoItem::Static(def_id) => tcx
-                .symbol_name(Instance::mono(tcx, *def_id))
-                .name
-                .contains("__rustc_proc_macro_decls_"),
-            _ => false,
-        }) {
-            init_atomics_mutex_from_constructor =
-                Some(crate::atomic_shim::init_global_lock_constructor(
-                    &mut module,
-                    &format!("{}_init_atomics_mutex", cgu_name.as_str()),
-                ));
+    let mut obj = crate::backend::with_object(tcx.sess, &custom_sections_cgu_name, |object| {
+        set_file_flags(tcx, config, object);
+        for (section_name, data) in &config.custom_sections {
+            crate::backend::add_custom_section(object, section_name, data);
         }
+    });
+    let tmp_file = write_object(tcx, config, &custom_sections_cgu_name, tmp_file, &mut obj);
+
+    CompiledModule {
+        name: custom_sections_cgu_name,
+        kind: ModuleKind::Regular,
+        object: Some(tmp_file),
+        dwarf_object: None,
+        bytecode: None,
     }
+}
 
-    let mut cx = crate::CodegenCx::new(
-        tcx,
-        module,
-        tcx.sess.opts.debuginfo != DebugInfo::None,
-        true,
+/// Returns whether the global lock used to emulate atomics has to be defined by a module of its
+/// own, see [`codegen_atomic_mutex_module`].
+fn needs_atomic_mutex_module(tcx: TyCtxt<'_>) -> bool {
+    use rustc_session::config::CrateType;
+
+    let crate_types = tcx.sess.crate_types();
+    // The entry wrapper of executables and the codegen unit registering the proc macros of proc
+    // macro crates define the lock. All object files are linked into every crate type, so
+    // defining it here too would result in a duplicate definition.
+    crate_types
+        .iter()
+        .any(|crate_type| matches!(crate_type, CrateType::Cdylib | CrateType::Staticlib))
+        && !crate_types
+            .iter()
+            .any(|crate_type| matches!(crate_type, CrateType::Executable | CrateType::ProcMacro))
+}
+
+/// Create an object file defining the global lock used to emulate atomics together with a
+/// constructor initializing it. A `cdylib` or `staticlib` is loaded into or linked into programs
+/// which don't define the lock, while the code of the crate and its dependencies uses it.
+fn codegen_atomic_mutex_module(tcx: TyCtxt<'_>, config: &BackendConfig) -> CompiledModule {
+    use rustc_middle::mir::mono::CodegenUnitNameBuilder;
+
+    let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
+    let atomic_mutex_cgu_name = cgu_name_builder
+        .build_cgu_name(LOCAL_CRATE, &["crate"], Some("atomic_mutex"))
+        .as_str()
+        .to_string();
+
+    let mut module = new_module(tcx, config, atomic_mutex_cgu_name.clone());
+    let init_func_id = crate::atomic_shim::init_global_lock_constructor(
+        &mut module,
+        &format!("{}_init_atomics_mutex", atomic_mutex_cgu_name),
     );
-    super::predefine_mono_items(&mut cx, &mono_items);
-    for (mono_item, (linkage, visibility)) in mono_items {
-        let linkage = crate::linkage::get_clif_linkage(mono_item, linkage, visibility);
-        match mono_item {
-            MonoItem::Fn(inst) => {
-                cx.tcx.sess.time("codegen fn", || {
-                    crate::base::codegen_fn(&mut cx, inst, linkage)
-                });
-            }
-            MonoItem::Static(def_id) => {
-                crate::constant::codegen_static(&mut cx.constants_cx, def_id)
-            }
-            MonoItem::GlobalAsm(item_id) => {
-                let item = cx.tcx.hir().item(item_id);
-                if let rustc_hir::ItemKind::GlobalAsm(rustc_hir::GlobalAsm { asm }) = item.kind {
-                    cx.global_asm.push_str(&*asm.as_str());
-                    cx.global_asm.push_str("\n\n");
-                } else {
-                    bug!("Expected GlobalAsm found {:?}", item);
-                }
-            }
-        }
-    }
-    let (mut module, global_asm, debug, mut unwind_context) =
-        tcx.sess.time("finalize CodegenCx", || cx.finalize());
-    crate::main_shim::maybe_create_entry_wrapper(tcx, &mut module, &mut unwind_context, false);
+    let unwind_context = UnwindContext::new(tcx, module.isa(), true);
 
-    let codegen_result = emit_module(
+    let ModuleCodegenResult(module, _, _) = emit_module(
         tcx,
-        cgu.name().as_str().to_string(),
+        config,
+        atomic_mutex_cgu_name,
         ModuleKind::Regular,
         module,
-        debug,
+        None,
         unwind_context,
+        false,
+        &FxHashMap::default(),
         |mut product| {
-            if let Some(func_id) = init_atomics_mutex_from_constructor {
-                product.add_constructor(func_id);
-            }
-
+            product.add_constructor(init_func_id);
             product
         },
     );
-
-    codegen_global_asm(tcx, &cgu.name().as_str(), &global_asm);
-
-    codegen_result
+    module
 }
 
-pub(super) fn run_aot(
-    tcx: TyCtxt<'_>,
-    metadata: EncodedMetadata,
-    need_metadata_module: bool,
-) -> Box<(CodegenResults, FxHashMap<WorkProductId, WorkProduct>)> {
-    let mut work_products = FxHashMap::default();
-
-    let cgus = if tcx.sess.opts.output_types.should_codegen() {
-        tcx.collect_and_partition_mono_items(LOCAL_CRATE).1
-    } else {
-        // If only `--emit metadata` is used, we shouldn't perform any codegen.
-        // Also `tcx.collect_and_partition_mono_items` may panic in that case.
-        &[]
-    };
+/// Create an object file containing a `.note.rustc.crate-hash` note with the crate hash of the
+/// local crate, which identifies the source and the compiler flags it was compiled with.
+fn codegen_crate_hash_module(tcx: TyCtxt<'_>, config: &BackendConfig) -> CompiledModule {
+    use rustc_middle::mir::mono::CodegenUnitNameBuilder;
 
-    if tcx.dep_graph.is_fully_enabled() {
-        for cgu in &*cgus {
-            tcx.ensure().codegen_unit(cgu.name());
-        }
+    if crate::target_triple(tcx.sess).binary_format != target_lexicon::BinaryFormat::Elf {
+        tcx.sess
+            .fatal("the `crate_hash_note` option is only supported for ELF targets");
     }
 
-    let modules = super::time(tcx, "codegen mono items", || {
-        cgus.iter()
-            .map(|cgu| {
-                let cgu_reuse = determine_cgu_reuse(tcx, cgu);
-                tcx.sess
-                    .cgu_reuse_tracker
-                    .set_actual_reuse(&cgu.name().as_str(), cgu_reuse);
-
-                match cgu_reuse {
-                    _ if std::env::var("CG_CLIF_INCR_CACHE_DISABLED").is_ok() => {}
-                    CguReuse::No => {}
-                    CguReuse::PreLto => {
-                        return reuse_workproduct_for_cgu(tcx, &*cgu, &mut work_products);
-                    }
-                    CguReuse::PostLto => unreachable!(),
-                }
-
-                let dep_node = cgu.codegen_dep_node(tcx);
-                let (ModuleCodegenResult(module, work_product), _) = tcx.dep_graph.with_task(
-                    dep_node,
-                    tcx,
-                    cgu.name(),
-                    module_codegen,
-                    rustc_middle::dep_graph::hash_result,
-                );
-
-                if let Some((id, product)) = work_product {
-                    work_products.insert(id, product);
-                }
+    let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
+    let crate_hash_cgu_name = cgu_name_builder
+        .build_cgu_name(LOCAL_CRATE, &["crate"], Some("crate_hash"))
+        .as_str()
+        .to_string();
+    let tmp_file = temp_path(tcx, config, OutputType::Object, &crate_hash_cgu_name);
 
-                module
-            })
-            .collect::<Vec<_>>()
+    let mut obj = crate::backend::with_object(tcx.sess, &crate_hash_cgu_name, |object| {
+        set_file_flags(tcx, config, object);
+        crate::backend::add_crate_hash_note(tcx.sess, object, tcx.crate_hash(LOCAL_CRATE).as_u64());
     });
+    let tmp_file = write_object(tcx, config, &crate_hash_cgu_name, tmp_file, &mut obj);
 
-    tcx.sess.abort_if_errors();
-
-    let mut allocator_module = new_module(tcx, "allocator_shim".to_string());
-    let mut allocator_unwind_context = UnwindContext::new(tcx, allocator_module.isa(), true);
-    let created_alloc_shim =
-        crate::allocator::codegen(tcx, &mut allocator_module, &mut allocator_unwind_context);
+    CompiledModule {
+        name: crate_hash_cgu_name,
+        kind: ModuleKind::Regular,
+        object: Some(tmp_file),
+        dwarf_object: None,
+        bytecode: None,
+    }
+}
 
-    let allocator_module = if created_alloc_shim {
-        let ModuleCodegenResult(module, work_product) = emit_module(
-            tcx,
-            "allocator_shim".to_string(),
-            ModuleKind::Allocator,
-            allocator_module,
-            None,
-            allocator_unwind_context,
-            |product| product,
-        );
-        if let Some((id, product)) = work_product {
-            work_products.insert(id, product);
-        }
-        Some(module)
-    } else {
-        None
-    };
+/// Create an object file containing a `.note.gnu.build-id` note with a hash of the contents of all
+/// object files in `modules`. The build-id is the same across identical builds.
+fn codegen_build_id_module<'a>(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    modules: impl Iterator<Item = &'a CompiledModule>,
+) -> CompiledModule {
+    use rustc_middle::mir::mono::CodegenUnitNameBuilder;
 
-    let metadata_module = if need_metadata_module {
-        let _timer = tcx.prof.generic_activity("codegen crate metadata");
-        let (metadata_cgu_name, tmp_file) = tcx.sess.time("write compressed metadata", || {
-            use rustc_middle::mir::mono::CodegenUnitNameBuilder;
-
-            let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
-            let metadata_cgu_name = cgu_name_builder
-                .build_cgu_name(LOCAL_CRATE, &["crate"], Some("metadata"))
-                .as_str()
-                .to_string();
-
-            let tmp_file = tcx
-                .output_filenames(LOCAL_CRATE)
-                .temp_path(OutputType::Metadata, Some(&metadata_cgu_name));
-
-            let obj = crate::backend::with_object(tcx.sess, &metadata_cgu_name, |object| {
-                crate::metadata::write_metadata(tcx, object);
-            });
+    if crate::target_triple(tcx.sess).binary_format != target_lexicon::BinaryFormat::Elf {
+        tcx.sess
+            .fatal("the `build_id` option is only supported for ELF targets");
+    }
 
-            if let Err(err) = std::fs::write(&tmp_file, obj) {
-                tcx.sess
-                    .fatal(&format!("error writing metadata object file: {}", err));
+    let mut hasher = StableHasher::new();
+    // The code of the upstream crates is part of the linked artifact too.
+    let mut upstream_crates = tcx
+        .crates()
+        .iter()
+        .map(|&cnum| {
+            (
+                tcx.crate_name(cnum).as_str().to_string(),
+                tcx.crate_hash(cnum).as_u64(),
+            )
+        })
+        .collect::<Vec<_>>();
+    upstream_crates.sort();
+    upstream_crates.hash(&mut hasher);
+    for module in modules {
+        module.name.hash(&mut hasher);
+        if let Some(object) = &module.object {
+            match std::fs::read(object) {
+                Ok(data) => data.hash(&mut hasher),
+                Err(err) => tcx.sess.fatal(&format!(
+                    "error reading object file {}: {}",
+                    object.display(),
+                    err
+                )),
             }
+        }
+    }
+    let build_id: Fingerprint = hasher.finish();
+    let (build_id_hi, build_id_lo) = build_id.as_value();
+    let build_id = [build_id_hi.to_be_bytes(), build_id_lo.to_be_bytes()].concat();
 
-            (metadata_cgu_name, tmp_file)
-        });
+    let cgu_name_builder = &mut CodegenUnitNameBuilder::new(tcx);
+    let build_id_cgu_name = cgu_name_builder
+        .build_cgu_name(LOCAL_CRATE, &["crate"], Some("build_id"))
+        .as_str()
+        .to_string();
+    let tmp_file = temp_path(tcx, config, OutputType::Object, &build_id_cgu_name);
 
-        Some(CompiledModule {
-            name: metadata_cgu_name,
-            kind: ModuleKind::Metadata,
-            object: Some(tmp_file),
-            dwarf_object: None,
-            bytecode: None,
-        })
-    } else {
-        None
-    };
+    let mut obj = crate::backend::with_object(tcx.sess, &build_id_cgu_name, |object| {
+        set_file_flags(tcx, config, object);
+        crate::backend::add_build_id_note(tcx.sess, object, &build_id);
+    });
+    let tmp_file = write_object(tcx, config, &build_id_cgu_name, tmp_file, &mut obj);
 
-    Box::new((
-        CodegenResults {
-            crate_name: tcx.crate_name(LOCAL_CRATE),
-            modules,
-            allocator_module,
-            metadata_module,
-            metadata,
-            windows_subsystem: None, // Windows is not yet supported
-            linker_info: LinkerInfo::new(tcx),
-            crate_info: CrateInfo::new(tcx),
-        },
-        work_products,
-    ))
+    CompiledModule {
+        name: build_id_cgu_name,
+        kind: ModuleKind::Regular,
+        object: Some(tmp_file),
+        dwarf_object: None,
+        bytecode: None,
+    }
 }
 
-fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
-    use std::io::Write;
+/// Assembles `global_asm` and links it into the object file of the codegen unit `cgu_name`. The
+/// symbols in `local_symbols` are made local once the reference to them from the object file has
+/// been resolved.
+fn codegen_global_asm(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    cgu_name: &str,
+    global_asm: &str,
+    local_symbols: &[String],
+) {
     use std::process::{Command, Stdio};
 
     if global_asm.is_empty() {
@@ -347,6 +1930,13 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
         || tcx.sess.target.is_like_windows
     {
         if global_asm.contains("__rust_probestack") {
+            // Only compiler_builtins defines `__rust_probestack`. Cranelift doesn't emit calls to
+            // it as `enable_probestack` is disabled, but users should know that large stack frames
+            // aren't protected against stack overflows.
+            tcx.sess.warn(
+                "stack probes are not supported by rustc_codegen_cranelift on this target; \
+                 `__rust_probestack` will not be defined",
+            );
             return;
         }
 
@@ -361,8 +1951,14 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
         }
     }
 
+    if config.object_sink.is_some() {
+        tcx.sess
+            .fatal("global_asm! is not supported when object files are handed to an object sink");
+    }
+
     let assembler = crate::toolchain::get_toolchain_binary(tcx.sess, "as");
     let linker = crate::toolchain::get_toolchain_binary(tcx.sess, "ld");
+    let objcopy = crate::toolchain::get_toolchain_binary(tcx.sess, "objcopy");
 
     // Remove all LLVM style comments
     let global_asm = global_asm
@@ -377,13 +1973,13 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
         .collect::<Vec<_>>()
         .join("\n");
 
-    let output_object_file = tcx
-        .output_filenames(LOCAL_CRATE)
-        .temp_path(OutputType::Object, Some(cgu_name));
+    let output_object_file = temp_path(tcx, config, OutputType::Object, cgu_name);
 
     // Assemble `global_asm`
     let global_asm_object_file = add_file_stem_postfix(output_object_file.clone(), ".asm");
     let mut child = Command::new(assembler)
+        // Don't mark the stack as executable because of a missing `.note.GNU-stack` section
+        .arg("--noexecstack")
         .arg("-o")
         .arg(&global_asm_object_file)
         .stdin(Stdio::piped())
@@ -407,7 +2003,7 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
     let status = Command::new(linker)
         .arg("-r") // Create a new object file
         .arg("-o")
-        .arg(output_object_file)
+        .arg(&output_object_file)
         .arg(&main_object_file)
         .arg(&global_asm_object_file)
         .status()
@@ -420,6 +2016,44 @@ fn codegen_global_asm(tcx: TyCtxt<'_>, cgu_name: &str, global_asm: &str) {
         ));
     }
 
+    if !local_symbols.is_empty() {
+        let status = Command::new(objcopy)
+            .args(
+                local_symbols
+                    .iter()
+                    .map(|symbol| format!("--localize-symbol={}", symbol)),
+            )
+            .arg(&output_object_file)
+            .status()
+            .expect("Failed to spawn `objcopy`.");
+        if !status.success() {
+            tcx.sess.fatal(&format!(
+                "Failed to make the symbols of internal naked functions in `{}` local",
+                output_object_file.display(),
+            ));
+        }
+    }
+
+    // `ld` writes the `EI_OSABI` byte it defaults to for the target rather than the one of the
+    // object file written by `write_object`.
+    if let Some(os_abi) = elf_os_abi(tcx, config) {
+        let mut obj = std::fs::read(&output_object_file).unwrap_or_else(|err| {
+            tcx.sess.fatal(&format!(
+                "error reading object file {}: {}",
+                output_object_file.display(),
+                err
+            ))
+        });
+        crate::backend::set_elf_os_abi(&mut obj, os_abi);
+        if let Err(err) = std::fs::write(&output_object_file, obj) {
+            tcx.sess.fatal(&format!(
+                "error writing object file {}: {}",
+                output_object_file.display(),
+                err
+            ));
+        }
+    }
+
     std::fs::remove_file(global_asm_object_file).unwrap();
     std::fs::remove_file(main_object_file).unwrap();
 }
@@ -436,11 +2070,41 @@ fn add_file_stem_postfix(mut path: PathBuf, postfix: &str) -> PathBuf {
 }
 
 // Adapted from https://github.com/rust-lang/rust/blob/303d8aff6092709edd4dbd35b1c88e9aa40bf6d8/src/librustc_codegen_ssa/base.rs#L922-L953
-fn determine_cgu_reuse<'tcx>(tcx: TyCtxt<'tcx>, cgu: &CodegenUnit<'tcx>) -> CguReuse {
+fn determine_cgu_reuse<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    cgu: &CodegenUnit<'tcx>,
+) -> CguReuse {
     if !tcx.dep_graph.is_fully_enabled() {
         return CguReuse::No;
     }
 
+    // This has to be checked before marking the CGU as green, as the CGU is codegened again.
+    let cgu_name = cgu.name().as_str();
+    if config
+        .force_cgu_regen
+        .iter()
+        .any(|pattern| glob_matches(pattern, &cgu_name))
+    {
+        return CguReuse::No;
+    }
+
+    // The disassembly and Cranelift IR are not cached, so they can only be emitted by codegening
+    // the codegen unit again.
+    if tcx
+        .sess
+        .opts
+        .output_types
+        .contains_key(&OutputType::Assembly)
+        || tcx
+            .sess
+            .opts
+            .output_types
+            .contains_key(&OutputType::LlvmAssembly)
+    {
+        return CguReuse::No;
+    }
+
     let work_product_id = &cgu.work_product_id();
     if tcx
         .dep_graph
@@ -471,3 +2135,145 @@ fn determine_cgu_reuse<'tcx>(tcx: TyCtxt<'tcx>, cgu: &CodegenUnit<'tcx>) -> CguR
         CguReuse::No
     }
 }
+
+/// Returns the sections to move functions of a codegen unit to, keyed by their symbol name.
+/// Functions matching one of the `function_section_rules` are moved to the section of the first
+/// matching rule. `#[cold]` functions are moved to `.text.unlikely.*` sections, which linkers group
+/// together away from the other functions, when `-Zfunction-sections` is enabled. Without it they
+/// are kept in `.text`, as giving them a section of their own requires every function of the
+/// codegen unit to have its own section, which slows down linking considerably. Only supported
+/// for ELF. The sections are found through the symbols of the functions and renamed in the written
+/// object file, see [`FunctionSectionRenames`], so only functions defined by Cranelift are moved.
+fn function_section_names<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    mono_items: &[(MonoItem<'tcx>, (RLinkage, Visibility))],
+) -> FxHashMap<String, String> {
+    if !config.function_section_rules.is_empty()
+        && crate::target_triple(tcx.sess).binary_format != target_lexicon::BinaryFormat::Elf
+    {
+        tcx.sess
+            .fatal("the `function_section` option is only supported for ELF targets");
+    }
+
+    mono_items
+        .iter()
+        .filter_map(|&(mono_item, _)| {
+            let instance = match mono_item {
+                MonoItem::Fn(instance) => instance,
+                MonoItem::Static(_) | MonoItem::GlobalAsm(_) => return None,
+            };
+            let symbol_name =
+                crate::linkage::symbol_name(tcx, config.symbol_prefix.as_deref(), instance);
+            if !config.function_section_rules.is_empty() {
+                let path = rustc_middle::ty::print::with_no_trimmed_paths(|| {
+                    tcx.def_path_str_with_substs(instance.def_id(), instance.substs)
+                });
+                let rule = config.function_section_rules.iter().find(|(pattern, _)| {
+                    glob_matches(pattern, &symbol_name) || glob_matches(pattern, &path)
+                });
+                if let Some((_, section_name)) = rule {
+                    return Some((symbol_name, section_name.clone()));
+                }
+            }
+            if tcx.sess.opts.debugging_opts.function_sections == Some(true)
+                && tcx
+                    .codegen_fn_attrs(instance.def_id())
+                    .flags
+                    .contains(CodegenFnAttrFlags::COLD)
+            {
+                let section_name = format!(".text.unlikely.{}", symbol_name);
+                return Some((symbol_name, section_name));
+            }
+            None
+        })
+        .collect()
+}
+
+/// Codegens the mono items reachable from the roots given by the `codegen_roots` option. Statics
+/// and global asm are always codegened. All other functions are neither declared nor defined.
+fn codegen_reachable_mono_items<'tcx>(
+    cx: &mut crate::CodegenCx<'tcx, ObjectModule>,
+    config: &BackendConfig,
+    mono_items: Vec<(MonoItem<'tcx>, (RLinkage, Visibility))>,
+    progress: Option<&Progress>,
+) {
+    let tcx = cx.tcx;
+    let entry_def_id = tcx
+        .entry_fn(LOCAL_CRATE)
+        .map(|(def_id, _)| def_id.to_def_id());
+    let start_def_id = tcx.lang_items().start_fn();
+
+    let mut deferred = FxHashMap::default();
+    let mut worklist = Vec::new();
+    for (mono_item, linkage) in mono_items {
+        let instance = match mono_item {
+            MonoItem::Fn(instance) => instance,
+            MonoItem::Static(_) | MonoItem::GlobalAsm(_) => {
+                worklist.push((mono_item, linkage));
+                continue;
+            }
+        };
+        let name = crate::linkage::symbol_name(tcx, config.symbol_prefix.as_deref(), instance);
+        if Some(instance.def_id()) == entry_def_id
+            || Some(instance.def_id()) == start_def_id
+            || config
+                .codegen_roots
+                .iter()
+                .any(|pattern| glob_matches(pattern, &name))
+        {
+            worklist.push((mono_item, linkage));
+        } else {
+            deferred.insert(name, (mono_item, linkage));
+        }
+    }
+
+    // Referencing a function declares it, so every function declared since the last iteration is
+    // either already codegened, not part of this codegen unit or newly reached.
+    let mut known_functions = 0;
+    while !worklist.is_empty() {
+        super::predefine_mono_items(cx, &worklist);
+        for (mono_item, (linkage, visibility)) in worklist {
+            let linkage = crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
+            super::codegen_mono_item(cx, mono_item, linkage);
+            if let Some(progress) = progress {
+                progress.items_done(1);
+            }
+        }
+        cx.constants_cx
+            .define_pending(tcx, &mut cx.module, cx.symbol_prefix.as_deref());
+
+        let functions = cx
+            .module
+            .declarations()
+            .get_functions()
+            .skip(known_functions)
+            .map(|(_, decl)| decl.name.clone())
+            .collect::<Vec<_>>();
+        known_functions += functions.len();
+        worklist = functions
+            .into_iter()
+            .filter_map(|name| deferred.remove(&name))
+            .collect();
+    }
+
+    if let Some(progress) = progress {
+        progress.items_done(deferred.len());
+    }
+}
+
+/// Returns whether `name` matches `pattern`, in which `*` matches any sequence of characters.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(name) => name,
+                None => return false,
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| glob_matches(rest, &name[i..]))
+        }
+    }
+}
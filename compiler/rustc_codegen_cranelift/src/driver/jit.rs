@@ -1,25 +1,358 @@
 //! The JIT driver uses [`cranelift_simplejit`] to JIT execute programs without writing any object
 //! files.
 
+use std::any::Any;
 use std::cell::RefCell;
+use std::collections::hash_map::Entry;
 use std::ffi::CString;
+use std::lazy::SyncLazy;
 use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::sync::Mutex;
 
-use rustc_codegen_ssa::CrateInfo;
-use rustc_middle::mir::mono::MonoItem;
+use rustc_codegen_ssa::back::linker::LinkerInfo;
+use rustc_codegen_ssa::{CodegenResults, CrateInfo};
+use rustc_middle::dep_graph::{WorkProduct, WorkProductId};
+use rustc_middle::middle::cstore::EncodedMetadata;
+use rustc_middle::mir::mono::{CodegenUnit, Linkage as RLinkage, MonoItem, Visibility};
 
 use cranelift_jit::{JITBuilder, JITModule};
 
+use crate::debuginfo::UnwindRegistry;
 use crate::prelude::*;
-use crate::{CodegenCx, CodegenMode};
+use crate::{BackendConfig, CodegenCx, CodegenMode, JitEntry, JitError, JitSymbolResolver};
 
 thread_local! {
     pub static CURRENT_MODULE: RefCell<Option<JITModule>> = RefCell::new(None);
+
+    /// The [`BackendConfig::symbol_prefix`] used for [`CURRENT_MODULE`], which the functions
+    /// codegened lazily have to use too.
+    static CURRENT_SYMBOL_PREFIX: RefCell<Option<String>> = RefCell::new(None);
+
+    /// The imports of [`CURRENT_MODULE`], which the imports of the functions codegened lazily are
+    /// checked against.
+    static CURRENT_IMPORTS: RefCell<Option<JitImports>> = RefCell::new(None);
+}
+
+/// The symbols the imports of a JIT module are resolved to by `cranelift_jit`: the imported
+/// symbols passed to the [`JITBuilder`], followed by the [`JitSymbolResolver`] and the symbols of
+/// the current process. `cranelift_jit` panics when it can't resolve an import, so all imports
+/// are checked using [`check_imports`] before finalizing the definitions of the module.
+#[derive(Clone)]
+struct JitImports {
+    symbols: FxHashSet<String>,
+    resolver: Option<JitSymbolResolver>,
+}
+
+impl JitImports {
+    fn resolves(&self, name: &str) -> bool {
+        if self.symbols.contains(name) {
+            return true;
+        }
+        if let Some(resolver) = &self.resolver {
+            if (resolver.0)(name).is_some() {
+                return true;
+            }
+        }
+        lookup_in_process(name)
+    }
+}
+
+/// Returns whether the symbol `name` is defined by the current process, the same way
+/// `cranelift_jit` looks it up.
+#[cfg(unix)]
+fn lookup_in_process(name: &str) -> bool {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    !unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) }.is_null()
+}
+
+#[cfg(not(unix))]
+fn lookup_in_process(_name: &str) -> bool {
+    // Leave reporting missing symbols to `cranelift_jit`.
+    true
+}
+
+/// The JIT module kept alive between sessions when [`BackendConfig::jit_keep_module`] is set,
+/// together with the hash of the Cranelift IR of every function defined in it.
+struct KeptModule {
+    module: JITModule,
+    imports: JitImports,
+    function_hashes: FxHashMap<String, u64>,
+    /// The unwind tables registered by every session, together with the functions defined by the
+    /// session which weren't redefined since. Once all of them are redefined, the registration is
+    /// dropped, which deregisters the unwind tables. The registration of the session creating the
+    /// module has no set of functions and is never dropped, as it also contains the unwind tables
+    /// of the entry wrapper and the allocator shim.
+    unwind_registrations: Vec<(UnwindRegistry, Option<FxHashSet<String>>)>,
+}
+
+// The kept module is only used by a single session at a time, but the next session may run on a
+// different thread.
+unsafe impl Send for KeptModule {}
+
+static KEPT_MODULE: SyncLazy<Mutex<Option<KeptModule>>> = SyncLazy::new(|| Mutex::new(None));
+
+/// Address and size of the machine code of every function of the current JIT module, by symbol
+/// name. See [`jit_function_code`].
+static FUNCTION_CODE: SyncLazy<Mutex<FxHashMap<String, (usize, usize)>>> =
+    SyncLazy::new(|| Mutex::new(FxHashMap::default()));
+
+/// Returns the machine code of the JIT compiled function with the given symbol name, or `None` if
+/// no such function has been compiled. In `jit-lazy` mode a function is only compiled when it is
+/// first called.
+///
+/// # Safety
+///
+/// The returned slice points into the memory of the JIT module and is only valid as long as that
+/// module is alive and the function isn't redefined. This is the case during
+/// [`JitEntry::run`](crate::JitEntry::run), and with [`BackendConfig::jit_keep_module`] until the
+/// next session redefines the function. Once the module is dropped, `None` is returned for all of
+/// its functions.
+pub unsafe fn jit_function_code(symbol: &str) -> Option<&'static [u8]> {
+    let &(addr, size) = FUNCTION_CODE.lock().unwrap().get(symbol)?;
+    Some(std::slice::from_raw_parts(addr as *const u8, size))
+}
+
+/// Forgets the machine code recorded for [`jit_function_code`] when dropped. It has to be dropped
+/// together with the JIT module the recorded code belongs to.
+struct ForgetFunctionCode;
+
+impl Drop for ForgetFunctionCode {
+    fn drop(&mut self) {
+        FUNCTION_CODE.lock().unwrap().clear();
+    }
 }
 
-pub(super) fn run_jit(tcx: TyCtxt<'_>, codegen_mode: CodegenMode) -> ! {
+fn record_function_code(jit_module: &JITModule, code_sizes: FxHashMap<FuncId, u32>) {
+    let mut function_code = FUNCTION_CODE.lock().unwrap();
+    for (func_id, size) in code_sizes {
+        let name = jit_module
+            .declarations()
+            .get_function_decl(func_id)
+            .name
+            .clone();
+        let addr = jit_module.get_finalized_function(func_id) as usize;
+        function_code.insert(name, (addr, size as usize));
+    }
+}
+
+pub(super) fn run_jit(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    metadata: EncodedMetadata,
+) -> Box<dyn Any> {
+    if config.jit_entry.is_some() || config.jit_keep_module {
+        let ret =
+            run_jit_entry(tcx, config).unwrap_or_else(|err| report_jit_error(tcx, config, err));
+        if !config.jit_keep_module {
+            std::process::exit(ret);
+        }
+
+        return Box::new((
+            CodegenResults {
+                crate_name: tcx.crate_name(LOCAL_CRATE),
+                modules: vec![],
+                allocator_module: None,
+                metadata_module: None,
+                metadata,
+                windows_subsystem: None,
+                linker_info: LinkerInfo::new(tcx),
+                crate_info: CrateInfo::new(tcx),
+            },
+            FxHashMap::<WorkProductId, WorkProduct>::default(),
+        ));
+    }
+
+    let (jit_module, imports, _function_hashes, unwind_context, entry) =
+        match jit_build(tcx, config, None) {
+            Ok(res) => res,
+            Err(err) => report_jit_error(tcx, config, err),
+        };
+
+    let _unwind_register_guard = unsafe { unwind_context.register_jit(&jit_module) };
+
+    CURRENT_SYMBOL_PREFIX.with(|symbol_prefix| {
+        *symbol_prefix.borrow_mut() = config.symbol_prefix.clone();
+    });
+    CURRENT_IMPORTS.with(|current_imports| *current_imports.borrow_mut() = Some(imports));
+
+    println!("Rustc codegen cranelift will JIT run the executable, because -Cllvm-args=mode=jit was passed");
+
+    let f: extern "C" fn(c_int, *const *const c_char) -> c_int =
+        unsafe { ::std::mem::transmute(entry) };
+
+    let args = ::std::env::var("CG_CLIF_JIT_ARGS").unwrap_or_else(|_| String::new());
+    let args = std::iter::once(&*tcx.crate_name(LOCAL_CRATE).as_str().to_string())
+        .chain(args.split(' '))
+        .map(|arg| CString::new(arg).expect("argument contains a nul byte"))
+        .collect::<Vec<_>>();
+    let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+
+    // Push a null pointer as a terminating argument. This is required by POSIX and
+    // useful as some dynamic linkers use it as a marker to jump over.
+    argv.push(std::ptr::null());
+
+    CURRENT_MODULE
+        .with(|current_module| assert!(current_module.borrow_mut().replace(jit_module).is_none()));
+
+    let ret = f(args.len() as c_int, argv.as_ptr());
+
+    std::process::exit(ret);
+}
+
+/// JIT compiles the crate of `tcx` and calls [`BackendConfig::jit_entry`] with the address of its
+/// entry function, returning the value returned by it. This is the same as using `config` for the
+/// codegen backend in JIT mode, except that errors are returned instead of being reported and that
+/// the process isn't exited afterwards. It can be called for example from
+/// `rustc_driver::Callbacks::after_analysis` of an application embedding the JIT.
+///
+/// With [`BackendConfig::jit_keep_module`] the module kept by the previous call is reused and the
+/// module is kept alive for the next call. Otherwise the JIT compiled code must not be used anymore
+/// once this function returned, as its unwind tables are deregistered.
+pub fn run_jit_entry(tcx: TyCtxt<'_>, config: &BackendConfig) -> Result<i32, JitError> {
+    let jit_entry = config
+        .jit_entry
+        .as_ref()
+        .ok_or_else(|| JitError::InvalidConfig("no JIT entry is set".to_string()))?;
+    if config.jit_keep_module {
+        return run_jit_keep_module(tcx, config, jit_entry);
+    }
+
+    // Declared before the module, so that it is dropped after it.
+    let _forget_function_code = ForgetFunctionCode;
+    let (jit_module, imports, _function_hashes, unwind_context, entry) =
+        jit_build(tcx, config, None)?;
+
+    let _unwind_register_guard = unsafe { unwind_context.register_jit(&jit_module) };
+
+    CURRENT_SYMBOL_PREFIX.with(|symbol_prefix| {
+        *symbol_prefix.borrow_mut() = config.symbol_prefix.clone();
+    });
+    CURRENT_IMPORTS.with(|current_imports| *current_imports.borrow_mut() = Some(imports));
+    CURRENT_MODULE
+        .with(|current_module| assert!(current_module.borrow_mut().replace(jit_module).is_none()));
+
+    let ret = (jit_entry.run)(entry);
+
+    CURRENT_MODULE.with(|current_module| current_module.borrow_mut().take());
+    CURRENT_IMPORTS.with(|current_imports| current_imports.borrow_mut().take());
+    Ok(ret)
+}
+
+/// Runs the JIT entry like [`run_jit_entry`], but reuses the module kept by the previous session
+/// and keeps it alive for the next one.
+fn run_jit_keep_module(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+    jit_entry: &JitEntry,
+) -> Result<i32, JitError> {
+    if matches!(config.codegen_mode, CodegenMode::JitLazy) {
+        // The shims of the lazy JIT mode refer to the `TyCtxt` of the session that created them.
+        return Err(JitError::InvalidConfig(
+            "keeping the JIT module is not supported in lazy JIT mode".to_string(),
+        ));
+    }
+
+    // A session failing to compile the crate drops the kept module, so the next session starts
+    // with a new module.
+    let mut kept_module = KEPT_MODULE.lock().unwrap().take();
+    // Forgets the code of the kept module when it is dropped because of an error or a panic.
+    let forget_function_code = ForgetFunctionCode;
+    let is_new_module = kept_module.is_none();
+    let (prev_function_hashes, mut unwind_registrations) = match &mut kept_module {
+        Some(kept_module) => (
+            kept_module.function_hashes.clone(),
+            std::mem::take(&mut kept_module.unwind_registrations),
+        ),
+        None => (FxHashMap::default(), Vec::new()),
+    };
+    let (jit_module, imports, function_hashes, unwind_context, entry) =
+        jit_build(tcx, config, kept_module)?;
+    let function_hashes = function_hashes.unwrap();
+
+    // Only functions whose Cranelift IR changed are defined again.
+    let defined_functions = function_hashes
+        .iter()
+        .filter(|&(name, hash)| prev_function_hashes.get(name) != Some(hash))
+        .map(|(name, _hash)| name.clone())
+        .collect::<FxHashSet<_>>();
+    for (_registration, functions) in &mut unwind_registrations {
+        if let Some(functions) = functions {
+            functions.retain(|name| !defined_functions.contains(name));
+        }
+    }
+    unwind_registrations.retain(|(_registration, functions)| {
+        functions
+            .as_ref()
+            .map_or(true, |functions| !functions.is_empty())
+    });
+    if let Some(registration) = unsafe { unwind_context.register_jit(&jit_module) } {
+        let functions = if is_new_module {
+            None
+        } else {
+            Some(defined_functions)
+        };
+        unwind_registrations.push((registration, functions));
+    }
+
+    let ret = (jit_entry.run)(entry);
+
+    *KEPT_MODULE.lock().unwrap() = Some(KeptModule {
+        module: jit_module,
+        imports,
+        function_hashes,
+        unwind_registrations,
+    });
+    std::mem::forget(forget_function_code);
+
+    Ok(ret)
+}
+
+fn report_jit_error(tcx: TyCtxt<'_>, config: &BackendConfig, err: JitError) -> ! {
+    match config.jit_error_handler {
+        Some(jit_error_handler) => std::process::exit(jit_error_handler(err)),
+        None => tcx.sess.fatal(&err.to_string()),
+    }
+}
+
+/// JIT compiles the crate into `kept_module` if passed and into a new module otherwise. Returns
+/// the JIT module, its imports, the hashes of its functions if [`BackendConfig::jit_keep_module`]
+/// is set, the unwind context whose unwind tables still have to be registered and the address of
+/// the function to run, which is either the `main` function or the function of
+/// [`BackendConfig::jit_entry`].
+fn jit_build<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    kept_module: Option<KeptModule>,
+) -> Result<
+    (
+        JITModule,
+        JitImports,
+        Option<FxHashMap<String, u64>>,
+        UnwindContext<'tcx>,
+        *const u8,
+    ),
+    JitError,
+> {
+    let codegen_mode = config.codegen_mode;
+
+    let is_executable = tcx
+        .sess
+        .crate_types()
+        .contains(&rustc_session::config::CrateType::Executable);
+    if !is_executable {
+        return Err(JitError::UnsupportedCrate(
+            "can't jit non-executable crate".to_string(),
+        ));
+    }
+
     if !tcx.sess.opts.output_types.should_codegen() {
-        tcx.sess.fatal("JIT mode doesn't work with `cargo check`.");
+        return Err(JitError::InvalidConfig(
+            "JIT mode doesn't work with `cargo check`".to_string(),
+        ));
     }
 
     #[cfg(unix)]
@@ -34,114 +367,280 @@ pub(super) fn run_jit(tcx: TyCtxt<'_>, codegen_mode: CodegenMode) -> ! {
             libc::dladdr(run_jit as *const libc::c_void, &mut dl_info),
             0
         );
-        assert_ne!(
-            libc::dlopen(dl_info.dli_fname, libc::RTLD_NOW | libc::RTLD_GLOBAL),
-            std::ptr::null_mut(),
-        );
+        if libc::dlopen(dl_info.dli_fname, libc::RTLD_NOW | libc::RTLD_GLOBAL).is_null() {
+            return Err(JitError::DylibLoad {
+                path: PathBuf::from(
+                    std::ffi::CStr::from_ptr(dl_info.dli_fname)
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+                error: std::ffi::CStr::from_ptr(libc::dlerror())
+                    .to_string_lossy()
+                    .into_owned(),
+            });
+        }
     }
 
-    let imported_symbols = load_imported_symbols_for_jit(tcx);
-
-    let mut jit_builder = JITBuilder::with_isa(
-        crate::build_isa(tcx.sess),
-        cranelift_module::default_libcall_names(),
-    );
-    jit_builder.hotswap(matches!(codegen_mode, CodegenMode::JitLazy));
-    jit_builder.symbols(imported_symbols);
-    let mut jit_module = JITModule::new(jit_builder);
+    let is_new_module = kept_module.is_none();
+    let (mut jit_module, imports, function_hashes) = match kept_module {
+        Some(KeptModule {
+            module,
+            imports,
+            function_hashes,
+            unwind_registrations: _,
+        }) => (module, imports, Some(function_hashes)),
+        None => {
+            let (module, imports) = new_jit_module(tcx, config)?;
+            let function_hashes = if config.jit_keep_module {
+                Some(FxHashMap::default())
+            } else {
+                None
+            };
+            (module, imports, function_hashes)
+        }
+    };
     assert_eq!(pointer_ty(tcx), jit_module.target_config().pointer_type());
 
-    let sig = Signature {
-        params: vec![
-            AbiParam::new(jit_module.target_config().pointer_type()),
-            AbiParam::new(jit_module.target_config().pointer_type()),
-        ],
-        returns: vec![AbiParam::new(
-            jit_module.target_config().pointer_type(), /*isize*/
-        )],
-        call_conv: CallConv::triple_default(&crate::target_triple(tcx.sess)),
+    // The entry function is looked up by name after codegen instead, as its signature is unknown.
+    let main_func_id = if config.jit_entry.is_none() {
+        let sig = Signature {
+            params: vec![
+                AbiParam::new(jit_module.target_config().pointer_type()),
+                AbiParam::new(jit_module.target_config().pointer_type()),
+            ],
+            returns: vec![AbiParam::new(
+                jit_module.target_config().pointer_type(), /*isize*/
+            )],
+            call_conv: CallConv::triple_default(&crate::target_triple(tcx.sess)),
+        };
+        Some(
+            jit_module
+                .declare_function("main", Linkage::Import, &sig)
+                .map_err(|err| JitError::Codegen(err.to_string()))?,
+        )
+    } else {
+        None
     };
-    let main_func_id = jit_module
-        .declare_function("main", Linkage::Import, &sig)
-        .unwrap();
 
-    let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
-    let mono_items = cgus
-        .iter()
-        .map(|cgu| cgu.items_in_deterministic_order(tcx).into_iter())
-        .flatten()
-        .collect::<FxHashMap<_, (_, _)>>()
-        .into_iter()
-        .collect::<Vec<(_, (_, _))>>();
+    let cgus = super::collect_and_partition_mono_items(tcx);
+    let mono_items = merge_cgus(tcx, cgus);
+
+    if !is_new_module {
+        check_kept_signatures(tcx, config, &jit_module, &mono_items)?;
+    }
 
     let mut cx = crate::CodegenCx::new(tcx, jit_module, false, false);
+    cx.verify_ir = config.verify_ir;
+    cx.max_function_size = config.max_function_size;
+    cx.intrinsic_overrides = config.intrinsic_overrides.iter().cloned().collect();
+    cx.mir_transform = config.mir_transform.clone();
+    cx.symbol_prefix = config.symbol_prefix.clone();
+    cx.test_break_ir = config.test_break_ir.clone();
+    cx.test_skip_definition = config.test_skip_definition.clone();
+    cx.code_sizes = Some(FxHashMap::default());
+    cx.jit_redefine = function_hashes.map(|function_hashes| {
+        let prepare_redefine: fn(&mut JITModule, FuncId) =
+            |module, func_id| module.prepare_for_function_redefine(func_id).unwrap();
+        (function_hashes, prepare_redefine)
+    });
 
+    let progress = super::Progress::new(tcx, config, mono_items.len());
     super::time(tcx, "codegen mono items", || {
         super::predefine_mono_items(&mut cx, &mono_items);
         for (mono_item, (linkage, visibility)) in mono_items {
-            let linkage = crate::linkage::get_clif_linkage(mono_item, linkage, visibility);
+            let linkage = crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
             match mono_item {
-                MonoItem::Fn(inst) => match codegen_mode {
-                    CodegenMode::Aot => unreachable!(),
-                    CodegenMode::Jit => {
-                        cx.tcx.sess.time("codegen fn", || {
-                            crate::base::codegen_fn(&mut cx, inst, linkage)
-                        });
-                    }
-                    CodegenMode::JitLazy => codegen_shim(&mut cx, inst),
-                },
-                MonoItem::Static(def_id) => {
-                    crate::constant::codegen_static(&mut cx.constants_cx, def_id);
+                MonoItem::Fn(inst) if matches!(codegen_mode, CodegenMode::JitLazy) => {
+                    codegen_shim(&mut cx, inst)?
                 }
                 MonoItem::GlobalAsm(item_id) => {
                     let item = cx.tcx.hir().item(item_id);
                     tcx.sess.span_fatal(item.span, "Global asm is not supported in JIT mode");
                 }
+                MonoItem::Fn(_) | MonoItem::Static(_) => {
+                    super::codegen_mono_item(&mut cx, mono_item, linkage)
+                }
+            }
+            if let Some(progress) = &progress {
+                progress.items_done(1);
             }
         }
-    });
-
-    let (mut jit_module, global_asm, _debug, mut unwind_context) =
+        Ok(())
+    })?;
+
+    let function_hashes = cx
+        .jit_redefine
+        .take()
+        .map(|(function_hashes, _)| function_hashes);
+    let code_sizes = cx.code_sizes.take().unwrap();
+    let (mut jit_module, global_asm, _debug, mut unwind_context, _stats) =
         tcx.sess.time("finalize CodegenCx", || cx.finalize());
-    jit_module.finalize_definitions();
+    finalize_definitions(&mut jit_module, &imports)?;
+
+    // Functions of a kept module which weren't redefined keep their previous code.
+    if is_new_module {
+        FUNCTION_CODE.lock().unwrap().clear();
+    }
+    record_function_code(&jit_module, code_sizes);
 
     if !global_asm.is_empty() {
-        tcx.sess.fatal("Inline asm is not supported in JIT mode");
+        return Err(JitError::UnsupportedCrate(
+            "inline asm is not supported in JIT mode".to_string(),
+        ));
     }
 
-    crate::main_shim::maybe_create_entry_wrapper(tcx, &mut jit_module, &mut unwind_context, true);
-    crate::allocator::codegen(tcx, &mut jit_module, &mut unwind_context);
+    // A kept module already contains the entry wrapper and the allocator shim.
+    if is_new_module {
+        crate::main_shim::maybe_create_entry_wrapper(
+            tcx,
+            &mut jit_module,
+            config.symbol_prefix.as_deref(),
+            &mut unwind_context,
+            true,
+        );
+        crate::allocator::codegen(
+            tcx,
+            &mut jit_module,
+            &mut unwind_context,
+            crate::allocator::use_small_shim(tcx, config),
+        );
+    }
 
     tcx.sess.abort_if_errors();
 
-    jit_module.finalize_definitions();
-
-    let _unwind_register_guard = unsafe { unwind_context.register_jit(&jit_module) };
-
-    let finalized_main: *const u8 = jit_module.get_finalized_function(main_func_id);
+    finalize_definitions(&mut jit_module, &imports)?;
 
-    println!("Rustc codegen cranelift will JIT run the executable, because -Cllvm-args=mode=jit was passed");
+    let entry = if let Some(jit_entry) = &config.jit_entry {
+        let func_id = match jit_module.get_name(&jit_entry.symbol) {
+            Some(cranelift_module::FuncOrDataId::Func(func_id)) => func_id,
+            _ => {
+                return Err(JitError::SymbolNotFound {
+                    symbol: jit_entry.symbol.clone(),
+                    error: "the JIT entry function is not defined by the crate".to_string(),
+                });
+            }
+        };
+        jit_module.get_finalized_function(func_id)
+    } else {
+        jit_module.get_finalized_function(main_func_id.unwrap())
+    };
 
-    let f: extern "C" fn(c_int, *const *const c_char) -> c_int =
-        unsafe { ::std::mem::transmute(finalized_main) };
+    Ok((jit_module, imports, function_hashes, unwind_context, entry))
+}
 
-    let args = ::std::env::var("CG_CLIF_JIT_ARGS").unwrap_or_else(|_| String::new());
-    let args = std::iter::once(&*tcx.crate_name(LOCAL_CRATE).as_str().to_string())
-        .chain(args.split(' '))
-        .map(|arg| CString::new(arg).unwrap())
-        .collect::<Vec<_>>();
-    let mut argv = args.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+/// Checks that the functions already declared in the JIT module kept by a previous session keep
+/// their signature. Existing callers would pass the wrong arguments to the new version otherwise.
+fn check_kept_signatures<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    config: &BackendConfig,
+    jit_module: &JITModule,
+    mono_items: &[(MonoItem<'tcx>, (RLinkage, Visibility))],
+) -> Result<(), JitError> {
+    for &(mono_item, _) in mono_items {
+        let instance = match mono_item {
+            MonoItem::Fn(instance) => instance,
+            MonoItem::Static(_) | MonoItem::GlobalAsm(_) => continue,
+        };
+        let name = crate::linkage::symbol_name(tcx, config.symbol_prefix.as_deref(), instance);
+        let func_id = match jit_module.get_name(&name) {
+            Some(cranelift_module::FuncOrDataId::Func(func_id)) => func_id,
+            _ => continue,
+        };
+        let prev_sig = &jit_module
+            .declarations()
+            .get_function_decl(func_id)
+            .signature;
+        let sig = crate::abi::get_function_sig(tcx, jit_module.isa().triple(), instance);
+        if *prev_sig != sig {
+            return Err(JitError::IncompatibleRedefinition {
+                symbol: name,
+                error: format!("its signature changed from `{}` to `{}`", prev_sig, sig),
+            });
+        }
+    }
+    Ok(())
+}
 
-    // Push a null pointer as a terminating argument. This is required by POSIX and
-    // useful as some dynamic linkers use it as a marker to jump over.
-    argv.push(std::ptr::null());
+fn new_jit_module(
+    tcx: TyCtxt<'_>,
+    config: &BackendConfig,
+) -> Result<(JITModule, JitImports), JitError> {
+    let codegen_mode = config.codegen_mode;
+
+    let imported_symbols = match &config.jit_symbols {
+        Some(jit_symbols) if !jit_symbols.merge_with_dylibs => jit_symbols.symbols.clone(),
+        Some(jit_symbols) => {
+            // Later symbols override earlier ones with the same name.
+            let mut imported_symbols = load_imported_symbols_for_jit(tcx)?;
+            imported_symbols.extend(jit_symbols.symbols.iter().cloned());
+            imported_symbols
+        }
+        None => load_imported_symbols_for_jit(tcx)?,
+    };
 
-    CURRENT_MODULE
-        .with(|current_module| assert!(current_module.borrow_mut().replace(jit_module).is_none()));
+    let isa = crate::build_isa(
+        tcx.sess,
+        !config.disable_jit_host_features,
+        config.test_break_ir.is_some(),
+    );
+    if matches!(codegen_mode, CodegenMode::JitLazy) && !isa.flags().is_pic() {
+        return Err(JitError::InvalidConfig(
+            "lazy JIT mode requires `-Crelocation-model=pic`".to_string(),
+        ));
+    }
+    if config.jit_keep_module && !isa.flags().is_pic() {
+        return Err(JitError::InvalidConfig(
+            "keeping the JIT module requires `-Crelocation-model=pic`".to_string(),
+        ));
+    }
+    let mut jit_builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+    // Functions can only be redefined in hotswap mode.
+    jit_builder.hotswap(matches!(codegen_mode, CodegenMode::JitLazy) || config.jit_keep_module);
+    let imports = JitImports {
+        symbols: imported_symbols
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect(),
+        resolver: config.jit_symbol_resolver.clone(),
+    };
+    jit_builder.symbols(imported_symbols);
+    if let Some(resolver) = &config.jit_symbol_resolver {
+        let resolver = resolver.0.clone();
+        jit_builder.symbol_lookup_fn(Box::new(move |name| resolver(name)));
+    }
+    Ok((JITModule::new(jit_builder), imports))
+}
 
-    let ret = f(args.len() as c_int, argv.as_ptr());
+/// Finalizes all definitions of `jit_module` once all of its imports are known to resolve, as
+/// `cranelift_jit` panics when it can't resolve a symbol.
+fn finalize_definitions(jit_module: &mut JITModule, imports: &JitImports) -> Result<(), JitError> {
+    check_imports(jit_module, imports)?;
+    jit_module.finalize_definitions();
+    Ok(())
+}
 
-    std::process::exit(ret);
+/// Checks that every function and data object imported by `jit_module` resolves to a symbol.
+fn check_imports(jit_module: &JITModule, imports: &JitImports) -> Result<(), JitError> {
+    let declarations = jit_module.declarations();
+    let imported_names = declarations
+        .get_functions()
+        .filter(|(_, decl)| decl.linkage == Linkage::Import)
+        .map(|(_, decl)| &decl.name)
+        .chain(
+            declarations
+                .get_data_objects()
+                .filter(|(_, decl)| decl.linkage == Linkage::Import)
+                .map(|(_, decl)| &decl.name),
+        );
+    for name in imported_names {
+        if !imports.resolves(name) {
+            return Err(JitError::SymbolNotFound {
+                symbol: name.clone(),
+                error: "not defined by the crate or any dylib it depends on".to_string(),
+            });
+        }
+    }
+    Ok(())
 }
 
 #[no_mangle]
@@ -154,8 +653,11 @@ extern "C" fn __clif_jit_fn(instance_ptr: *const Instance<'static>) -> *const u8
             let mut jit_module = jit_module.borrow_mut();
             let jit_module = jit_module.as_mut().unwrap();
             let mut cx = crate::CodegenCx::new(tcx, jit_module, false, false);
+            cx.symbol_prefix =
+                CURRENT_SYMBOL_PREFIX.with(|symbol_prefix| symbol_prefix.borrow().clone());
+            cx.code_sizes = Some(FxHashMap::default());
 
-            let name = tcx.symbol_name(instance).name.to_string();
+            let name = crate::linkage::symbol_name(tcx, cx.symbol_prefix.as_deref(), instance);
             let sig = crate::abi::get_function_sig(tcx, cx.module.isa().triple(), instance);
             let func_id = cx
                 .module
@@ -163,20 +665,56 @@ extern "C" fn __clif_jit_fn(instance_ptr: *const Instance<'static>) -> *const u8
                 .unwrap();
             cx.module.prepare_for_function_redefine(func_id).unwrap();
 
-            tcx.sess.time("codegen fn", || {
+            super::profile(tcx, "codegen fn", || {
                 crate::base::codegen_fn(&mut cx, instance, Linkage::Export)
             });
 
-            let (jit_module, global_asm, _debug_context, unwind_context) = cx.finalize();
+            let code_sizes = cx.code_sizes.take().unwrap();
+            let (jit_module, global_asm, _debug_context, unwind_context, _stats) = cx.finalize();
             assert!(global_asm.is_empty());
-            jit_module.finalize_definitions();
+            let res = CURRENT_IMPORTS.with(|imports| {
+                finalize_definitions(jit_module, imports.borrow().as_ref().unwrap())
+            });
+            res.unwrap_or_else(|err| tcx.sess.fatal(&err.to_string()));
+            record_function_code(jit_module, code_sizes);
             std::mem::forget(unsafe { unwind_context.register_jit(&jit_module) });
             jit_module.get_finalized_function(func_id)
         })
     })
 }
 
-fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
+/// Merges the items of all codegen units into a single list of items to codegen into the JIT
+/// module, as the JIT module is shared between all codegen units.
+///
+/// Items like `#[inline]` functions are copied into every codegen unit using them with internal
+/// linkage. Only a single copy is needed in the JIT module, but it has to be visible to the other
+/// items if any of the copies is. The order of the items is kept deterministic.
+fn merge_cgus<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    cgus: &[CodegenUnit<'tcx>],
+) -> Vec<(MonoItem<'tcx>, (RLinkage, Visibility))> {
+    let mut mono_items: Vec<(MonoItem<'tcx>, (RLinkage, Visibility))> = Vec::new();
+    let mut item_indices = FxHashMap::default();
+    for cgu in cgus {
+        for (mono_item, (linkage, visibility)) in cgu.items_in_deterministic_order(tcx) {
+            match item_indices.entry(mono_item) {
+                Entry::Vacant(entry) => {
+                    entry.insert(mono_items.len());
+                    mono_items.push((mono_item, (linkage, visibility)));
+                }
+                Entry::Occupied(entry) => {
+                    let existing = &mut mono_items[*entry.get()].1;
+                    if existing.0 == RLinkage::Internal && linkage != RLinkage::Internal {
+                        *existing = (linkage, visibility);
+                    }
+                }
+            }
+        }
+    }
+    mono_items
+}
+
+fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Result<Vec<(String, *const u8)>, JitError> {
     use rustc_middle::middle::dependency_format::Linkage;
 
     let mut dylib_paths = Vec::new();
@@ -206,42 +744,94 @@ fn load_imported_symbols_for_jit(tcx: TyCtxt<'_>) -> Vec<(String, *const u8)> {
         }
     }
 
+    let expected_architecture =
+        crate::backend::object_architecture(tcx.sess, &crate::target_triple(tcx.sess));
+
+    let dylibs = dylib_paths
+        .into_iter()
+        .map(|path| match std::fs::read(&path) {
+            Ok(data) => Ok((path, data)),
+            Err(err) => Err(JitError::DylibLoad {
+                path,
+                error: err.to_string(),
+            }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Loading a dylib for the wrong architecture would either fail inside `dlopen` or result in
+    // bogus symbol addresses. All of them are reported at once, as they are likely to have been
+    // built together.
+    let mut mismatched_dylibs = Vec::new();
+    for (path, data) in &dylibs {
+        use object::Object;
+        let obj = object::File::parse(data).map_err(|err| JitError::DylibLoad {
+            path: path.clone(),
+            error: err.to_string(),
+        })?;
+        if obj.architecture() != expected_architecture {
+            mismatched_dylibs.push((path.clone(), format!("{:?}", obj.architecture())));
+        }
+    }
+    if !mismatched_dylibs.is_empty() {
+        return Err(JitError::UnsupportedTarget {
+            expected: format!("{:?}", expected_architecture),
+            dylibs: mismatched_dylibs,
+        });
+    }
+
     let mut imported_symbols = Vec::new();
-    for path in dylib_paths {
+    for (path, data) in dylibs {
         use object::{Object, ObjectSymbol};
-        let lib = libloading::Library::new(&path).unwrap();
-        let obj = std::fs::read(path).unwrap();
-        let obj = object::File::parse(&obj).unwrap();
-        imported_symbols.extend(obj.dynamic_symbols().filter_map(|symbol| {
-            let name = symbol.name().unwrap().to_string();
+        let dylib_load_error = |error: String| JitError::DylibLoad {
+            path: path.clone(),
+            error,
+        };
+        let obj = object::File::parse(&data).map_err(|err| dylib_load_error(err.to_string()))?;
+        let lib =
+            libloading::Library::new(&path).map_err(|err| dylib_load_error(err.to_string()))?;
+        for symbol in obj.dynamic_symbols() {
+            let name = symbol
+                .name()
+                .map_err(|err| dylib_load_error(err.to_string()))?
+                .to_string();
             if name.is_empty() || !symbol.is_global() || symbol.is_undefined() {
-                return None;
+                continue;
             }
-            let dlsym_name = if cfg!(target_os = "macos") {
-                // On macOS `dlsym` expects the name without leading `_`.
-                assert!(name.starts_with('_'), "{:?}", name);
-                &name[1..]
+            let name = if cfg!(target_os = "macos") {
+                // On macOS symbol names in object files have a leading `_`, while both `dlsym` and
+                // the names declared in the JIT module don't have it. Symbols without leading `_`
+                // can't be referenced from Rust code.
+                match name.strip_prefix('_') {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                }
             } else {
-                &name
+                name
             };
-            let symbol: libloading::Symbol<'_, *const u8> =
-                unsafe { lib.get(dlsym_name.as_bytes()) }.unwrap();
-            Some((name, *symbol))
-        }));
+            let symbol: libloading::Symbol<'_, *const u8> = unsafe { lib.get(name.as_bytes()) }
+                .map_err(|err| JitError::SymbolNotFound {
+                    symbol: name.clone(),
+                    error: err.to_string(),
+                })?;
+            imported_symbols.push((name, *symbol));
+        }
         std::mem::forget(lib)
     }
 
     tcx.sess.abort_if_errors();
 
-    imported_symbols
+    Ok(imported_symbols)
 }
 
-pub(super) fn codegen_shim<'tcx>(cx: &mut CodegenCx<'tcx, impl Module>, inst: Instance<'tcx>) {
+pub(super) fn codegen_shim<'tcx>(
+    cx: &mut CodegenCx<'tcx, impl Module>,
+    inst: Instance<'tcx>,
+) -> Result<(), JitError> {
     let tcx = cx.tcx;
 
     let pointer_type = cx.module.target_config().pointer_type();
 
-    let name = tcx.symbol_name(inst).name.to_string();
+    let name = crate::linkage::symbol_name(tcx, cx.symbol_prefix.as_deref(), inst);
     let sig = crate::abi::get_function_sig(tcx, cx.module.isa().triple(), inst);
     let func_id = cx
         .module
@@ -298,5 +888,7 @@ pub(super) fn codegen_shim<'tcx>(cx: &mut CodegenCx<'tcx, impl Module>, inst: In
             &mut Context::for_function(trampoline),
             &mut cranelift_codegen::binemit::NullTrapSink {},
         )
-        .unwrap();
+        .map_err(|err| JitError::Codegen(err.to_string()))?;
+    cx.undefined_functions.remove(&func_id);
+    Ok(())
 }
@@ -2,9 +2,16 @@
 //! like JIT executing or writing object files.
 
 use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use rustc_codegen_ssa::back::linker::LinkerInfo;
+use rustc_codegen_ssa::{CodegenResults, CrateInfo};
+use rustc_data_structures::sync::{par_iter, ParallelIterator};
+use rustc_middle::dep_graph::{WorkProduct, WorkProductId};
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::middle::cstore::EncodedMetadata;
-use rustc_middle::mir::mono::{Linkage as RLinkage, MonoItem, Visibility};
+use rustc_middle::mir::mono::{CodegenUnit, Linkage as RLinkage, MonoItem, Visibility};
+use rustc_session::config::CrateType;
 
 use crate::prelude::*;
 use crate::CodegenMode;
@@ -13,6 +20,9 @@
 #[cfg(feature = "jit")]
 mod jit;
 
+#[cfg(feature = "jit")]
+pub use jit::{jit_function_code, run_jit_entry};
+
 pub(crate) fn codegen_crate(
     tcx: TyCtxt<'_>,
     metadata: EncodedMetadata,
@@ -21,19 +31,30 @@ pub(crate) fn codegen_crate(
 ) -> Box<dyn Any> {
     tcx.sess.abort_if_errors();
 
+    // Other crates would refer to the items of the crate using the unprefixed symbols.
+    if config.symbol_prefix.is_some()
+        && tcx.sess.crate_types().iter().any(|crate_type| {
+            matches!(
+                crate_type,
+                CrateType::Rlib | CrateType::Dylib | CrateType::ProcMacro
+            )
+        })
+    {
+        tcx.sess.fatal(
+            "the `symbol_prefix` option is not supported for crate types other Rust crates can \
+             depend on",
+        );
+    }
+
+    if config.print_cgu_names {
+        return print_cgu_names(tcx, metadata);
+    }
+
     match config.codegen_mode {
-        CodegenMode::Aot => aot::run_aot(tcx, metadata, need_metadata_module),
+        CodegenMode::Aot => aot::run_aot(tcx, &config, metadata, need_metadata_module),
         CodegenMode::Jit | CodegenMode::JitLazy => {
-            let is_executable = tcx
-                .sess
-                .crate_types()
-                .contains(&rustc_session::config::CrateType::Executable);
-            if !is_executable {
-                tcx.sess.fatal("can't jit non-executable crate");
-            }
-
             #[cfg(feature = "jit")]
-            let _: ! = jit::run_jit(tcx, config.codegen_mode);
+            return jit::run_jit(tcx, &config, metadata);
 
             #[cfg(not(feature = "jit"))]
             tcx.sess
@@ -42,32 +63,340 @@ pub(crate) fn codegen_crate(
     }
 }
 
+/// Prints the names of all codegen units sorted by name. Returns codegen results without any
+/// modules, which [`CraneliftCodegenBackend::link`](crate::CraneliftCodegenBackend) doesn't link.
+fn print_cgu_names(tcx: TyCtxt<'_>, metadata: EncodedMetadata) -> Box<dyn Any> {
+    use std::io::Write;
+
+    let mut cgu_names = collect_and_partition_mono_items(tcx)
+        .iter()
+        .map(|cgu| cgu.name().as_str().to_string())
+        .collect::<Vec<_>>();
+    cgu_names.sort();
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for cgu_name in cgu_names {
+        writeln!(stdout, "{}", cgu_name).unwrap();
+    }
+    stdout.flush().unwrap();
+
+    Box::new((
+        CodegenResults {
+            crate_name: tcx.crate_name(LOCAL_CRATE),
+            modules: vec![],
+            allocator_module: None,
+            metadata_module: None,
+            metadata,
+            windows_subsystem: None,
+            linker_info: LinkerInfo::new(tcx),
+            crate_info: CrateInfo::new(tcx),
+        },
+        FxHashMap::<WorkProductId, WorkProduct>::default(),
+    ))
+}
+
+/// Collects and partitions the mono items of the local crate. Errors reported during collection,
+/// like constants failing to evaluate once their generic parameters are known, abort compilation
+/// here, as codegening the items referring to them would ICE.
+fn collect_and_partition_mono_items<'tcx>(tcx: TyCtxt<'tcx>) -> &'tcx [CodegenUnit<'tcx>] {
+    let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
+    tcx.sess.abort_if_errors();
+    cgus
+}
+
+/// Returns the name of every codegen unit of the local crate together with the symbol name,
+/// linkage and visibility of every function and static it contains, in the order they would be
+/// codegened. Global asm items are omitted as they don't have a symbol name.
+///
+/// This only partitions the mono items, so it can be used to analyse how work is distributed
+/// between codegen units and where symbols are placed without codegening anything.
+pub fn cgu_item_report(tcx: TyCtxt<'_>) -> Vec<(String, Vec<(String, RLinkage, Visibility)>)> {
+    let cgus = collect_and_partition_mono_items(tcx);
+    cgus.iter()
+        .map(|cgu| {
+            let items = cgu
+                .items_in_deterministic_order(tcx)
+                .into_iter()
+                .filter_map(|(mono_item, (linkage, visibility))| {
+                    let instance = match mono_item {
+                        MonoItem::Fn(instance) => instance,
+                        MonoItem::Static(def_id) => Instance::mono(tcx, def_id),
+                        MonoItem::GlobalAsm(_) => return None,
+                    };
+                    Some((
+                        crate::linkage::symbol_name(tcx, None, instance),
+                        linkage,
+                        visibility,
+                    ))
+                })
+                .collect();
+            (cgu.name().as_str().to_string(), items)
+        })
+        .collect()
+}
+
+/// Codegen the given mono items into `module` without going through the AOT or JIT driver, for
+/// example to test or fuzz the codegen of specific items in isolation. The linkage and visibility
+/// of every item are the ones assigned by the partitioning of rustc, see [`cgu_item_report`].
+///
+/// All functions and statics are declared before any of them is codegened, so the items may refer
+/// to each other. Items referred to which are not part of `mono_items` are left as imports. No
+/// debuginfo or unwind info is emitted. The returned string contains the assembly of all
+/// `global_asm!` items, which has to be assembled separately.
+pub fn codegen_mono_items<'tcx, M: Module>(
+    tcx: TyCtxt<'tcx>,
+    module: M,
+    mono_items: &[(MonoItem<'tcx>, (RLinkage, Visibility))],
+) -> (M, String) {
+    let mut cx = crate::CodegenCx::new(tcx, module, false, false);
+    predefine_mono_items(&mut cx, mono_items);
+    for &(mono_item, (linkage, visibility)) in mono_items {
+        let linkage = crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
+        codegen_mono_item(&mut cx, mono_item, linkage);
+    }
+    let (module, global_asm, _debug_context, _unwind_context, _stats) = cx.finalize();
+    (module, global_asm)
+}
+
+/// Codegen a single mono item into the module of `cx`.
+///
+/// Functions and statics still need to be declared using [`predefine_mono_items`] first.
+pub(crate) fn codegen_mono_item<'tcx, M: Module>(
+    cx: &mut crate::CodegenCx<'tcx, M>,
+    mono_item: MonoItem<'tcx>,
+    linkage: Linkage,
+) {
+    match mono_item {
+        MonoItem::Fn(inst) => {
+            let tcx = cx.tcx;
+            profile(tcx, "codegen fn", || {
+                crate::base::codegen_fn(cx, inst, linkage)
+            });
+        }
+        MonoItem::Static(def_id) => crate::constant::codegen_static(&mut cx.constants_cx, def_id),
+        MonoItem::GlobalAsm(item_id) => {
+            let item = cx.tcx.hir().item(item_id);
+            if let rustc_hir::ItemKind::GlobalAsm(rustc_hir::GlobalAsm { asm }) = item.kind {
+                cx.global_asm.push_str(&*asm.as_str());
+                cx.global_asm.push_str("\n\n");
+            } else {
+                bug!("Expected GlobalAsm found {:?}", item);
+            }
+        }
+    }
+}
+
 fn predefine_mono_items<'tcx>(
     cx: &mut crate::CodegenCx<'tcx, impl Module>,
     mono_items: &[(MonoItem<'tcx>, (RLinkage, Visibility))],
 ) {
-    cx.tcx.sess.time("predefine functions", || {
-        for &(mono_item, (linkage, visibility)) in mono_items {
-            match mono_item {
-                MonoItem::Fn(instance) => {
-                    let name = cx.tcx.symbol_name(instance).name.to_string();
+    let tcx = cx.tcx;
+    // Errors of previously codegened codegen units are already accounted for when using
+    // `keep_going`, so only abort for errors reported here.
+    let err_count = tcx.sess.err_count();
+    profile(tcx, "predefine functions", || {
+        // Computing symbol names and signatures doesn't need access to the module, so it is done
+        // in parallel when rustc is built with the parallel compiler. Only declaring the functions
+        // has to happen serially.
+        let triple = cx.module.isa().triple().clone();
+        let symbol_prefix = cx.symbol_prefix.as_deref();
+        let declarations = par_iter(mono_items)
+            .map(|&(mono_item, (linkage, visibility))| {
+                let instance = match mono_item {
+                    MonoItem::Fn(instance) => instance,
+                    MonoItem::Static(def_id) => Instance::mono(tcx, def_id),
+                    MonoItem::GlobalAsm(_) => return None,
+                };
+                let name = crate::linkage::symbol_name(tcx, symbol_prefix, instance);
+                let func_decl = if let MonoItem::Fn(instance) = mono_item {
                     let _inst_guard = crate::PrintOnPanic(|| format!("{:?} {}", instance, name));
-                    let sig = get_function_sig(cx.tcx, cx.module.isa().triple(), instance);
-                    let linkage = crate::linkage::get_clif_linkage(mono_item, linkage, visibility);
-                    cx.module.declare_function(&name, linkage, &sig).unwrap();
+                    let sig = get_function_sig(tcx, &triple, instance);
+                    let linkage = if tcx
+                        .codegen_fn_attrs(instance.def_id())
+                        .flags
+                        .contains(CodegenFnAttrFlags::NAKED)
+                    {
+                        // Defined using global asm by `inline_asm::codegen_naked_fn`.
+                        Linkage::Import
+                    } else {
+                        crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility)
+                    };
+                    Some((instance, sig, linkage))
+                } else {
+                    None
+                };
+                Some((mono_item, name, func_decl))
+            })
+            .collect::<Vec<_>>();
+
+        let mut symbols = FxHashMap::<String, MonoItem<'tcx>>::default();
+        for (mono_item, name, func_decl) in declarations.into_iter().flatten() {
+            if let Some(prev_mono_item) = symbols.insert(name.clone(), mono_item) {
+                report_duplicate_symbol(tcx, &name, prev_mono_item, mono_item);
+                continue;
+            }
+
+            if let Some((instance, sig, linkage)) = func_decl {
+                match cx.module.declare_function(&name, linkage, &sig) {
+                    Ok(func_id) => {
+                        if cx.verify_ir && linkage != Linkage::Import {
+                            cx.undefined_functions.insert(func_id);
+                        }
+                    }
+                    Err(err) => {
+                        tcx.sess.span_err(
+                            tcx.def_span(instance.def_id()),
+                            &format!("failed to declare `{}`: {}", name, err),
+                        );
+                    }
                 }
-                MonoItem::Static(_) | MonoItem::GlobalAsm(_) => {}
             }
         }
     });
+    if tcx.sess.err_count() > err_count {
+        rustc_errors::FatalError.raise();
+    }
 }
 
-fn time<R>(tcx: TyCtxt<'_>, name: &'static str, f: impl FnOnce() -> R) -> R {
-    if std::env::var("CG_CLIF_DISPLAY_CG_TIME")
+/// Reports symbols defined by different mono items in different codegen units, like two functions
+/// using the same `#[export_name]` in different modules. Such duplicates within a single codegen
+/// unit are reported by [`predefine_mono_items`]. Copies of the same mono item in multiple codegen
+/// units are fine, as they are local to their codegen unit.
+fn check_duplicate_mono_item_symbols<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    symbol_prefix: Option<&str>,
+    cgus: &[CodegenUnit<'tcx>],
+) {
+    if cgus.len() <= 1 {
+        return;
+    }
+
+    let mut symbols = FxHashMap::<String, MonoItem<'tcx>>::default();
+    for cgu in cgus {
+        for (mono_item, _) in cgu.items_in_deterministic_order(tcx) {
+            let instance = match mono_item {
+                MonoItem::Fn(instance) => instance,
+                MonoItem::Static(def_id) => Instance::mono(tcx, def_id),
+                MonoItem::GlobalAsm(_) => continue,
+            };
+            let name = crate::linkage::symbol_name(tcx, symbol_prefix, instance);
+            match symbols.get(&name) {
+                Some(&prev_mono_item) if prev_mono_item != mono_item => {
+                    report_duplicate_symbol(tcx, &name, prev_mono_item, mono_item);
+                }
+                Some(_) => {}
+                None => {
+                    symbols.insert(name, mono_item);
+                }
+            }
+        }
+    }
+    tcx.sess.abort_if_errors();
+}
+
+fn report_duplicate_symbol<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    name: &str,
+    prev_mono_item: MonoItem<'tcx>,
+    mono_item: MonoItem<'tcx>,
+) {
+    let span_of = |mono_item: MonoItem<'tcx>| match mono_item {
+        MonoItem::Fn(instance) => tcx.def_span(instance.def_id()),
+        MonoItem::Static(def_id) => tcx.def_span(def_id),
+        MonoItem::GlobalAsm(_) => unreachable!(),
+    };
+    tcx.sess
+        .struct_span_err(
+            span_of(mono_item),
+            &format!("symbol `{}` is already defined", name),
+        )
+        .span_note(span_of(prev_mono_item), "previous definition here")
+        .emit();
+}
+
+/// Prints how many mono items have been codegened so far when the `progress` option is set. Can
+/// be shared between threads codegening different codegen units.
+pub(crate) struct Progress {
+    crate_name: String,
+    total: usize,
+    interval: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    fn new(tcx: TyCtxt<'_>, config: &crate::BackendConfig, total: usize) -> Option<Self> {
+        Some(Progress {
+            crate_name: tcx.crate_name(LOCAL_CRATE).to_string(),
+            total,
+            interval: config.progress? as usize,
+            done: AtomicUsize::new(0),
+        })
+    }
+
+    /// Records that `count` more mono items have been codegened or reused from the incremental
+    /// cache and reports the progress every `interval` items.
+    pub(crate) fn items_done(&self, count: usize) {
+        let prev = self.done.fetch_add(count, Ordering::Relaxed);
+        let done = prev + count;
+        if done / self.interval > prev / self.interval || (done == self.total && count != 0) {
+            eprintln!(
+                "[{}] codegened {}/{} mono items ({}%)",
+                self.crate_name,
+                done,
+                self.total,
+                done * 100 / self.total.max(1),
+            );
+        }
+    }
+}
+
+fn should_display_cg_time() -> bool {
+    std::env::var("CG_CLIF_DISPLAY_CG_TIME")
         .as_ref()
         .map(|val| &**val)
         == Ok("1")
-    {
+}
+
+fn print_stats(
+    tcx: TyCtxt<'_>,
+    module_name: &str,
+    stats: crate::CodegenStats,
+    object: Option<&std::path::Path>,
+) {
+    use object::{Object, ObjectSection};
+
+    // Relocations are only known once the object file is written.
+    let relocations = object.and_then(|object| {
+        let data = std::fs::read(object).ok()?;
+        let obj = object::File::parse(&data).ok()?;
+        Some(
+            obj.sections()
+                .map(|section| section.relocations().count())
+                .sum::<usize>(),
+        )
+    });
+
+    println!(
+        "[{:<30}: {}] functions: {}, cached functions: {}, code bytes: {}, data objects: {}, \
+         relocations: {}",
+        tcx.crate_name(LOCAL_CRATE),
+        module_name,
+        stats.functions,
+        stats.cached_functions,
+        stats.code_bytes,
+        stats.data_objects,
+        relocations.map_or_else(
+            || "unknown".to_string(),
+            |relocations| relocations.to_string()
+        ),
+    );
+}
+
+/// Times the phase `name` of the compilation of the whole crate. The phase is reported by
+/// `-Ztime-passes` and recorded by `-Zself-profile` like the phases of rustc itself.
+fn time<R>(tcx: TyCtxt<'_>, name: &'static str, f: impl FnOnce() -> R) -> R {
+    if should_display_cg_time() {
         println!("[{:<30}: {}] start", tcx.crate_name(LOCAL_CRATE), name);
         let before = std::time::Instant::now();
         let res = tcx.sess.time(name, f);
@@ -83,3 +412,11 @@ fn time<R>(tcx: TyCtxt<'_>, name: &'static str, f: impl FnOnce() -> R) -> R {
         tcx.sess.time(name, f)
     }
 }
+
+/// Records the activity `name`, which happens for every codegen unit or function, for
+/// `-Zself-profile`. Unlike [`time`] it isn't reported by `-Ztime-passes`, which would otherwise
+/// print a line for every function. The LLVM backend of rustc does the same.
+pub(crate) fn profile<R>(tcx: TyCtxt<'_>, name: &'static str, f: impl FnOnce() -> R) -> R {
+    let _timer = tcx.prof.generic_activity(name);
+    f()
+}
@@ -0,0 +1,97 @@
+//! Machine readable list of the capabilities of this build of cg_clif for the current target.
+//! Printed for `--print target-features`, which the `cg_clif` driver also accepts as
+//! `--print cg-clif-features`.
+
+use std::str::FromStr;
+
+use rustc_session::Session;
+
+use crate::{BackendConfig, CodegenMode};
+
+/// Target triples for which support is probed. Whether a triple is supported depends on the
+/// Cranelift backends this build of cg_clif was compiled with.
+const PROBED_TRIPLES: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "x86_64-apple-darwin",
+    "x86_64-pc-windows-gnu",
+    "aarch64-unknown-linux-gnu",
+    "i686-unknown-linux-gnu",
+    "armv7-unknown-linux-gnueabihf",
+];
+
+/// Returns a list of `(name, value)` pairs describing the capabilities of this build for the
+/// target of `sess`. Multiple values are separated by a comma. The names are stable, new
+/// capabilities may be added in the future.
+pub(crate) fn supported_features(
+    sess: &Session,
+    config: &BackendConfig,
+) -> Vec<(&'static str, String)> {
+    let targets = PROBED_TRIPLES
+        .iter()
+        .copied()
+        .filter(|triple| is_supported(target_lexicon::Triple::from_str(triple).unwrap()))
+        .collect::<Vec<_>>();
+
+    let target_triple = crate::target_triple(sess);
+    let mut features = vec![
+        (
+            "backend",
+            match crate::backend_variant() {
+                cranelift_codegen::isa::BackendVariant::Legacy => "legacy",
+                _ => "machinst",
+            }
+            .to_string(),
+        ),
+        ("targets", targets.join(",")),
+        ("target", target_triple.to_string()),
+        (
+            "target_supported",
+            is_supported(target_triple.clone()).to_string(),
+        ),
+    ];
+    if !is_supported(target_triple.clone()) {
+        return features;
+    }
+
+    let isa = crate::build_isa(
+        sess,
+        matches!(config.codegen_mode, CodegenMode::Jit | CodegenMode::JitLazy)
+            && !config.disable_jit_host_features,
+        config.test_break_ir.is_some(),
+    );
+    features.extend(vec![
+        ("isa", isa.name().to_string()),
+        ("pointer_width", isa.pointer_bits().to_string()),
+        ("opt_level", isa.flags().opt_level().to_string()),
+        ("pic", isa.flags().is_pic().to_string()),
+        // The JIT can only run code for the host.
+        (
+            "jit",
+            (cfg!(feature = "jit") && target_triple == target_lexicon::Triple::host()).to_string(),
+        ),
+        // `asm!` and `global_asm!` are assembled using an external assembler, see `inline_asm`.
+        (
+            "inline_asm",
+            (cfg!(feature = "inline_asm")
+                && !sess.target.is_like_osx
+                && !sess.target.is_like_windows)
+                .to_string(),
+        ),
+        // Atomic instructions are emulated using a global lock, see `atomic_shim`.
+        ("atomics", "global-lock".to_string()),
+        // Only a subset of the SIMD intrinsics is implemented.
+        ("simd", "partial".to_string()),
+        ("unwinding", "false".to_string()),
+        (
+            "unwind_tables",
+            crate::debuginfo::emit_unwind_tables(sess).to_string(),
+        ),
+        // `asm` only produces a disassembly for inspection.
+        ("output_types", "link,obj,metadata,asm,llvm-ir".to_string()),
+    ]);
+    features
+}
+
+fn is_supported(triple: target_lexicon::Triple) -> bool {
+    cranelift_codegen::isa::lookup_variant(triple, crate::backend_variant()).is_ok()
+}
@@ -0,0 +1,242 @@
+//! Prototype of a per-function machine code cache for incremental compilation, enabled using
+//! `-Cllvm-args=function_cache=1`.
+//!
+//! Codegen units are normally reused as a whole, so changing a single function requires all other
+//! functions of the codegen unit to be compiled by Cranelift again. When this cache is enabled the
+//! machine code of every cacheable function is stored in the incremental session directory. When
+//! the codegen unit has to be codegened again, functions whose Cranelift IR didn't change skip
+//! compilation by Cranelift and reuse the cached machine code instead.
+//!
+//! The cache is keyed by a hash of the optimized Cranelift IR rather than by the MIR of the
+//! function, as the MIR doesn't change when for example the layout of a used type changes. This
+//! means that the translation from MIR to Cranelift IR still happens for every function.
+//!
+//! As `cranelift_module` doesn't support defining precompiled functions with relocations, only
+//! functions that don't reference any other function or data object are cacheable. In addition
+//! debuginfo and unwind tables are not cached, so nothing is cached when either of them is
+//! emitted. This means that the cache only has an effect with `-Cdebuginfo=0` and `-Cpanic=abort`.
+
+use std::fmt::{self, Write};
+use std::hash::Hasher;
+use std::path::PathBuf;
+
+use rustc_data_structures::stable_hasher::StableHasher;
+
+use cranelift_codegen::binemit::{
+    Addend, CodeOffset, NullStackMapSink, NullTrapSink, Reloc, RelocSink,
+};
+use cranelift_codegen::ir::{ExternalName, JumpTable, SourceLoc};
+
+use crate::prelude::*;
+
+struct CachedFunction {
+    hash: u64,
+    code: Vec<u8>,
+}
+
+/// The cached functions of a single codegen unit.
+pub(crate) struct FunctionCache {
+    path: PathBuf,
+    /// Functions cached by the previous session.
+    old: FxHashMap<String, CachedFunction>,
+    /// Functions that will be cached for the next session. Functions that are no longer part of
+    /// the codegen unit are dropped from the cache this way.
+    new: FxHashMap<String, CachedFunction>,
+}
+
+impl FunctionCache {
+    /// Loads the function cache of `cgu_name` from the incremental session directory. Returns
+    /// `None` when incremental compilation is disabled.
+    pub(crate) fn load(tcx: TyCtxt<'_>, cgu_name: &str) -> Option<Self> {
+        if tcx.sess.opts.incremental.is_none() {
+            return None;
+        }
+
+        let path = tcx
+            .sess
+            .incr_comp_session_dir()
+            .join(format!("cg_clif_fn_cache-{}.bin", cgu_name));
+        // A missing or corrupted cache only means that all functions have to be compiled again.
+        let old = std::fs::read(&path)
+            .ok()
+            .and_then(|data| decode(&data))
+            .unwrap_or_default();
+
+        Some(FunctionCache {
+            path,
+            old,
+            new: FxHashMap::default(),
+        })
+    }
+
+    /// Writes all functions looked up or inserted during this session to the incremental session
+    /// directory.
+    pub(crate) fn save(self, tcx: TyCtxt<'_>) {
+        if let Err(err) = std::fs::write(&self.path, encode(&self.new)) {
+            tcx.sess.warn(&format!(
+                "failed to write function cache {}: {}",
+                self.path.display(),
+                err
+            ));
+        }
+    }
+
+    /// Returns the cache key of `func` or `None` when the function can't be cached.
+    pub(crate) fn cache_key(func: &Function) -> Option<u64> {
+        // Any external reference would need a relocation.
+        if !func.dfg.ext_funcs.is_empty() || !func.global_values.is_empty() {
+            return None;
+        }
+
+        Some(ir_hash(func))
+    }
+
+    pub(crate) fn lookup(&mut self, symbol_name: &str, hash: u64) -> Option<&[u8]> {
+        match self.old.remove(symbol_name) {
+            Some(cached) if cached.hash == hash => {
+                let cached = self.new.entry(symbol_name.to_string()).or_insert(cached);
+                Some(&cached.code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Compiles `context` and defines `func_id` with the resulting machine code, which is added to
+    /// the cache.
+    pub(crate) fn compile_and_insert(
+        &mut self,
+        module: &mut impl Module,
+        func_id: FuncId,
+        context: &mut Context,
+        symbol_name: &str,
+        hash: u64,
+    ) -> cranelift_module::ModuleResult<cranelift_module::ModuleCompiledFunction> {
+        let mut code = Vec::new();
+        let mut reloc_sink = AssertNoRelocSink;
+        context.compile_and_emit(
+            module.isa(),
+            &mut code,
+            &mut reloc_sink,
+            &mut NullTrapSink {},
+            &mut NullStackMapSink {},
+        )?;
+
+        let compiled_function = module.define_function_bytes(func_id, &code)?;
+        self.new
+            .insert(symbol_name.to_string(), CachedFunction { hash, code });
+        Ok(compiled_function)
+    }
+}
+
+/// Returns a hash of the Cranelift IR of `func`. Functions with the same hash compile to the same
+/// machine code, except for the targets of their relocations.
+pub(crate) fn ir_hash(func: &Function) -> u64 {
+    let mut hasher = StableHasher::new();
+
+    // The name of the function is not stable across sessions and doesn't influence the generated
+    // code, so it isn't hashed. Most IR entities don't implement `Hash`, so their textual form is
+    // hashed instead.
+    let mut writer = HashWriter(&mut hasher);
+    write!(writer, "{:?}", func.signature).unwrap();
+    for (ss, data) in func.stack_slots.iter() {
+        write!(writer, "{}={};", ss, data).unwrap();
+    }
+    for (gv, data) in func.global_values.iter() {
+        write!(writer, "{}={};", gv, data).unwrap();
+    }
+    for (sig, data) in func.dfg.signatures.iter() {
+        write!(writer, "{}={:?};", sig, data).unwrap();
+    }
+    for (fn_ref, data) in func.dfg.ext_funcs.iter() {
+        write!(writer, "{}={};", fn_ref, data).unwrap();
+    }
+    for (jt, data) in func.jump_tables.iter() {
+        write!(writer, "{}={};", jt, data).unwrap();
+    }
+    for block in func.layout.blocks() {
+        write!(writer, "{}(", block).unwrap();
+        for &param in func.dfg.block_params(block) {
+            write!(writer, "{}:{},", param, func.dfg.value_type(param)).unwrap();
+        }
+        write!(writer, "):").unwrap();
+        for inst in func.layout.block_insts(block) {
+            write!(writer, "{};", func.dfg.display_inst(inst, None)).unwrap();
+        }
+    }
+
+    hasher.finish()
+}
+
+/// Feeds everything written to it into a hasher without collecting it into a string first.
+struct HashWriter<'a>(&'a mut StableHasher);
+
+impl fmt::Write for HashWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Functions without external references shouldn't need any relocations.
+struct AssertNoRelocSink;
+
+impl RelocSink for AssertNoRelocSink {
+    fn reloc_external(
+        &mut self,
+        _offset: CodeOffset,
+        _srcloc: SourceLoc,
+        _reloc: Reloc,
+        name: &ExternalName,
+        _addend: Addend,
+    ) {
+        bug!("cached function references {}", name);
+    }
+
+    // Constant pools and jump tables are part of the function itself.
+    fn reloc_constant(&mut self, _offset: CodeOffset, _reloc: Reloc, _constant_offset: u32) {}
+
+    fn reloc_jt(&mut self, _offset: CodeOffset, _reloc: Reloc, _jt: JumpTable) {}
+}
+
+// The cache file consists of entries with the following layout, with all integers in little
+// endian:
+//
+// u32 symbol name length, symbol name, u64 hash, u32 code length, code
+
+fn encode(functions: &FxHashMap<String, CachedFunction>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (symbol_name, cached) in functions {
+        data.extend_from_slice(&(symbol_name.len() as u32).to_le_bytes());
+        data.extend_from_slice(symbol_name.as_bytes());
+        data.extend_from_slice(&cached.hash.to_le_bytes());
+        data.extend_from_slice(&(cached.code.len() as u32).to_le_bytes());
+        data.extend_from_slice(&cached.code);
+    }
+    data
+}
+
+fn decode(mut data: &[u8]) -> Option<FxHashMap<String, CachedFunction>> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        if data.len() < len {
+            return None;
+        }
+        let (res, rest) = data.split_at(len);
+        *data = rest;
+        Some(res)
+    }
+
+    fn take_u32(data: &mut &[u8]) -> Option<usize> {
+        Some(u32::from_le_bytes(take(data, 4)?.try_into().unwrap()) as usize)
+    }
+
+    let mut functions = FxHashMap::default();
+    while !data.is_empty() {
+        let name_len = take_u32(&mut data)?;
+        let symbol_name = std::str::from_utf8(take(&mut data, name_len)?).ok()?;
+        let hash = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        let code_len = take_u32(&mut data)?;
+        let code = take(&mut data, code_len)?.to_vec();
+        functions.insert(symbol_name.to_string(), CachedFunction { hash, code });
+    }
+    Some(functions)
+}
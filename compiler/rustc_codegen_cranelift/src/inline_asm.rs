@@ -99,7 +99,7 @@ pub(crate) fn codegen_inline_asm<'tcx>(
     fx.inline_asm_index += 1;
     let asm_name = format!(
         "{}__inline_asm_{}",
-        fx.tcx.symbol_name(fx.instance).name,
+        crate::linkage::symbol_name(fx.tcx, fx.cx.symbol_prefix.as_deref(), fx.instance),
         inline_asm_index
     );
 
@@ -117,6 +117,92 @@ pub(crate) fn codegen_inline_asm<'tcx>(
     call_inline_asm(fx, &asm_name, slot_size, inputs, outputs);
 }
 
+/// Codegen a `#[naked]` function by emitting its `asm!` block as the whole function body using
+/// global asm. Cranelift always generates a prologue and epilogue, so the function is not compiled
+/// by Cranelift at all. It has to be declared as import in the Cranelift module.
+pub(crate) fn codegen_naked_fn<'tcx>(
+    cx: &mut crate::CodegenCx<'tcx, impl Module>,
+    instance: Instance<'tcx>,
+    linkage: Linkage,
+) {
+    let tcx = cx.tcx;
+    let mir = tcx.instance_mir(instance.def);
+    let span = tcx.def_span(instance.def_id());
+
+    let start_block = &mir.basic_blocks()[START_BLOCK];
+    let only_storage_markers = start_block.statements.iter().all(|stmt| {
+        matches!(
+            stmt.kind,
+            StatementKind::StorageLive(_) | StatementKind::StorageDead(_) | StatementKind::Nop
+        )
+    });
+    let (template, options) = match start_block.terminator().kind {
+        TerminatorKind::InlineAsm {
+            template,
+            ref operands,
+            options,
+            ..
+        } if only_storage_markers
+            && operands.is_empty()
+            && options.contains(InlineAsmOptions::NORETURN) =>
+        {
+            (template, options)
+        }
+        _ => {
+            tcx.sess.span_err(
+                span,
+                "naked functions must consist of a single `asm!` without operands using \
+                 `options(noreturn)` to be supported by rustc_codegen_cranelift",
+            );
+            return;
+        }
+    };
+
+    if tcx.sess.target.arch != "x86_64" {
+        tcx.sess
+            .span_err(span, "naked functions are only supported on x86_64");
+        return;
+    }
+    // The generated assembler directives are specific to ELF.
+    if crate::target_triple(tcx.sess).binary_format != target_lexicon::BinaryFormat::Elf {
+        tcx.sess
+            .span_err(span, "naked functions are only supported for ELF targets");
+        return;
+    }
+
+    let name = crate::linkage::symbol_name(tcx, cx.symbol_prefix.as_deref(), instance);
+    let mut generated_asm = String::new();
+    // The Cranelift module references the function as import, so it has to be a global symbol to
+    // resolve this reference when linking the global asm into the object file of the module.
+    // Internal functions are made local afterwards.
+    writeln!(generated_asm, ".globl {}", name).unwrap();
+    if linkage != Linkage::Export {
+        writeln!(generated_asm, ".hidden {}", name).unwrap();
+    }
+    if linkage == Linkage::Local {
+        cx.local_asm_symbols.push(name.clone());
+    }
+    writeln!(generated_asm, ".type {},@function", name).unwrap();
+    writeln!(generated_asm, ".section .text.{},\"ax\",@progbits", name).unwrap();
+    writeln!(generated_asm, "{}:", name).unwrap();
+    if !options.contains(InlineAsmOptions::ATT_SYNTAX) {
+        generated_asm.push_str(".intel_syntax noprefix\n");
+    }
+    for piece in template {
+        match piece {
+            InlineAsmTemplatePiece::String(s) => generated_asm.push_str(s),
+            InlineAsmTemplatePiece::Placeholder { .. } => unreachable!("no operands"),
+        }
+    }
+    generated_asm.push('\n');
+    generated_asm.push_str(".att_syntax\n");
+    writeln!(generated_asm, ".size {name}, .-{name}", name = name).unwrap();
+    generated_asm.push_str(".text\n");
+    generated_asm.push_str("\n\n");
+
+    cx.global_asm.push_str(&generated_asm);
+}
+
 fn generate_asm_wrapper(
     asm_name: &str,
     arch: InlineAsmArch,
@@ -17,8 +17,7 @@ pub(crate) fn codegen_llvm_intrinsic_call<'tcx>(
     intrinsic_match! {
         fx, intrinsic, substs, args,
         _ => {
-            fx.tcx.sess.warn(&format!("unsupported llvm intrinsic {}; replacing with trap", intrinsic));
-            crate::trap::trap_unimplemented(fx, intrinsic);
+            crate::trap::trap_unimplemented(fx, format!("unsupported llvm intrinsic {}", intrinsic));
         };
 
         // Used by `_mm_movemask_epi8` and `_mm256_movemask_epi8`
@@ -57,20 +56,12 @@ pub(crate) fn codegen_llvm_intrinsic_call<'tcx>(
                 0 => FloatCC::Equal,
                 1 => FloatCC::LessThan,
                 2 => FloatCC::LessThanOrEqual,
-                7 => {
-                    unimplemented!("Compares corresponding elements in `a` and `b` to see if neither is `NaN`.");
-                }
-                3 => {
-                    unimplemented!("Compares corresponding elements in `a` and `b` to see if either is `NaN`.");
-                }
+                3 => FloatCC::Unordered,
                 4 => FloatCC::NotEqual,
-                5 => {
-                    unimplemented!("not less than");
-                }
-                6 => {
-                    unimplemented!("not less than or equal");
-                }
-                kind => unreachable!("kind {:?}", kind),
+                5 => FloatCC::UnorderedOrGreaterThanOrEqual, // not less than
+                6 => FloatCC::UnorderedOrGreaterThan, // not less than or equal
+                7 => FloatCC::Ordered,
+                kind => fx.tcx.sess.fatal(&format!("invalid comparison kind {} for {}", kind, intrinsic)),
             };
 
             simd_pair_for_each_lane(fx, x, y, ret, |fx, lane_layout, res_lane_layout, x_lane, y_lane| {
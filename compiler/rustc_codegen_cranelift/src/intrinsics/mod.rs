@@ -162,6 +162,21 @@
     }
 }
 
+macro validate_float_type($fx:ident, $intrinsic:ident, $span:ident, $ty:expr) {
+    if !$ty.is_floating_point() {
+        $fx.tcx.sess.span_err(
+            $span,
+            &format!(
+                "invalid monomorphization of `{}` intrinsic: expected basic float type, found `{}`",
+                $intrinsic, $ty
+            ),
+        );
+        // Prevent verifier error
+        crate::trap::trap_unreachable($fx, "compilation should not have succeeded");
+        return;
+    }
+}
+
 macro validate_simd_type($fx:ident, $intrinsic:ident, $span:ident, $ty:expr) {
     if !$ty.is_simd() {
         $fx.tcx.sess.span_err($span, &format!("invalid monomorphization of `{}` intrinsic: expected SIMD input type, found non-SIMD `{}`", $intrinsic, $ty));
@@ -171,6 +186,17 @@
     }
 }
 
+macro validate_simd_float_type($fx:ident, $intrinsic:ident, $span:ident, $ty:expr) {
+    validate_simd_type!($fx, $intrinsic, $span, $ty);
+    let (_, lane_ty) = $ty.simd_size_and_type($fx.tcx);
+    if !lane_ty.is_floating_point() {
+        $fx.tcx.sess.span_err($span, &format!("invalid monomorphization of `{}` intrinsic: expected SIMD float type, found `{}` with element type `{}`", $intrinsic, $ty, lane_ty));
+        // Prevent verifier error
+        crate::trap::trap_unreachable($fx, "compilation should not have succeeded");
+        return;
+    }
+}
+
 pub(crate) fn clif_vector_type<'tcx>(tcx: TyCtxt<'tcx>, layout: TyAndLayout<'tcx>) -> Option<Type> {
     let (element, count) = match &layout.abi {
         Abi::Vector { element, count } => (element.clone(), *count),
@@ -184,6 +210,57 @@ pub(crate) fn clif_vector_type<'tcx>(tcx: TyCtxt<'tcx>, layout: TyAndLayout<'tcx
     }
 }
 
+/// Applies `bin_op` to all lanes of `x` and `y` at once using a single Cranelift vector
+/// instruction. Returns `false` without emitting anything when this isn't possible for the type of
+/// the lanes, in which case the caller has to fall back to applying it to every lane separately.
+///
+/// Only operations which can be lowered to SSE2 instructions are supported, as SSE2 is available
+/// on every x86_64 cpu. Other targets always use the per-lane fallback.
+fn simd_vector_binop<'tcx>(
+    fx: &mut FunctionCx<'_, 'tcx, impl Module>,
+    bin_op: BinOp,
+    x: CValue<'tcx>,
+    y: CValue<'tcx>,
+    ret: CPlace<'tcx>,
+) -> bool {
+    if fx.tcx.sess.target.arch != "x86_64" || x.layout() != ret.layout() {
+        return false;
+    }
+    let lane_ty = match clif_vector_type(fx.tcx, x.layout()) {
+        Some(vector_ty) => vector_ty.lane_type(),
+        None => return false,
+    };
+    let supported = match bin_op {
+        BinOp::Add | BinOp::Sub => true,
+        // `pmulld` and 64bit lane multiplication need SSE4.1 and AVX-512 respectively.
+        BinOp::Mul => lane_ty.is_float() || lane_ty == types::I16,
+        BinOp::Div => lane_ty.is_float(),
+        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => lane_ty.is_int(),
+        _ => false,
+    };
+    if !supported {
+        return false;
+    }
+
+    let x = x.load_scalar(fx);
+    let y = y.load_scalar(fx);
+    let res = match (bin_op, lane_ty.is_float()) {
+        (BinOp::Add, false) => fx.bcx.ins().iadd(x, y),
+        (BinOp::Add, true) => fx.bcx.ins().fadd(x, y),
+        (BinOp::Sub, false) => fx.bcx.ins().isub(x, y),
+        (BinOp::Sub, true) => fx.bcx.ins().fsub(x, y),
+        (BinOp::Mul, false) => fx.bcx.ins().imul(x, y),
+        (BinOp::Mul, true) => fx.bcx.ins().fmul(x, y),
+        (BinOp::Div, true) => fx.bcx.ins().fdiv(x, y),
+        (BinOp::BitAnd, false) => fx.bcx.ins().band(x, y),
+        (BinOp::BitOr, false) => fx.bcx.ins().bor(x, y),
+        (BinOp::BitXor, false) => fx.bcx.ins().bxor(x, y),
+        _ => unreachable!("{:?} {}", bin_op, lane_ty),
+    };
+    ret.write_cvalue(fx, CValue::by_val(res, ret.layout()));
+    true
+}
+
 fn simd_for_each_lane<'tcx, M: Module>(
     fx: &mut FunctionCx<'_, 'tcx, M>,
     val: CValue<'tcx>,
@@ -423,6 +500,59 @@ fn bool_to_zero_or_max_uint<'tcx>(
     );
 }
 
+fn codegen_intrinsic_override<'tcx>(
+    fx: &mut FunctionCx<'_, 'tcx, impl Module>,
+    intrinsic: &str,
+    intrinsic_override: crate::IntrinsicOverride,
+    args: &[mir::Operand<'tcx>],
+    destination: Option<(CPlace<'tcx>, BasicBlock)>,
+    span: Span,
+) {
+    match intrinsic_override {
+        crate::IntrinsicOverride::Trap => {
+            trap_unreachable(fx, format!("Called overridden intrinsic {}.", intrinsic));
+        }
+        crate::IntrinsicOverride::Call(symbol) => {
+            let args = args
+                .iter()
+                .map(|arg| codegen_operand(fx, arg))
+                .collect::<Vec<_>>();
+            let ret_ty = match destination {
+                Some((place, _)) => place.layout().ty,
+                None => fx.tcx.mk_unit(),
+            };
+            let is_scalar = |ty: Ty<'tcx>| match ty.kind() {
+                ty::Tuple(tys) => tys.types().all(|ty| fx.clif_type(ty).is_some()),
+                _ => fx.clif_type(ty).is_some(),
+            };
+            if !args
+                .iter()
+                .all(|arg| fx.clif_type(arg.layout().ty).is_some())
+                || !is_scalar(ret_ty)
+            {
+                fx.tcx.sess.span_fatal(
+                    span,
+                    &format!(
+                        "intrinsic {} can't be overridden by a call as it has non-scalar arguments \
+                         or return value",
+                        intrinsic
+                    ),
+                );
+            }
+
+            let res = fx.easy_call(&symbol, &args, ret_ty);
+            match destination {
+                Some((place, dest)) => {
+                    place.write_cvalue(fx, res);
+                    let ret_block = fx.get_block(dest);
+                    fx.bcx.ins().jump(ret_block, &[]);
+                }
+                None => trap_unreachable(fx, "Diverging overridden intrinsic returned."),
+            }
+        }
+    }
+}
+
 pub(crate) fn codegen_intrinsic_call<'tcx>(
     fx: &mut FunctionCx<'_, 'tcx, impl Module>,
     instance: Instance<'tcx>,
@@ -436,6 +566,11 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
     let intrinsic = fx.tcx.item_name(def_id).as_str();
     let intrinsic = &intrinsic[..];
 
+    if let Some(intrinsic_override) = fx.cx.intrinsic_overrides.get(intrinsic).cloned() {
+        codegen_intrinsic_override(fx, intrinsic, intrinsic_override, args, destination, span);
+        return;
+    }
+
     let ret = match destination {
         Some((place, _)) => place,
         None => {
@@ -447,7 +582,10 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
                 "transmute" => {
                     crate::base::codegen_panic(fx, "Transmuting to uninhabited type.", span);
                 }
-                _ => unimplemented!("unsupported instrinsic {}", intrinsic),
+                _ => fx
+                    .tcx
+                    .sess
+                    .span_fatal(span, &format!("unsupported intrinsic {}", intrinsic)),
             }
             return;
         }
@@ -497,6 +635,10 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
         truncf64(flt) -> f64 => trunc,
         roundf32(flt) -> f32 => roundf,
         roundf64(flt) -> f64 => round,
+        rintf32(flt) -> f32 => rintf,
+        rintf64(flt) -> f64 => rint,
+        nearbyintf32(flt) -> f32 => nearbyintf,
+        nearbyintf64(flt) -> f64 => nearbyint,
 
         // trigonometry
         sinf32(flt) -> f32 => sinf,
@@ -529,6 +671,9 @@ pub(crate) fn codegen_intrinsic_call<'tcx>(
                 count
             };
 
+            // These are always real calls to `memcpy` and `memmove`, which is also what
+            // `#![no_builtins]` crates expect. When switching to emit_small_mem{cpy,move} for
+            // small constant sizes, keep emitting calls for those crates.
             if intrinsic.contains("nonoverlapping") {
                 // FIXME emit_small_memcpy
                 fx.bcx.call_memcpy(fx.cx.module.target_config(), dst, src, byte_amount);
@@ -1066,7 +1211,9 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
         };
 
         try, (v f, v data, v _catch_fn) {
-            // FIXME once unwinding is supported, change this to actually catch panics
+            // FIXME once unwinding is supported, change this to actually catch panics. The unwind
+            // tables of JIT compiled code are already registered by `UnwindContext::register_jit`,
+            // but without landing pads a panic can never reach `_catch_fn`.
             let f_sig = fx.bcx.func.import_signature(Signature {
                 call_conv: CallConv::triple_default(fx.triple()),
                 params: vec![AbiParam::new(fx.bcx.func.dfg.value_type(data))],
@@ -1081,6 +1228,7 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
         };
 
         fadd_fast | fsub_fast | fmul_fast | fdiv_fast | frem_fast, (c x, c y) {
+            validate_float_type!(fx, intrinsic, span, x.layout().ty);
             let res = crate::num::codegen_float_binop(fx, match intrinsic {
                 "fadd_fast" => BinOp::Add,
                 "fsub_fast" => BinOp::Sub,
@@ -1091,7 +1239,9 @@ fn swap(bcx: &mut FunctionBuilder<'_>, v: Value) -> Value {
             }, x, y);
             ret.write_cvalue(fx, res);
         };
-        float_to_int_unchecked, (v f) {
+        float_to_int_unchecked, (c f) {
+            validate_float_type!(fx, intrinsic, span, f.layout().ty);
+            let f = f.load_scalar(fx);
             let res = crate::cast::clif_int_or_float_cast(
                 fx,
                 f,
@@ -146,10 +146,6 @@ pub(super) fn codegen_simd_intrinsic_call<'tcx>(
             let idx_const = if let Some(idx_const) = crate::constant::mir_operand_get_const_val(fx, idx) {
                 idx_const
             } else {
-                fx.tcx.sess.span_warn(
-                    span,
-                    "Index argument for `simd_extract` is not a constant",
-                );
                 let res = crate::trap::trap_unimplemented_ret_value(
                     fx,
                     ret.layout(),
@@ -171,19 +167,27 @@ pub(super) fn codegen_simd_intrinsic_call<'tcx>(
 
         simd_add, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_flt_binop!(fx, iadd|fadd(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::Add, x, y, ret) {
+                simd_int_flt_binop!(fx, iadd|fadd(x, y) -> ret);
+            }
         };
         simd_sub, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_flt_binop!(fx, isub|fsub(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::Sub, x, y, ret) {
+                simd_int_flt_binop!(fx, isub|fsub(x, y) -> ret);
+            }
         };
         simd_mul, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_flt_binop!(fx, imul|fmul(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::Mul, x, y, ret) {
+                simd_int_flt_binop!(fx, imul|fmul(x, y) -> ret);
+            }
         };
         simd_div, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_flt_binop!(fx, udiv|sdiv|fdiv(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::Div, x, y, ret) {
+                simd_int_flt_binop!(fx, udiv|sdiv|fdiv(x, y) -> ret);
+            }
         };
         simd_shl, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
@@ -195,19 +199,25 @@ pub(super) fn codegen_simd_intrinsic_call<'tcx>(
         };
         simd_and, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_binop!(fx, band(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::BitAnd, x, y, ret) {
+                simd_int_binop!(fx, band(x, y) -> ret);
+            }
         };
         simd_or, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_binop!(fx, bor(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::BitOr, x, y, ret) {
+                simd_int_binop!(fx, bor(x, y) -> ret);
+            }
         };
         simd_xor, (c x, c y) {
             validate_simd_type!(fx, intrinsic, span, x.layout().ty);
-            simd_int_binop!(fx, bxor(x, y) -> ret);
+            if !simd_vector_binop(fx, BinOp::BitXor, x, y, ret) {
+                simd_int_binop!(fx, bxor(x, y) -> ret);
+            }
         };
 
         simd_fma, (c a, c b, c c) {
-            validate_simd_type!(fx, intrinsic, span, a.layout().ty);
+            validate_simd_float_type!(fx, intrinsic, span, a.layout().ty);
             assert_eq!(a.layout(), b.layout());
             assert_eq!(a.layout(), c.layout());
             let layout = a.layout();
@@ -231,11 +241,11 @@ pub(super) fn codegen_simd_intrinsic_call<'tcx>(
         };
 
         simd_fmin, (c x, c y) {
-            validate_simd_type!(fx, intrinsic, span, x.layout().ty);
+            validate_simd_float_type!(fx, intrinsic, span, x.layout().ty);
             simd_flt_binop!(fx, fmin(x, y) -> ret);
         };
         simd_fmax, (c x, c y) {
-            validate_simd_type!(fx, intrinsic, span, x.layout().ty);
+            validate_simd_float_type!(fx, intrinsic, span, x.layout().ty);
             simd_flt_binop!(fx, fmax(x, y) -> ret);
         };
 
@@ -6,18 +6,20 @@
     never_type,
     try_blocks,
     hash_drain_filter,
-    str_split_once
+    str_split_once,
+    once_cell
 )]
 #![warn(rust_2018_idioms)]
 #![warn(unused_lifetimes)]
 #![warn(unreachable_pub)]
 
-#[cfg(feature = "jit")]
+#[cfg(any(feature = "jit", unix))]
 extern crate libc;
 extern crate snap;
 #[macro_use]
 extern crate rustc_middle;
 extern crate rustc_ast;
+extern crate rustc_attr;
 extern crate rustc_codegen_ssa;
 extern crate rustc_data_structures;
 extern crate rustc_errors;
@@ -25,6 +27,7 @@
 extern crate rustc_hir;
 extern crate rustc_incremental;
 extern crate rustc_index;
+extern crate rustc_mir;
 extern crate rustc_session;
 extern crate rustc_span;
 extern crate rustc_target;
@@ -34,7 +37,10 @@
 extern crate rustc_driver;
 
 use std::any::Any;
+use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use rustc_codegen_ssa::traits::CodegenBackend;
 use rustc_codegen_ssa::CodegenResults;
@@ -42,7 +48,7 @@
 use rustc_middle::dep_graph::{WorkProduct, WorkProductId};
 use rustc_middle::middle::cstore::{EncodedMetadata, MetadataLoader};
 use rustc_middle::ty::query::Providers;
-use rustc_session::config::OutputFilenames;
+use rustc_session::config::{OutputFilenames, PrintRequest};
 use rustc_session::Session;
 
 use cranelift_codegen::settings::{self, Configurable};
@@ -50,6 +56,9 @@
 use crate::constant::ConstantCx;
 use crate::prelude::*;
 
+pub use crate::driver::{cgu_item_report, codegen_mono_items};
+#[cfg(feature = "jit")]
+pub use crate::driver::{jit_function_code, run_jit_entry};
 mod abi;
 mod allocator;
 mod analyze;
@@ -58,12 +67,16 @@
 mod backend;
 mod base;
 mod cast;
+mod cgu_graph;
 mod codegen_i128;
 mod common;
 mod constant;
+mod coverage;
 mod debuginfo;
 mod discriminant;
 mod driver;
+mod features;
+mod function_cache;
 mod inline_asm;
 mod intrinsics;
 mod linkage;
@@ -73,6 +86,8 @@
 mod optimize;
 mod pointer;
 mod pretty_clif;
+mod size_report;
+mod symbol_dump;
 mod toolchain;
 mod trap;
 mod unsize;
@@ -94,7 +109,7 @@ mod prelude {
     };
     pub(crate) use rustc_target::abi::{Abi, LayoutOf, Scalar, Size, VariantIdx};
 
-    pub(crate) use rustc_data_structures::fx::FxHashMap;
+    pub(crate) use rustc_data_structures::fx::{FxHashMap, FxHashSet};
 
     pub(crate) use rustc_index::vec::Idx;
 
@@ -130,6 +145,16 @@ fn drop(&mut self) {
     }
 }
 
+/// Statistics about the functions and data objects defined by a [`CodegenCx`].
+#[derive(Copy, Clone, Debug, Default)]
+struct CodegenStats {
+    functions: usize,
+    /// Functions whose machine code was reused from the function cache.
+    cached_functions: usize,
+    code_bytes: u64,
+    data_objects: usize,
+}
+
 struct CodegenCx<'tcx, M: Module> {
     tcx: TyCtxt<'tcx>,
     module: M,
@@ -139,16 +164,63 @@ struct CodegenCx<'tcx, M: Module> {
     vtables: FxHashMap<(Ty<'tcx>, Option<ty::PolyExistentialTraitRef<'tcx>>), DataId>,
     debug_context: Option<DebugContext<'tcx>>,
     unwind_context: UnwindContext<'tcx>,
+    /// Disassembly of all defined functions when `--emit asm` is passed.
+    asm_output: Option<String>,
+    /// Cranelift IR of all defined functions when `--emit llvm-ir` is passed.
+    ir_output: Option<String>,
+    /// Coverage map of all instrumented functions when the `coverage` option is enabled.
+    coverage: Option<crate::coverage::CoverageMap>,
+    /// Run the Cranelift verifier on the final IR of every function before compiling it.
+    verify_ir: bool,
+    /// Functions declared by `predefine_mono_items` which haven't been defined yet. Only tracked
+    /// when `verify_ir` is set. Any function left at [`CodegenCx::finalize`] is reported.
+    undefined_functions: FxHashSet<FuncId>,
+    /// See [`BackendConfig::max_function_size`].
+    max_function_size: Option<u32>,
+    /// Machine code cache of the codegen unit when the `function_cache` option is enabled.
+    function_cache: Option<crate::function_cache::FunctionCache>,
+    /// Intrinsics to lower differently, see [`IntrinsicOverride`].
+    intrinsic_overrides: FxHashMap<String, IntrinsicOverride>,
+    /// Callback to inspect or replace the MIR of every function, see [`MirTransform`].
+    mir_transform: Option<MirTransform>,
+    /// Symbols defined by `global_asm` which have to be made local once the object file of the
+    /// global asm has been linked together with the object file of the module, see
+    /// [`inline_asm::codegen_naked_fn`].
+    local_asm_symbols: Vec<String>,
+    /// See [`BackendConfig::symbol_prefix`].
+    symbol_prefix: Option<String>,
+    /// See [`BackendConfig::test_break_ir`].
+    test_break_ir: Option<String>,
+    /// See [`BackendConfig::test_skip_definition`].
+    test_skip_definition: Option<String>,
+    /// Hashes of the Cranelift IR of the functions already defined in the module by a previous
+    /// session and the function preparing the module to redefine a function, when the JIT module
+    /// is kept alive between sessions. See [`BackendConfig::jit_keep_module`].
+    jit_redefine: Option<(FxHashMap<String, u64>, fn(&mut M, FuncId))>,
+    /// Size of the machine code of every function defined in the module, when recorded for
+    /// [`jit_function_code`].
+    code_sizes: Option<FxHashMap<FuncId, u32>>,
+    /// Cranelift IR of the `#[inline(always)]` functions already codegened to inline them into
+    /// their callers, or `None` if they can't be inlined. See [`optimize::inline`].
+    inline_cache: FxHashMap<Instance<'tcx>, Option<Function>>,
+    stats: CodegenStats,
 }
 
 impl<'tcx, M: Module> CodegenCx<'tcx, M> {
     fn new(tcx: TyCtxt<'tcx>, module: M, debug_info: bool, pic_eh_frame: bool) -> Self {
         let unwind_context = UnwindContext::new(tcx, module.isa(), pic_eh_frame);
         let debug_context = if debug_info {
-            Some(DebugContext::new(tcx, module.isa()))
+            DebugContext::new(tcx, module.isa())
         } else {
             None
         };
+        let output_types = &tcx.sess.opts.output_types;
+        let asm_output = output_types
+            .contains_key(&rustc_session::config::OutputType::Assembly)
+            .then(String::new);
+        let ir_output = output_types
+            .contains_key(&rustc_session::config::OutputType::LlvmAssembly)
+            .then(String::new);
         CodegenCx {
             tcx,
             module,
@@ -158,16 +230,59 @@ fn new(tcx: TyCtxt<'tcx>, module: M, debug_info: bool, pic_eh_frame: bool) -> Se
             vtables: FxHashMap::default(),
             debug_context,
             unwind_context,
+            asm_output,
+            ir_output,
+            coverage: None,
+            verify_ir: false,
+            max_function_size: None,
+            undefined_functions: FxHashSet::default(),
+            function_cache: None,
+            intrinsic_overrides: FxHashMap::default(),
+            mir_transform: None,
+            local_asm_symbols: Vec::new(),
+            symbol_prefix: None,
+            test_break_ir: None,
+            test_skip_definition: None,
+            jit_redefine: None,
+            code_sizes: None,
+            inline_cache: FxHashMap::default(),
+            stats: CodegenStats::default(),
         }
     }
 
-    fn finalize(mut self) -> (M, String, Option<DebugContext<'tcx>>, UnwindContext<'tcx>) {
-        self.constants_cx.finalize(self.tcx, &mut self.module);
+    fn finalize(
+        mut self,
+    ) -> (
+        M,
+        String,
+        Option<DebugContext<'tcx>>,
+        UnwindContext<'tcx>,
+        CodegenStats,
+    ) {
+        self.stats.data_objects =
+            self.constants_cx
+                .finalize(self.tcx, &mut self.module, self.symbol_prefix.as_deref());
+
+        // A function that is declared, but not defined would be an undefined symbol in the object
+        // file, which would only be noticed as a confusing error when linking.
+        let mut undefined_functions = self
+            .undefined_functions
+            .iter()
+            .map(|&func_id| &*self.module.declarations().get_function_decl(func_id).name)
+            .collect::<Vec<_>>();
+        undefined_functions.sort();
+        for name in undefined_functions {
+            self.tcx.sess.err(&format!(
+                "function `{}` was declared, but never defined",
+                name
+            ));
+        }
         (
             self.module,
             self.global_asm,
             self.debug_context,
             self.unwind_context,
+            self.stats,
         )
     }
 }
@@ -198,18 +313,580 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+/// An explicit table of symbols against which the imports of JIT compiled code are resolved.
+///
+/// This allows running code in JIT mode with a controlled set of host functions, for example when
+/// using the JIT as a scripting engine. Symbols which are not part of the table are still looked up
+/// in the current process using `dlsym` by `cranelift_jit`, so this is not a security boundary on
+/// its own.
+#[derive(Clone, Debug)]
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub struct JitSymbols {
+    symbols: Vec<(String, *const u8)>,
+    merge_with_dylibs: bool,
+}
+
+impl JitSymbols {
+    /// Create a new symbol table. When `merge_with_dylibs` is true, the symbols of the dylibs the
+    /// crate depends on are loaded too, with the symbols in `symbols` taking precedence.
+    /// Otherwise no dylibs are loaded at all.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer must point to a function or data object which matches the declaration used by
+    /// the JIT compiled code for the symbol of the same name and which stays valid for the rest of
+    /// the process, as JIT compiled code may keep calling into it until the process exits.
+    pub unsafe fn new(symbols: Vec<(String, *const u8)>, merge_with_dylibs: bool) -> Self {
+        JitSymbols {
+            symbols,
+            merge_with_dylibs,
+        }
+    }
+}
+
+/// A callback resolving the imports of JIT compiled code which are not part of the imported
+/// symbols. It is consulted before the symbols of the current process are searched using `dlsym`.
+/// It may be called more than once for the same symbol.
+///
+/// This allows an application embedding the JIT to provide functions lazily, for example to
+/// plugins calling back into the application.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "jit"), allow(dead_code))]
+pub struct JitSymbolResolver(Arc<dyn Fn(&str) -> Option<*const u8>>);
+
+impl JitSymbolResolver {
+    /// Create a new resolver from `resolve`, which returns the address of the symbol with the given
+    /// name or `None` if it doesn't know the symbol.
+    ///
+    /// # Safety
+    ///
+    /// Every returned pointer must point to a function or data object which matches the
+    /// declaration used by the JIT compiled code for the symbol and which stays valid for the rest
+    /// of the process, as JIT compiled code may keep calling into it until the process exits.
+    pub unsafe fn new(resolve: impl Fn(&str) -> Option<*const u8> + 'static) -> Self {
+        JitSymbolResolver(Arc::new(resolve))
+    }
+}
+
+impl fmt::Debug for JitSymbolResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("JitSymbolResolver(..)")
+    }
+}
+
+/// Replacement for the default lowering of an intrinsic.
+///
+/// This is a testing and debugging facility for stubbing out or instrumenting intrinsics without
+/// changing their lowering in the `intrinsics` module. Overrides are set using
+/// [`BackendConfig::intrinsic_overrides`].
+#[derive(Clone, Debug)]
+pub enum IntrinsicOverride {
+    /// Trap when the intrinsic is called.
+    Trap,
+    /// Call the function with the given symbol name instead, passing the arguments of the
+    /// intrinsic using the C calling convention. Only supported for intrinsics whose arguments and
+    /// return value are all scalars.
+    Call(String),
+}
+
+/// A callback invoked with the MIR of every function right before it is codegened.
+///
+/// This is an unstable testing facility for experimenting with custom MIR transforms, like
+/// instrumentation, or for analysis tools which want to see exactly the MIR cg_clif codegens. The
+/// callback is called with the instance and its MIR as returned by `TyCtxt::instance_mir`, which is
+/// shared with the rest of the compiler and thus can't be modified in place. Returning a new body
+/// makes cg_clif codegen it instead of the original one, while returning `None` keeps the original.
+/// The new body is moved into the arena of `tcx`, so it lives as long as the original one.
+///
+/// The callback runs while cg_clif holds no borrows of `tcx`, so it may run arbitrary queries.
+/// However it must not hold on to any `'tcx` value beyond the call, as the `TyCtxt` is gone once
+/// the crate has been codegened. It has to return the same result for the same input every time,
+/// as object files of unchanged codegen units are reused from the incremental cache. It is also
+/// called for the callees of inlined calls, but not for the functions codegened lazily in
+/// `jit-lazy` mode.
+#[derive(Clone)]
+pub struct MirTransform(
+    Arc<
+        dyn for<'tcx> Fn(TyCtxt<'tcx>, Instance<'tcx>, &mir::Body<'tcx>) -> Option<mir::Body<'tcx>>,
+    >,
+);
+
+impl MirTransform {
+    pub fn new<F>(transform: F) -> Self
+    where
+        F: for<'tcx> Fn(TyCtxt<'tcx>, Instance<'tcx>, &mir::Body<'tcx>) -> Option<mir::Body<'tcx>>
+            + 'static,
+    {
+        MirTransform(Arc::new(transform))
+    }
+}
+
+impl fmt::Debug for MirTransform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MirTransform(..)")
+    }
+}
+
+/// A callback receiving the object files produced in AOT mode instead of writing them to the
+/// filesystem.
+///
+/// This allows embedding cg_clif in build systems which don't allow or want writes to the
+/// filesystem, like remote execution sandboxes. The callback is called with the name of the module
+/// and the contents of its object file and returns the path to record for the object file. rustc
+/// links the object files itself using these paths, so either they must exist by the time rustc
+/// links or linking has to be done by the embedder, for example using `-Zno-link`.
+///
+/// As the object files are never written by cg_clif, the incremental cache of cg_clif is disabled
+/// and options reading the object files back, like `build_id`, are not supported. Neither are
+/// options writing files of their own, like `module_list` or `--emit asm`.
+#[derive(Clone)]
+pub struct ObjectSink(Arc<dyn Fn(&str, &[u8]) -> PathBuf>);
+
+impl ObjectSink {
+    pub fn new(sink: impl Fn(&str, &[u8]) -> PathBuf + 'static) -> Self {
+        ObjectSink(Arc::new(sink))
+    }
+}
+
+impl fmt::Debug for ObjectSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ObjectSink(..)")
+    }
+}
+
+/// A function to call instead of the `main` function of the crate in JIT mode.
+///
+/// This is the entry point for differential testing harnesses comparing the behavior of a
+/// function compiled by cg_clif with a reference build by another backend. The harness passes a
+/// [`JitEntry`] in the [`BackendConfig`] of the [`CraneliftCodegenBackend`] it creates, feeds its
+/// inputs to the JIT compiled function and compares the outputs. The crate still has to be an
+/// executable, but its `main` function is never called.
+#[derive(Clone, Debug)]
+pub struct JitEntry {
+    /// Symbol name of the function, for example of a `#[no_mangle] extern "C" fn`.
+    pub symbol: String,
+    /// Called with the address of the JIT compiled function once all functions are compiled. The
+    /// caller is responsible for calling it with the right signature. The returned value is used
+    /// as exit code of the process, unless [`BackendConfig::jit_keep_module`] is set.
+    pub run: fn(*const u8) -> i32,
+}
+
+/// An error preventing the crate from being JIT compiled and run.
+///
+/// Errors in the crate itself are reported as diagnostics by rustc as usual. These are the errors
+/// specific to JIT mode, which an application embedding the JIT can handle using
+/// [`BackendConfig::jit_error_handler`] or by calling [`run_jit_entry`] itself.
+#[derive(Clone, Debug)]
+pub enum JitError {
+    /// A symbol couldn't be found, either in a loaded dylib or in the JIT compiled code.
+    SymbolNotFound { symbol: String, error: String },
+    /// A dylib the crate depends on couldn't be read or loaded.
+    DylibLoad { path: PathBuf, error: String },
+    /// Dylibs the crate depends on were built for another architecture than the host. Every
+    /// mismatching dylib is listed together with its architecture.
+    UnsupportedTarget {
+        expected: String,
+        dylibs: Vec<(PathBuf, String)>,
+    },
+    /// The crate can't be JIT compiled, for example because it isn't an executable or uses global
+    /// asm.
+    UnsupportedCrate(String),
+    /// The [`BackendConfig`] or the session options don't allow JIT compiling the crate, for
+    /// example because an option required by another option is missing.
+    InvalidConfig(String),
+    /// A function of the module kept by [`BackendConfig::jit_keep_module`] can't be redefined by
+    /// the current session. The kept module is dropped, so the next session starts with a new
+    /// module.
+    IncompatibleRedefinition { symbol: String, error: String },
+    /// Cranelift failed to compile or define a function.
+    Codegen(String),
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JitError::SymbolNotFound { symbol, error } => {
+                write!(f, "symbol `{}` not found: {}", symbol, error)
+            }
+            JitError::DylibLoad { path, error } => {
+                write!(f, "failed to load {}: {}", path.display(), error)
+            }
+            JitError::UnsupportedTarget { expected, dylibs } => {
+                write!(f, "can't load dylibs not built for {}:", expected)?;
+                for (path, architecture) in dylibs {
+                    write!(f, " {} ({})", path.display(), architecture)?;
+                }
+                Ok(())
+            }
+            JitError::UnsupportedCrate(msg) => write!(f, "not supported in JIT mode: {}", msg),
+            JitError::InvalidConfig(msg) => write!(f, "invalid JIT configuration: {}", msg),
+            JitError::IncompatibleRedefinition { symbol, error } => {
+                write!(
+                    f,
+                    "can't redefine `{}` in the kept JIT module: {}",
+                    symbol, error
+                )
+            }
+            JitError::Codegen(msg) => write!(f, "JIT codegen failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for JitError {}
+
+#[derive(Clone, Debug, Default)]
 pub struct BackendConfig {
     pub codegen_mode: CodegenMode,
+    /// Directory in which intermediate object files are written. When `None` rustc's default
+    /// location for temporary files is used.
+    pub temp_dir: Option<PathBuf>,
+    /// Write a report of the size of every section and function of the emitted object files.
+    pub size_report: bool,
+    /// Don't generate the C `main` function calling the Rust entry point, even when the crate has
+    /// one. This is useful for freestanding binaries which provide their own entry symbol. Crates
+    /// using `#![no_main]` never get an entry wrapper.
+    pub disable_entry_wrapper: bool,
+    /// Value of the `e_flags` field in the header of emitted ELF object files. Some embedded
+    /// linkers reject object files whose flags don't match the expected ABI variant.
+    pub elf_e_flags: Option<u32>,
+    /// Value of the `EI_OSABI` byte in the header of emitted ELF object files. Defaults to
+    /// `ELFOSABI_FREEBSD` for FreeBSD targets, `ELFOSABI_SOLARIS` for Solaris and illumos targets
+    /// and `ELFOSABI_NONE` otherwise, like LLVM.
+    pub elf_os_abi: Option<u8>,
+    /// Compress the DWARF debuginfo sections of emitted object files using zlib and mark them with
+    /// `SHF_COMPRESSED`, like `-gz` of gcc and clang. Debuggers and binutils decompress them
+    /// transparently. This considerably reduces the size of object files and the incremental cache
+    /// with debuginfo enabled. Only supported for ELF targets storing relocation addends in the
+    /// relocations, like x86_64, AArch64 and riscv64. Ignored in JIT mode.
+    pub compress_debug_sections: bool,
+    /// Don't cache object files in the incremental cache. Defaults to whether the
+    /// `CG_CLIF_INCR_CACHE_DISABLED` env var is set.
+    pub disable_incr_cache: bool,
+    /// Codegen units to always codegen again instead of reusing them from the incremental cache,
+    /// while all other codegen units are reused as usual. This is useful to find out whether an
+    /// object file in the incremental cache is stale. Every entry is a codegen unit name in which
+    /// `*` matches any sequence of characters. Can be set using `force_cgu_regen=<pattern>`.
+    pub force_cgu_regen: Vec<String>,
+    /// Write the symbol table of every emitted object file to a `.symbols` file next to it.
+    pub dump_symbols: bool,
+    /// Print the name of every codegen unit of the crate on a line of its own to stdout instead of
+    /// codegening and linking anything, like a `--print` request of rustc. This shows the names
+    /// used by options like `force_cgu_regen` and in the file names of object files. Can be set
+    /// using `print=cgu-names`.
+    pub print_cgu_names: bool,
+    /// Check that no global symbol is defined by more than one of the emitted object files, which
+    /// would otherwise only be reported by the linker, if at all. Weak symbols are allowed to be
+    /// defined multiple times. This reads back all object files, so it is off by default.
+    pub check_duplicate_symbols: bool,
+    /// Write the paths of all object files produced for the crate to this file, together with
+    /// the kind of module they belong to. This is useful to link them using a custom link step
+    /// instead of the linker invocation of rustc. rustc removes the object files after linking
+    /// unless `-Csave-temps` is passed. Ignored in JIT mode.
+    pub module_list: Option<PathBuf>,
+    /// Write the partitioning of the crate into codegen units to this file as Graphviz DOT graph.
+    /// See the `cgu_graph` module for the contents of the graph. Ignored in JIT mode.
+    pub cgu_graph: Option<PathBuf>,
+    /// Write the symbol of every function and static of the crate to this file together with the
+    /// name of the codegen unit defining it, one `<symbol> <codegen unit>` pair per line sorted by
+    /// symbol. This helps to find the object file a symbol mentioned by a linker error comes from.
+    /// Ignored in JIT mode.
+    pub symbol_map: Option<PathBuf>,
+    /// Write the symbols exported by the crate to this file, one per line. These are the symbols
+    /// rustc would export from the linked artifact, including those of statically linked upstream
+    /// crates and for a `dylib` its metadata symbol and allocator shim. For a `cdylib` or
+    /// `staticlib` these are only `#[no_mangle]` and `#[export_name]` items. The file can be
+    /// passed to `--retain-symbols-file` of `ld`. Ignored in JIT mode.
+    pub exports_file: Option<PathBuf>,
+    /// Like `exports_file`, but write the exported symbols as linker version script which can be
+    /// passed to `--version-script`. All other symbols are made local. Ignored in JIT mode.
+    pub version_script: Option<PathBuf>,
+    /// Remove the names of functions and data objects which are local to an object file from its
+    /// symbol table to make the object files smaller. The symbol table entries themselves are kept
+    /// as relocations refer to them. Debuginfo is not affected. Only supported for ELF targets.
+    pub strip_local_symbols: bool,
+    /// Instrument every basic block with a counter. See the `coverage` module for the format of
+    /// the emitted counters and coverage map. Ignored in JIT mode.
+    pub coverage: bool,
+    /// Don't generate the allocator shim defining `__rust_alloc` and the other allocator symbols.
+    /// They are left as imports and have to be provided externally, for example by the program
+    /// the emitted objects are embedded in. Ignored in JIT mode.
+    pub disable_allocator_shim: bool,
+    /// Emit the size optimized variant of the allocator shim, which omits the unwind info of the
+    /// functions forwarding to the global allocator. Defaults to whether `-Copt-level` is `s` or
+    /// `z`. Can be set using `small_allocator_shim=<bool>`.
+    pub small_allocator_shim: Option<bool>,
+    /// Symbols to resolve the imports of JIT compiled code against instead of the symbols of the
+    /// dylibs the crate depends on. Can only be set programmatically.
+    pub jit_symbols: Option<JitSymbols>,
+    /// Fallback for imports of JIT compiled code which are not part of the imported symbols. Can
+    /// only be set programmatically.
+    pub jit_symbol_resolver: Option<JitSymbolResolver>,
+    /// Call this function instead of `main` in JIT mode. Ignored with a warning in AOT mode. Can
+    /// only be set programmatically.
+    pub jit_entry: Option<JitEntry>,
+    /// Called with the error instead of aborting compilation when the crate can't be JIT compiled
+    /// and run. The returned value is used as exit code of the process. Can only be set
+    /// programmatically.
+    pub jit_error_handler: Option<fn(JitError) -> i32>,
+    /// Keep the JIT module alive after calling [`BackendConfig::jit_entry`] and return to rustc
+    /// instead of exiting the process. The next session JIT compiling a crate in the same process
+    /// reuses the module and only compiles the functions whose Cranelift IR changed. This is meant
+    /// for REPLs which compile every input as a new version of the same crate. Requires
+    /// `jit_entry` and `-Crelocation-model=pic`. Can only be set programmatically.
+    ///
+    /// Redefining a function patches the code running in the process, which has a few hazards:
+    ///
+    /// * Calls made after the redefinition use the new code, but code of the old version which is
+    ///   still executing, for example on another thread, keeps running until it returns.
+    /// * Function pointers and vtables created by a previous session keep pointing to the old
+    ///   code, which is never freed.
+    /// * Changing the signature of a function is a [`JitError::IncompatibleRedefinition`], as
+    ///   existing callers would pass the wrong arguments.
+    /// * The unwind tables of the old code are deregistered once all functions of the session that
+    ///   defined it have been redefined, so old code still executing at that point can't be
+    ///   unwound anymore.
+    /// * Statics keep their current value. Changes to their initializer have no effect.
+    /// * The other JIT options only take effect for the session creating the module.
+    ///
+    /// Functions referencing anonymous constants, like string literals, are always compiled
+    /// again, as the constants get a new name in every session.
+    pub jit_keep_module: bool,
+    /// Don't enable the cpu features of the host in JIT mode. By default JIT compiled code may
+    /// use every cpu feature detected at runtime, as it only ever runs in the process that
+    /// compiled it and thus on the same cpu. This isn't done in AOT mode, as the emitted object
+    /// files may run on a different cpu than the one they were compiled on. Features explicitly
+    /// disabled using `-Ctarget-feature` stay disabled either way.
+    pub disable_jit_host_features: bool,
+    /// Hand the object files produced in AOT mode to this sink instead of writing them to the
+    /// filesystem. Can only be set programmatically.
+    pub object_sink: Option<ObjectSink>,
+    /// Intrinsics to lower differently, keyed by the name of the intrinsic. Can be set using
+    /// `override_intrinsic=<name>:trap` or `override_intrinsic=<name>:call:<symbol>`. Ignored for
+    /// functions codegened lazily in `jit-lazy` mode.
+    pub intrinsic_overrides: Vec<(String, IntrinsicOverride)>,
+    /// Called with the MIR of every function before codegening it, see [`MirTransform`]. Can only
+    /// be set programmatically.
+    pub mir_transform: Option<MirTransform>,
+    /// Run the Cranelift verifier on every function right before it is compiled, after all cg_clif
+    /// specific optimizations. Verifier errors mention the symbol name of the function. In addition
+    /// check that every function declared for a mono item got defined. This is slow, so it is off
+    /// by default.
+    pub verify_ir: bool,
+    /// Maximum number of Cranelift instructions a single function may consist of. Codegening a
+    /// function exceeding it is aborted with an error mentioning its symbol name instead of letting
+    /// Cranelift spend a very long time and a lot of memory compiling it. Hitting this limit points
+    /// to a function which cg_clif lowers much less efficiently than expected, which is worth
+    /// reporting upstream. The error is fatal, so it aborts the whole build unless `keep_going` is
+    /// set, in which case all other codegen units are still codegened. The size is only checked
+    /// after the statements of every basic block have been lowered, before lowering its
+    /// terminator, so a single large basic block can still exceed the limit considerably. It counts
+    /// the instructions before any optimization. Can be set using
+    /// `max_function_size=<instructions>`.
+    pub max_function_size: Option<u32>,
+    /// Cache the machine code of individual functions in the incremental cache, such that only
+    /// changed functions of a codegen unit that needs to be codegened again are compiled again.
+    /// This is a prototype with many limitations, see the `function_cache` module. Ignored in JIT
+    /// mode.
+    pub function_cache: bool,
+    /// Keep codegening the remaining codegen units when a fatal error occurs in one of them. All
+    /// codegen units that failed are reported at the end and compilation still fails, but all
+    /// errors of the crate can be seen at once this way.
+    pub keep_going: bool,
+    /// Codegen every codegen unit in a separate child process, such that a crash while codegening
+    /// one of them doesn't abort the others. Crashed codegen units are reported like with
+    /// `keep_going`. Every child process runs the compiler again with the same arguments, so the
+    /// crate is analyzed once more for every codegen unit. Codegen units codegened this way are
+    /// never reused by incremental compilation. Requires the compiler to be invoked as a process of
+    /// its own, not embedded in another program.
+    pub isolate_cgus: bool,
+    /// Maximum number of codegen units to codegen at the same time when `isolate_cgus` is set.
+    /// Every codegen unit in flight is codegened by its own child process which holds the
+    /// Cranelift module of the codegen unit in memory until its object file is written. Higher
+    /// values increase throughput at the cost of higher peak memory usage. Defaults to 1. Without
+    /// `isolate_cgus` all codegen units are codegened one after another and this option is
+    /// ignored with a warning.
+    pub max_parallel_cgus: Option<u32>,
+    /// Emit every function into an object file of its own instead of emitting one object file per
+    /// codegen unit. This is meant for linker based optimizers and size analyzers which work on
+    /// whole object files. All functions are given at least hidden visibility to make them
+    /// available to the functions in other object files. The copies of a function local to multiple
+    /// codegen units are made weak. For incremental compilation, the object files of a codegen unit
+    /// are cached together as an archive. This considerably increases the number of object files
+    /// and thus the time the linker needs to read them and resolve the symbols between them, as
+    /// well as the total size of the object files as for example constants used by multiple
+    /// functions are duplicated. Ignored in JIT mode.
+    pub object_per_function: bool,
+    /// Only codegen the functions reachable from the functions whose symbol name matches one of
+    /// these patterns, the entry function and the `start` lang item. All other functions are left
+    /// undefined, which makes the resulting object files only valid as long as none of them is
+    /// called, for example by a downstream crate. This is an experimental mode to quickly test
+    /// changes to a small part of a big crate. Functions referenced by statics are always
+    /// considered to be reachable. Every entry is a symbol name in which `*` matches any sequence
+    /// of characters. Can be set using `codegen_roots=<pattern>`. Requires a single codegen unit.
+    /// The resulting object files are not cached for incremental compilation. Ignored in JIT mode.
+    pub codegen_roots: Vec<String>,
+    /// Rules to place functions into specific sections, for example for linker scripts of
+    /// bootloaders and firmware. Every rule consists of a pattern and a section name. Functions
+    /// whose symbol name or path (like `my_crate::boot::init::<u8>`) matches the pattern of a rule
+    /// are placed in the section of the first matching rule instead of `.text`. `*` in a pattern
+    /// matches any sequence of characters. Can be set using `function_section=<pattern>:<section>`.
+    /// Every function is given its own section, like with `-Zfunction-sections`. As `object` can't
+    /// rename the section Cranelift puts a function in, the sections are renamed in the written
+    /// object file, the same way `#[cold]` functions are moved to `.text.unlikely.*` sections.
+    /// Naked functions, which are defined by global asm, are therefore never moved. Only supported
+    /// for ELF targets. Ignored in JIT mode.
+    pub function_section_rules: Vec<(String, String)>,
+    /// Sections to add to a dedicated object file of the crate, for example to embed build
+    /// information. Every entry consists of the section name and its contents. Can be set using
+    /// `custom_section=<name>:<path>`, which reads the contents from the file at `<path>`. Ignored
+    /// in JIT mode.
+    pub custom_sections: Vec<(String, Vec<u8>)>,
+    /// Add a `.note.gnu.build-id` note containing a hash of all object files of the crate and the
+    /// crate hashes of all upstream crates. The linker keeps it as build-id of the linked binary
+    /// when it doesn't generate one itself, so the linker has to be invoked with
+    /// `--build-id=none`. Ignored for rlibs and staticlibs, as their object files end up in another
+    /// artifact, and in JIT mode. Only supported for ELF targets.
+    pub build_id: bool,
+    /// Add a `.note.rustc.crate-hash` note containing the crate hash of the local crate, which
+    /// changes whenever the source of the crate or the flags it is compiled with change. This makes
+    /// it possible to find out which sources a binary was built from using `readelf -n`. Only
+    /// supported for ELF targets. Ignored in JIT mode.
+    pub crate_hash_note: bool,
+    /// Print the number of codegened mono items to stderr every time this many more mono items
+    /// have been codegened. Useful to tell whether codegen of a large crate is still progressing.
+    pub progress: Option<u32>,
+    /// Prepend this prefix to the symbol of every function and static defined by the crate, except
+    /// for items with a fixed symbol name like `#[no_mangle]` items. This makes it possible to load
+    /// multiple independently compiled crates, or versions of the same crate, into a single process
+    /// or JIT without symbol collisions. Other crates refer to the items of a crate using the
+    /// unprefixed symbols, so this is not supported for crates which other Rust crates can depend
+    /// on, like rlibs and dylibs.
+    pub symbol_prefix: Option<String>,
+    /// Break the Cranelift IR of every function whose symbol name contains this string and enable
+    /// the verifier of Cranelift. Only meant for the tests of cg_clif. Defaults to the value of the
+    /// `CG_CLIF_TEST_BREAK_IR` env var.
+    pub test_break_ir: Option<String>,
+    /// Don't define any function whose symbol name contains this string. Only meant for the tests
+    /// of cg_clif. Defaults to the value of the `CG_CLIF_TEST_SKIP_DEFINITION` env var.
+    pub test_skip_definition: Option<String>,
 }
 
 impl BackendConfig {
-    fn from_opts(opts: &[String]) -> Result<Self, String> {
-        let mut config = BackendConfig::default();
+    /// Parses the options passed using `-Cllvm-args`, which is done by [`CraneliftCodegenBackend`]
+    /// when it is created without a config.
+    pub fn from_opts(opts: &[String]) -> Result<Self, String> {
+        let mut config = BackendConfig {
+            disable_incr_cache: std::env::var("CG_CLIF_INCR_CACHE_DISABLED").is_ok(),
+            test_break_ir: std::env::var("CG_CLIF_TEST_BREAK_IR").ok(),
+            test_skip_definition: std::env::var("CG_CLIF_TEST_SKIP_DEFINITION").ok(),
+            ..BackendConfig::default()
+        };
         for opt in opts {
             if let Some((name, value)) = opt.split_once('=') {
                 match name {
                     "mode" => config.codegen_mode = value.parse()?,
+                    "print" => match value {
+                        "cgu-names" => config.print_cgu_names = true,
+                        _ => {
+                            return Err(format!("Invalid value `{}` for option `{}`", value, name))
+                        }
+                    },
+                    "temp_dir" => config.temp_dir = Some(PathBuf::from(value)),
+                    "size_report" => config.size_report = parse_bool(name, value)?,
+                    "disable_entry_wrapper" => {
+                        config.disable_entry_wrapper = parse_bool(name, value)?
+                    }
+                    "elf_e_flags" => config.elf_e_flags = Some(parse_u32(name, value)?),
+                    "elf_os_abi" => {
+                        config.elf_os_abi =
+                            Some(parse_u32(name, value)?.try_into().map_err(|_| {
+                                format!(
+                                    "Value `{}` of option `{}` doesn't fit in a byte",
+                                    value, name
+                                )
+                            })?)
+                    }
+                    "compress_debug_sections" => {
+                        config.compress_debug_sections = parse_bool(name, value)?
+                    }
+                    "disable_incr_cache" => config.disable_incr_cache = parse_bool(name, value)?,
+                    "force_cgu_regen" => config.force_cgu_regen.push(value.to_string()),
+                    "dump_symbols" => config.dump_symbols = parse_bool(name, value)?,
+                    "check_duplicate_symbols" => {
+                        config.check_duplicate_symbols = parse_bool(name, value)?
+                    }
+                    "module_list" => config.module_list = Some(PathBuf::from(value)),
+                    "cgu_graph" => config.cgu_graph = Some(PathBuf::from(value)),
+                    "symbol_map" => config.symbol_map = Some(PathBuf::from(value)),
+                    "exports_file" => config.exports_file = Some(PathBuf::from(value)),
+                    "version_script" => config.version_script = Some(PathBuf::from(value)),
+                    "strip_local_symbols" => config.strip_local_symbols = parse_bool(name, value)?,
+                    "coverage" => config.coverage = parse_bool(name, value)?,
+                    "disable_allocator_shim" => {
+                        config.disable_allocator_shim = parse_bool(name, value)?
+                    }
+                    "small_allocator_shim" => {
+                        config.small_allocator_shim = Some(parse_bool(name, value)?)
+                    }
+                    "disable_jit_host_features" => {
+                        config.disable_jit_host_features = parse_bool(name, value)?
+                    }
+                    "verify_ir" => config.verify_ir = parse_bool(name, value)?,
+                    "max_function_size" => config.max_function_size = Some(parse_u32(name, value)?),
+                    "function_cache" => config.function_cache = parse_bool(name, value)?,
+                    "build_id" => config.build_id = parse_bool(name, value)?,
+                    "crate_hash_note" => config.crate_hash_note = parse_bool(name, value)?,
+                    "keep_going" => config.keep_going = parse_bool(name, value)?,
+                    "isolate_cgus" => config.isolate_cgus = parse_bool(name, value)?,
+                    "object_per_function" => config.object_per_function = parse_bool(name, value)?,
+                    "codegen_roots" => config.codegen_roots.push(value.to_string()),
+                    "function_section" => {
+                        let (pattern, section_name) = value.rsplit_once(':').ok_or_else(|| {
+                            format!("Invalid value `{}` for option `{}`", value, name)
+                        })?;
+                        config
+                            .function_section_rules
+                            .push((pattern.to_string(), section_name.to_string()));
+                    }
+                    "max_parallel_cgus" => match parse_u32(name, value)? {
+                        0 => {
+                            return Err(format!("Invalid value `{}` for option `{}`", value, name))
+                        }
+                        max => config.max_parallel_cgus = Some(max),
+                    },
+                    "progress" => match parse_u32(name, value)? {
+                        0 => {
+                            return Err(format!("Invalid value `{}` for option `{}`", value, name))
+                        }
+                        interval => config.progress = Some(interval),
+                    },
+                    "symbol_prefix" => config.symbol_prefix = Some(value.to_string()),
+                    "override_intrinsic" => {
+                        let invalid_value =
+                            || format!("Invalid value `{}` for option `{}`", value, name);
+                        let (intrinsic, action) =
+                            value.split_once(':').ok_or_else(invalid_value)?;
+                        let intrinsic_override = match action.split_once(':') {
+                            None if action == "trap" => IntrinsicOverride::Trap,
+                            Some(("call", symbol)) if !symbol.is_empty() => {
+                                IntrinsicOverride::Call(symbol.to_string())
+                            }
+                            _ => return Err(invalid_value()),
+                        };
+                        config
+                            .intrinsic_overrides
+                            .push((intrinsic.to_string(), intrinsic_override));
+                    }
+                    "custom_section" => {
+                        let (section_name, path) = value.split_once(':').ok_or_else(|| {
+                            format!("Invalid value `{}` for option `{}`", value, name)
+                        })?;
+                        let data = std::fs::read(path)
+                            .map_err(|err| format!("Failed to read `{}`: {}", path, err))?;
+                        config
+                            .custom_sections
+                            .push((section_name.to_string(), data));
+                    }
                     _ => return Err(format!("Unknown option `{}`", name)),
                 }
             } else {
@@ -220,15 +897,86 @@ fn from_opts(opts: &[String]) -> Result<Self, String> {
     }
 }
 
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "0" | "false" => Ok(false),
+        "1" | "true" => Ok(true),
+        _ => Err(format!("Invalid value `{}` for option `{}`", value, name)),
+    }
+}
+
+fn parse_u32(name: &str, value: &str) -> Result<u32, String> {
+    let res = if let Some(hex) = value.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else {
+        value.parse()
+    };
+    res.map_err(|_| format!("Invalid value `{}` for option `{}`", value, name))
+}
+
 pub struct CraneliftCodegenBackend {
     pub config: Option<BackendConfig>,
 }
 
+impl CraneliftCodegenBackend {
+    /// Returns the programmatically set config, or else the config parsed from `-Cllvm-args`.
+    fn config(&self, sess: &Session) -> BackendConfig {
+        if let Some(config) = self.config.clone() {
+            config
+        } else {
+            BackendConfig::from_opts(&sess.opts.cg.llvm_args).unwrap_or_else(|err| sess.fatal(&err))
+        }
+    }
+}
+
 impl CodegenBackend for CraneliftCodegenBackend {
     fn init(&self, sess: &Session) {
         if sess.lto() != rustc_session::config::Lto::No && sess.opts.cg.embed_bitcode {
             sess.warn("LTO is not supported. You may get a linker error.");
         }
+
+        if matches!(
+            sess.relocation_model(),
+            rustc_target::spec::RelocModel::Ropi
+                | rustc_target::spec::RelocModel::Rwpi
+                | rustc_target::spec::RelocModel::RopiRwpi
+        ) {
+            sess.err(
+                "the `ropi`, `rwpi` and `ropi-rwpi` relocation models are not supported by \
+                 rustc_codegen_cranelift",
+            );
+        }
+
+        // Cranelift only implements the general dynamic TLS model on ELF. It is valid for all uses
+        // of thread locals, but requires `__tls_get_addr` to be available at runtime. Initial-exec
+        // is only an optimization of this, so falling back is fine. Local-exec is used when there
+        // is no dynamic linker to provide `__tls_get_addr`, so falling back would fail at link or
+        // run time instead.
+        if crate::target_triple(sess).binary_format == target_lexicon::BinaryFormat::Elf {
+            use rustc_target::spec::TlsModel;
+            match sess
+                .opts
+                .debugging_opts
+                .tls_model
+                .unwrap_or(sess.target.tls_model)
+            {
+                TlsModel::GeneralDynamic | TlsModel::LocalDynamic => {}
+                TlsModel::InitialExec => sess.warn(
+                    "the `initial-exec` TLS model is not supported by rustc_codegen_cranelift, \
+                     falling back to the `global-dynamic` TLS model",
+                ),
+                TlsModel::LocalExec => sess
+                    .err("the `local-exec` TLS model is not supported by rustc_codegen_cranelift"),
+            }
+        }
+
+        if let Ok(mut isa_builder) =
+            cranelift_codegen::isa::lookup_variant(crate::target_triple(sess), backend_variant())
+        {
+            for warning in configure_target_cpu(sess, &mut isa_builder) {
+                sess.warn(&warning);
+            }
+        }
     }
 
     fn metadata_loader(&self) -> Box<dyn MetadataLoader + Sync> {
@@ -238,6 +986,15 @@ fn metadata_loader(&self) -> Box<dyn MetadataLoader + Sync> {
     fn provide(&self, _providers: &mut Providers) {}
     fn provide_extern(&self, _providers: &mut Providers) {}
 
+    fn print(&self, req: PrintRequest, sess: &Session) {
+        if let PrintRequest::TargetFeatures = req {
+            let config = self.config(sess);
+            for (name, value) in crate::features::supported_features(sess, &config) {
+                println!("{}={}", name, value);
+            }
+        }
+    }
+
     fn target_features(&self, _sess: &Session) -> Vec<rustc_span::Symbol> {
         vec![]
     }
@@ -248,12 +1005,7 @@ fn codegen_crate<'tcx>(
         metadata: EncodedMetadata,
         need_metadata_module: bool,
     ) -> Box<dyn Any> {
-        let config = if let Some(config) = self.config {
-            config
-        } else {
-            BackendConfig::from_opts(&tcx.sess.opts.cg.llvm_args)
-                .unwrap_or_else(|err| tcx.sess.fatal(&err))
-        };
+        let config = self.config(tcx.sess);
         let res = driver::codegen_crate(tcx, metadata, need_metadata_module, config);
 
         res
@@ -277,6 +1029,15 @@ fn link(
     ) -> Result<(), ErrorReported> {
         use rustc_codegen_ssa::back::link::link_binary;
 
+        // The crate has already been run by the JIT or only the codegen unit names were printed.
+        // There is nothing to link.
+        let config = self.config(sess);
+        if (config.jit_keep_module && !matches!(config.codegen_mode, CodegenMode::Aot))
+            || config.print_cgu_names
+        {
+            return Ok(());
+        }
+
         let target_cpu = crate::target_triple(sess).to_string();
         link_binary::<crate::archive::ArArchiveBuilder<'_>>(
             sess,
@@ -294,18 +1055,30 @@ fn target_triple(sess: &Session) -> target_lexicon::Triple {
     sess.target.llvm_target.parse().unwrap()
 }
 
-fn build_isa(sess: &Session) -> Box<dyn isa::TargetIsa + 'static> {
+/// Builds the `TargetIsa` for the target of `sess`. When `host_features` is true, all cpu features
+/// supported by the host, as detected by `cranelift_native`, are enabled in addition to those
+/// implied by `-Ctarget-cpu` and `-Ctarget-feature`. This must only be used when the generated code
+/// runs on the host itself. `enable_verifier` enables the verifier of Cranelift, which is always
+/// enabled when cg_clif is built with debug assertions.
+fn build_isa(
+    sess: &Session,
+    host_features: bool,
+    enable_verifier: bool,
+) -> Box<dyn isa::TargetIsa + 'static> {
     use target_lexicon::BinaryFormat;
 
     let target_triple = crate::target_triple(sess);
 
     let mut flags_builder = settings::builder();
-    flags_builder.enable("is_pic").unwrap();
+    if is_pic(sess) {
+        flags_builder.enable("is_pic").unwrap();
+    }
     flags_builder.set("enable_probestack", "false").unwrap(); // __cranelift_probestack is not provided
+
     flags_builder
         .set(
             "enable_verifier",
-            if cfg!(debug_assertions) {
+            if cfg!(debug_assertions) || enable_verifier {
                 "true"
             } else {
                 "false"
@@ -323,32 +1096,160 @@ fn build_isa(sess: &Session) -> Box<dyn isa::TargetIsa + 'static> {
 
     flags_builder.set("enable_simd", "true").unwrap();
 
-    use rustc_session::config::OptLevel;
-    match sess.opts.optimize {
-        OptLevel::No => {
-            flags_builder.set("opt_level", "none").unwrap();
-        }
-        OptLevel::Less | OptLevel::Default => {}
-        OptLevel::Aggressive => {
-            flags_builder.set("opt_level", "speed_and_size").unwrap();
-        }
-        OptLevel::Size | OptLevel::SizeMin => {
-            sess.warn("Optimizing for size is not supported. Just ignoring the request");
-        }
-    }
+    // Cranelift has no setting to omit the frame pointer. Every function sets up a frame pointer
+    // in its prologue, so `Session::must_not_eliminate_frame_pointers` and thereby
+    // `-Cforce-frame-pointers=yes` is always honored. `-Cforce-frame-pointers=no` only permits
+    // omitting it, so ignoring it is fine too.
+
+    flags_builder
+        .set("opt_level", cranelift_opt_level(sess.opts.optimize))
+        .unwrap();
 
     let flags = settings::Flags::new(flags_builder);
 
-    let variant = if cfg!(feature = "oldbe") {
+    // The JIT only supports compiling for the host, but check the target anyway to never enable
+    // the features of the host for a different target.
+    let mut isa_builder = if host_features && target_triple == target_lexicon::Triple::host() {
+        cranelift_native::builder_with_options(backend_variant(), true)
+            .unwrap_or_else(|err| sess.fatal(err))
+    } else {
+        cranelift_codegen::isa::lookup_variant(target_triple, backend_variant()).unwrap()
+    };
+    // Unsupported cpus and features are already reported by `CodegenBackend::init`.
+    let _unsupported = configure_target_cpu(sess, &mut isa_builder);
+    isa_builder.finish(flags)
+}
+
+/// Whether to generate position independent code for `-Crelocation-model`. PIE executables use
+/// the `pic` relocation model too. With `static` and `dynamic-no-pic` functions and data objects
+/// are referenced using absolute relocations instead of through the PLT and GOT.
+///
+/// With `pic` only imported symbols are referenced through the PLT and GOT, as `cranelift_module`
+/// treats all symbols defined by the module as final. Whether an executable is linked as PIE using
+/// `-pie` is decided by `rustc_codegen_ssa` based on the relocation model.
+fn is_pic(sess: &Session) -> bool {
+    use rustc_target::spec::RelocModel;
+    match sess.relocation_model() {
+        RelocModel::Pic => true,
+        RelocModel::Static | RelocModel::DynamicNoPic => false,
+        // Rejected by `CodegenBackend::init`.
+        RelocModel::Ropi | RelocModel::Rwpi | RelocModel::RopiRwpi => false,
+    }
+}
+
+/// Maps `-Copt-level` to Cranelift's `opt_level` setting:
+///
+/// | `-Copt-level` | `opt_level`      |
+/// |---------------|------------------|
+/// | `0`           | `none`           |
+/// | `1`, `2`      | `speed`          |
+/// | `3`           | `speed_and_size` |
+/// | `s`, `z`      | `speed_and_size` |
+///
+/// `none` gives the fastest compilation. Cranelift has no setting that optimizes only for size,
+/// so `s` and `z` use `speed_and_size`, which is the only setting that also reduces code size.
+/// The cg_clif specific optimizations in the `optimize` module run for all levels except `0`.
+fn cranelift_opt_level(opt_level: rustc_session::config::OptLevel) -> &'static str {
+    use rustc_session::config::OptLevel;
+    match opt_level {
+        OptLevel::No => "none",
+        OptLevel::Less | OptLevel::Default => "speed",
+        OptLevel::Aggressive | OptLevel::Size | OptLevel::SizeMin => "speed_and_size",
+    }
+}
+
+fn backend_variant() -> cranelift_codegen::isa::BackendVariant {
+    if cfg!(feature = "oldbe") {
         cranelift_codegen::isa::BackendVariant::Legacy
     } else {
         cranelift_codegen::isa::BackendVariant::MachInst
+    }
+}
+
+/// Apply `-Ctarget-cpu` and `-Ctarget-feature` to `isa_builder`. Returns a warning for every cpu
+/// or feature that is not supported by Cranelift.
+fn configure_target_cpu(sess: &Session, isa_builder: &mut isa::Builder) -> Vec<String> {
+    let mut unsupported = vec![];
+    let is_x86_64 = crate::target_triple(sess).architecture == target_lexicon::Architecture::X86_64;
+
+    let cpu_enabled = match sess.opts.cg.target_cpu.as_deref() {
+        Some("native") => {
+            unsupported.push(
+                "`-Ctarget-cpu=native` is not yet supported by cg_clif, using the default cpu"
+                    .to_string(),
+            );
+            false
+        }
+        Some(cpu) => {
+            let res = isa_builder.enable(cpu).is_ok();
+            if !res {
+                unsupported.push(format!(
+                    "target cpu `{}` is not supported by Cranelift, using the default cpu",
+                    cpu
+                ));
+            }
+            res
+        }
+        None => false,
     };
-    let mut isa_builder = cranelift_codegen::isa::lookup_variant(target_triple, variant).unwrap();
-    // Don't use "haswell", as it implies `has_lzcnt`.macOS CI is still at Ivy Bridge EP, so `lzcnt`
-    // is interpreted as `bsr`.
-    isa_builder.enable("nehalem").unwrap();
-    isa_builder.finish(flags)
+    if is_x86_64 && !cpu_enabled {
+        // Don't use "haswell", as it implies `has_lzcnt`.macOS CI is still at Ivy Bridge EP, so
+        // `lzcnt` is interpreted as `bsr`.
+        isa_builder.enable("nehalem").unwrap();
+    }
+
+    for feature in sess.opts.cg.target_feature.split(',') {
+        let (enable, name) = if let Some(name) = feature.strip_prefix('+') {
+            (true, name)
+        } else if let Some(name) = feature.strip_prefix('-') {
+            (false, name)
+        } else {
+            continue;
+        };
+        let setting = if is_x86_64 {
+            cranelift_x86_feature(name)
+        } else {
+            None
+        };
+        match setting {
+            Some(Some(setting)) => {
+                if let Err(err) = isa_builder.set(setting, if enable { "true" } else { "false" }) {
+                    unsupported.push(format!(
+                        "target feature `{}` is not supported by Cranelift ({}), ignoring it",
+                        name, err
+                    ));
+                }
+            }
+            Some(None) => {}
+            // Not a cpu feature, but handled by rustc itself.
+            None if name == "crt-static" => {}
+            None => unsupported.push(format!(
+                "target feature `{}` is not supported by Cranelift, ignoring it",
+                name
+            )),
+        }
+    }
+
+    unsupported
+}
+
+/// Returns the Cranelift setting corresponding to an x86_64 target feature. Features which are
+/// always available on x86_64 map to `Some(None)`.
+fn cranelift_x86_feature(feature: &str) -> Option<Option<&'static str>> {
+    Some(Some(match feature {
+        "sse" | "sse2" | "fxsr" => return Some(None),
+        "sse3" => "has_sse3",
+        "ssse3" => "has_ssse3",
+        "sse4.1" => "has_sse41",
+        "sse4.2" => "has_sse42",
+        "popcnt" => "has_popcnt",
+        "avx" => "has_avx",
+        "avx2" => "has_avx2",
+        "bmi1" => "has_bmi1",
+        "bmi2" => "has_bmi2",
+        "lzcnt" => "has_lzcnt",
+        _ => return None,
+    }))
 }
 
 /// This is the entrypoint for a hot plugged rustc_codegen_cranelift
@@ -1,9 +1,13 @@
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
 use rustc_middle::mir::mono::{Linkage as RLinkage, MonoItem, Visibility};
 
+use rustc_span::DUMMY_SP;
+
 use crate::prelude::*;
 
-pub(crate) fn get_clif_linkage(
-    mono_item: MonoItem<'_>,
+pub(crate) fn get_clif_linkage<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    mono_item: MonoItem<'tcx>,
     linkage: RLinkage,
     visibility: Visibility,
 ) -> Linkage {
@@ -11,6 +15,11 @@ pub(crate) fn get_clif_linkage(
         (RLinkage::External, Visibility::Default) => Linkage::Export,
         (RLinkage::Internal, Visibility::Default) => Linkage::Local,
         (RLinkage::External, Visibility::Hidden) => Linkage::Hidden,
+        // Only items with a `#[linkage]` attribute get any other linkage.
+        (_, Visibility::Default) => {
+            let span = mono_item.local_span(tcx).unwrap_or(DUMMY_SP);
+            get_explicit_linkage(tcx, span, linkage)
+        }
         _ => panic!("{:?} = {:?} {:?}", mono_item, linkage, visibility),
     }
 }
@@ -19,15 +28,66 @@ pub(crate) fn get_static_linkage(tcx: TyCtxt<'_>, def_id: DefId) -> Linkage {
     let fn_attrs = tcx.codegen_fn_attrs(def_id);
 
     if let Some(linkage) = fn_attrs.linkage {
-        match linkage {
-            RLinkage::External => Linkage::Export,
-            RLinkage::Internal => Linkage::Local,
-            RLinkage::ExternalWeak | RLinkage::WeakAny => Linkage::Preemptible,
-            _ => panic!("{:?}", linkage),
-        }
+        get_explicit_linkage(tcx, tcx.def_span(def_id), linkage)
     } else if tcx.is_reachable_non_generic(def_id) {
         Linkage::Export
     } else {
         Linkage::Hidden
     }
 }
+
+/// Maps the linkage of a `#[linkage = "..."]` attribute to a Cranelift linkage. Cranelift only
+/// distinguishes between strong and weak symbols, so all weak and linkonce linkages result in a
+/// weak symbol. The linkages which have no equivalent are rejected.
+fn get_explicit_linkage(tcx: TyCtxt<'_>, span: Span, linkage: RLinkage) -> Linkage {
+    let unsupported = |name: &str| -> ! {
+        tcx.sess.span_fatal(
+            span,
+            &format!("linkage `{}` is not supported by cg_clif", name),
+        )
+    };
+    match linkage {
+        RLinkage::External => Linkage::Export,
+        RLinkage::Internal | RLinkage::Private => Linkage::Local,
+        RLinkage::ExternalWeak
+        | RLinkage::WeakAny
+        | RLinkage::WeakODR
+        | RLinkage::LinkOnceAny
+        | RLinkage::LinkOnceODR => Linkage::Preemptible,
+        RLinkage::AvailableExternally => unsupported("available_externally"),
+        RLinkage::Appending => unsupported("appending"),
+        RLinkage::Common => unsupported("common"),
+    }
+}
+
+/// Returns the symbol name of `instance`.
+///
+/// When [`BackendConfig::symbol_prefix`](crate::BackendConfig::symbol_prefix) is set,
+/// `symbol_prefix` is prepended to the symbol of every item defined by the local crate. This makes
+/// it possible to load multiple independently compiled crates into a single process or JIT without
+/// symbol collisions. Items of other crates, items in `extern` blocks, items with a fixed symbol
+/// name given by `#[no_mangle]` or `#[export_name]` and symbols the standard library expects to
+/// find under a fixed name, like those of `#[global_allocator]`, are not prefixed.
+pub(crate) fn symbol_name<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    symbol_prefix: Option<&str>,
+    instance: Instance<'tcx>,
+) -> String {
+    let name = tcx.symbol_name(instance).name;
+    let def_id = instance.def_id();
+    match symbol_prefix {
+        Some(prefix) if def_id.is_local() && !tcx.is_foreign_item(def_id) => {
+            let attrs = tcx.codegen_fn_attrs(def_id);
+            if attrs.export_name.is_some()
+                || attrs.flags.intersects(
+                    CodegenFnAttrFlags::NO_MANGLE | CodegenFnAttrFlags::RUSTC_STD_INTERNAL_SYMBOL,
+                )
+            {
+                name.to_string()
+            } else {
+                format!("{}{}", prefix, name)
+            }
+        }
+        _ => name.to_string(),
+    }
+}
@@ -8,6 +8,7 @@
 pub(crate) fn maybe_create_entry_wrapper(
     tcx: TyCtxt<'_>,
     module: &mut impl Module,
+    symbol_prefix: Option<&str>,
     unwind_context: &mut UnwindContext<'_>,
     use_jit: bool,
 ) {
@@ -23,13 +24,17 @@ pub(crate) fn maybe_create_entry_wrapper(
     };
 
     let instance = Instance::mono(tcx, main_def_id).polymorphize(tcx);
-    if module.get_name(&*tcx.symbol_name(instance).name).is_none() {
+    if module
+        .get_name(&crate::linkage::symbol_name(tcx, symbol_prefix, instance))
+        .is_none()
+    {
         return;
     }
 
     create_entry_fn(
         tcx,
         module,
+        symbol_prefix,
         unwind_context,
         main_def_id,
         use_start_lang_item,
@@ -39,6 +44,7 @@ pub(crate) fn maybe_create_entry_wrapper(
     fn create_entry_fn(
         tcx: TyCtxt<'_>,
         m: &mut impl Module,
+        symbol_prefix: Option<&str>,
         unwind_context: &mut UnwindContext<'_>,
         rust_main_def_id: DefId,
         use_start_lang_item: bool,
@@ -69,7 +75,7 @@ fn create_entry_fn(
 
         let instance = Instance::mono(tcx, rust_main_def_id).polymorphize(tcx);
 
-        let main_name = tcx.symbol_name(instance).name.to_string();
+        let main_name = crate::linkage::symbol_name(tcx, symbol_prefix, instance);
         let main_sig = get_function_sig(tcx, m.isa().triple(), instance);
         let main_func_id = m
             .declare_function(&main_name, Linkage::Import, &main_sig)
@@ -101,7 +107,7 @@ fn create_entry_fn(
                 .unwrap()
                 .unwrap()
                 .polymorphize(tcx);
-                let start_func_id = import_function(tcx, m, start_instance);
+                let start_func_id = import_function(tcx, m, symbol_prefix, start_instance);
 
                 let main_val = bcx
                     .ins()
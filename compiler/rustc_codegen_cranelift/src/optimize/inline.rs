@@ -0,0 +1,209 @@
+//! Inlining of calls to `#[inline(always)]` functions
+//!
+//! Cranelift doesn't inline functions, so calls to tiny helpers stay calls even when they are
+//! marked `#[inline(always)]`. When optimizations are enabled, calls to such functions are
+//! recorded while codegening the caller. Once the body of the caller is codegened, the body of
+//! every recorded callee is codegened on its own and copied into the caller in place of the call.
+//! Every callee is codegened only once per codegen unit, using a scratch module so that codegening
+//! it never declares anything in the module of the caller.
+//!
+//! Only small leaf functions are inlined: the callee must not reference any other function or
+//! data object. This also rules out recursion. When the callee doesn't fit these restrictions the
+//! call is kept.
+
+use rustc_attr::InlineAttr;
+use rustc_middle::middle::codegen_fn_attrs::CodegenFnAttrFlags;
+use rustc_session::config::OptLevel;
+
+use cranelift_codegen::ir::{InstructionData, JumpTableData, Opcode, ValueList};
+
+use crate::prelude::*;
+
+/// The maximum number of basic blocks of the MIR of a function to consider inlining it.
+const MAX_MIR_BLOCKS: usize = 16;
+
+/// The maximum number of instructions of the Cranelift IR of a function to inline it.
+const MAX_INSTS: usize = 100;
+
+/// Returns whether a call from `caller` to `callee` should be recorded to be inlined.
+pub(crate) fn is_inline_candidate<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    caller: Instance<'tcx>,
+    callee: Instance<'tcx>,
+) -> bool {
+    if tcx.sess.opts.optimize == OptLevel::No || caller == callee {
+        return false;
+    }
+
+    let def_id = match callee.def {
+        InstanceDef::Item(def) => def.did,
+        _ => return false,
+    };
+    let attrs = tcx.codegen_fn_attrs(def_id);
+    if attrs.inline != InlineAttr::Always
+        || attrs.flags.contains(CodegenFnAttrFlags::NAKED)
+        || callee.def.requires_caller_location(tcx)
+        || !tcx.is_mir_available(def_id)
+    {
+        return false;
+    }
+
+    // Codegening inline asm has side effects on the codegen unit, so it must only happen once.
+    let mir = tcx.instance_mir(callee.def);
+    mir.basic_blocks().len() <= MAX_MIR_BLOCKS
+        && mir.basic_blocks().iter().all(|bb_data| {
+            !matches!(bb_data.terminator().kind, TerminatorKind::InlineAsm { .. })
+                && !bb_data
+                    .statements
+                    .iter()
+                    .any(|stmt| matches!(stmt.kind, StatementKind::LlvmInlineAsm(_)))
+        })
+}
+
+/// Returns whether the codegened function `callee` can be inlined by [`inline_call`].
+pub(crate) fn is_inlinable(callee: &Function) -> bool {
+    if callee.dfg.num_insts() > MAX_INSTS
+        || !callee.dfg.ext_funcs.is_empty()
+        || !callee.dfg.signatures.is_empty()
+        || !callee.global_values.is_empty()
+        || !callee.heaps.is_empty()
+        || !callee.tables.is_empty()
+        || callee.dfg.constants.len() != 0
+        || !callee.dfg.immediates.is_empty()
+    {
+        return false;
+    }
+
+    callee.layout.blocks().all(|block| {
+        callee.layout.block_insts(block).all(|inst| {
+            !matches!(
+                callee.dfg[inst].opcode(),
+                Opcode::JumpTableEntry | Opcode::JumpTableBase | Opcode::IndirectJumpTableBr
+            )
+        })
+    })
+}
+
+/// Replaces `call_inst` in `func` with a copy of the body of `callee`. The callee must be the
+/// function called by `call_inst` and [`is_inlinable`] must return `true` for it.
+pub(crate) fn inline_call(func: &mut Function, call_inst: Inst, callee: &Function) {
+    let call_args = func.dfg.inst_args(call_inst).to_vec();
+    let srcloc = func.srclocs[call_inst];
+
+    // Move all instructions after the call to a new block, which the inlined returns jump to.
+    let call_block = func.layout.inst_block(call_inst).unwrap();
+    let next_inst = func
+        .layout
+        .next_inst(call_inst)
+        .expect("call is never the last instruction of a block");
+    let return_block = func.dfg.make_block();
+    func.layout.split_block(return_block, next_inst);
+
+    let results = func.dfg.inst_results(call_inst).to_vec();
+    func.dfg.clear_results(call_inst);
+    for result in results {
+        let ty = func.dfg.value_type(result);
+        let param = func.dfg.append_block_param(return_block, ty);
+        func.dfg.change_to_alias(result, param);
+    }
+
+    let mut stack_slot_map = FxHashMap::default();
+    for (stack_slot, data) in callee.stack_slots.iter() {
+        stack_slot_map.insert(stack_slot, func.create_stack_slot(data.clone()));
+    }
+
+    let mut block_map = FxHashMap::default();
+    let mut value_map = FxHashMap::default();
+    let mut prev_block = call_block;
+    for block in callee.layout.blocks() {
+        let new_block = func.dfg.make_block();
+        func.layout.insert_block_after(new_block, prev_block);
+        prev_block = new_block;
+        block_map.insert(block, new_block);
+        for &param in callee.dfg.block_params(block) {
+            let ty = callee.dfg.value_type(param);
+            value_map.insert(param, func.dfg.append_block_param(new_block, ty));
+        }
+    }
+
+    let mut jump_table_map = FxHashMap::default();
+    for (jump_table, data) in callee.jump_tables.iter() {
+        let mut new_data = JumpTableData::with_capacity(data.len());
+        for block in data.iter() {
+            new_data.push_entry(block_map[block]);
+        }
+        jump_table_map.insert(jump_table, func.create_jump_table(new_data));
+    }
+
+    // Create all instructions and their results first, as values may be used before their
+    // definition in layout order.
+    let mut inst_map = Vec::with_capacity(callee.dfg.num_insts());
+    for block in callee.layout.blocks() {
+        for inst in callee.layout.block_insts(block) {
+            // The value lists of the copied instruction are replaced below.
+            let new_inst = func.dfg.make_inst(callee.dfg[inst].clone());
+            func.dfg
+                .make_inst_results(new_inst, callee.dfg.ctrl_typevar(inst));
+            for (&result, &new_result) in callee
+                .dfg
+                .inst_results(inst)
+                .iter()
+                .zip(func.dfg.inst_results(new_inst))
+            {
+                value_map.insert(result, new_result);
+            }
+            func.layout.append_inst(new_inst, block_map[&block]);
+            func.srclocs[new_inst] = srcloc;
+            inst_map.push((inst, new_inst));
+        }
+    }
+
+    for (inst, new_inst) in inst_map {
+        let args = callee
+            .dfg
+            .inst_args(inst)
+            .iter()
+            .map(|&arg| value_map[&callee.dfg.resolve_aliases(arg)])
+            .collect::<Vec<_>>();
+
+        let mut data = if callee.dfg[inst].opcode() == Opcode::Return {
+            InstructionData::Jump {
+                opcode: Opcode::Jump,
+                args: ValueList::new(),
+                destination: return_block,
+            }
+        } else {
+            let mut data = callee.dfg[inst].clone();
+            match &mut data {
+                InstructionData::BranchTable {
+                    destination, table, ..
+                } => {
+                    *destination = block_map[&*destination];
+                    *table = jump_table_map[&*table];
+                }
+                InstructionData::StackLoad { stack_slot, .. }
+                | InstructionData::StackStore { stack_slot, .. } => {
+                    *stack_slot = stack_slot_map[&*stack_slot];
+                }
+                data => {
+                    if let Some(destination) = data.branch_destination_mut() {
+                        *destination = block_map[&*destination];
+                    }
+                }
+            }
+            data
+        };
+
+        // Value lists are stored in the value list pool of the function they belong to.
+        if data.take_value_list().is_some() {
+            data.put_value_list(ValueList::from_slice(&args, &mut func.dfg.value_lists));
+        } else {
+            data.arguments_mut(&mut func.dfg.value_lists)
+                .copy_from_slice(&args);
+        }
+        func.dfg[new_inst] = data;
+    }
+
+    let entry_block = block_map[&callee.layout.entry_block().unwrap()];
+    func.dfg.replace(call_inst).jump(entry_block, &call_args);
+}
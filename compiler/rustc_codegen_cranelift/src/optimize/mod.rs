@@ -3,6 +3,7 @@
 use crate::prelude::*;
 
 mod code_layout;
+pub(crate) mod inline;
 pub(crate) mod peephole;
 mod stack2reg;
 
@@ -21,5 +22,11 @@ pub(crate) fn optimize_function<'tcx>(
     }
     self::stack2reg::optimize_function(ctx, clif_comments);
     crate::pretty_clif::write_clif_file(tcx, "stack2reg", None, instance, &ctx, &*clif_comments);
-    crate::base::verify_func(tcx, &*clif_comments, &ctx.func);
+    crate::base::verify_func(
+        tcx,
+        tcx.symbol_name(instance).name,
+        None,
+        &*clif_comments,
+        &ctx.func,
+    );
 }
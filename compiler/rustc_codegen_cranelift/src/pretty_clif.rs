@@ -237,35 +237,47 @@ pub(crate) fn write_ir_file<'tcx>(
     }
 }
 
+/// Returns the Cranelift IR of the function of `context` annotated with the comments of
+/// `clif_comments`.
+pub(crate) fn clif_with_comments(
+    tcx: TyCtxt<'_>,
+    isa: Option<&dyn cranelift_codegen::isa::TargetIsa>,
+    context: &cranelift_codegen::Context,
+    mut clif_comments: &CommentWriter,
+) -> String {
+    let value_ranges = isa.map(|isa| {
+        context
+            .build_value_labels_ranges(isa)
+            .expect("value location ranges")
+    });
+
+    let mut clif = String::new();
+    cranelift_codegen::write::decorate_function(
+        &mut clif_comments,
+        &mut clif,
+        &context.func,
+        &DisplayFunctionAnnotations {
+            isa: Some(&*crate::build_isa(tcx.sess, false, false)),
+            value_ranges: value_ranges.as_ref(),
+        },
+    )
+    .unwrap();
+    clif
+}
+
 pub(crate) fn write_clif_file<'tcx>(
     tcx: TyCtxt<'tcx>,
     postfix: &str,
     isa: Option<&dyn cranelift_codegen::isa::TargetIsa>,
     instance: Instance<'tcx>,
     context: &cranelift_codegen::Context,
-    mut clif_comments: &CommentWriter,
+    clif_comments: &CommentWriter,
 ) {
     write_ir_file(
         tcx,
         &format!("{}.{}.clif", tcx.symbol_name(instance).name, postfix),
         |file| {
-            let value_ranges = isa.map(|isa| {
-                context
-                    .build_value_labels_ranges(isa)
-                    .expect("value location ranges")
-            });
-
-            let mut clif = String::new();
-            cranelift_codegen::write::decorate_function(
-                &mut clif_comments,
-                &mut clif,
-                &context.func,
-                &DisplayFunctionAnnotations {
-                    isa: Some(&*crate::build_isa(tcx.sess)),
-                    value_ranges: value_ranges.as_ref(),
-                },
-            )
-            .unwrap();
+            let clif = clif_with_comments(tcx, isa, context, clif_comments);
 
             writeln!(file, "test compile")?;
             writeln!(file, "set is_pic")?;
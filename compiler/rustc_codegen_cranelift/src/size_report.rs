@@ -0,0 +1,94 @@
+//! Reporting of the size of the emitted object files by section and function
+
+use std::fmt::Write;
+
+use rustc_codegen_ssa::CompiledModule;
+
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+
+use crate::prelude::*;
+
+pub(crate) fn write_size_report<'a>(
+    tcx: TyCtxt<'_>,
+    modules: impl Iterator<Item = &'a CompiledModule>,
+) {
+    let mut report = String::new();
+    let mut total_sections = FxHashMap::<String, u64>::default();
+    let mut total_functions = Vec::new();
+
+    for module in modules {
+        let path = if let Some(path) = &module.object {
+            path
+        } else {
+            continue;
+        };
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(err) => {
+                tcx.sess.warn(&format!(
+                    "error reading {} for size report: {}",
+                    path.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+        let obj = match object::File::parse(&data) {
+            Ok(obj) => obj,
+            Err(err) => {
+                tcx.sess.warn(&format!(
+                    "error parsing {} for size report: {}",
+                    path.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+
+        writeln!(report, "{}:", module.name).unwrap();
+        for section in obj.sections() {
+            if section.size() == 0 {
+                continue;
+            }
+            let name = section.name().unwrap_or("<invalid>");
+            writeln!(report, "  {:>10} {}", section.size(), name).unwrap();
+            *total_sections.entry(name.to_string()).or_default() += section.size();
+        }
+
+        // Symbol sizes don't include padding between functions, so this is only an approximation.
+        total_functions.extend(obj.symbols().filter_map(|symbol| {
+            if symbol.kind() != SymbolKind::Text || !symbol.is_definition() || symbol.size() == 0 {
+                return None;
+            }
+            Some((symbol.size(), symbol.name().ok()?.to_string()))
+        }));
+        writeln!(report).unwrap();
+    }
+
+    let mut total_sections = total_sections.into_iter().collect::<Vec<_>>();
+    total_sections.sort_by(|(a_name, a_size), (b_name, b_size)| {
+        b_size.cmp(a_size).then_with(|| a_name.cmp(b_name))
+    });
+    writeln!(report, "total:").unwrap();
+    for (name, size) in total_sections {
+        writeln!(report, "  {:>10} {}", size, name).unwrap();
+    }
+    writeln!(report).unwrap();
+
+    total_functions.sort_by(|a, b| b.cmp(a));
+    writeln!(report, "functions:").unwrap();
+    for (size, name) in total_functions {
+        writeln!(report, "  {:>10} {}", size, name).unwrap();
+    }
+
+    let report_file = tcx
+        .output_filenames(LOCAL_CRATE)
+        .with_extension("size_report.txt");
+    if let Err(err) = std::fs::write(&report_file, report) {
+        tcx.sess.warn(&format!(
+            "error writing size report {}: {}",
+            report_file.display(),
+            err
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+//! Dumping of the symbol table of the emitted object files, similar to `nm`, and checking them for
+//! duplicate symbols
+
+use std::fmt::Write;
+
+use rustc_codegen_ssa::CompiledModule;
+
+use object::{Object, ObjectSymbol, SymbolScope};
+
+use crate::prelude::*;
+
+/// Write the defined and undefined symbols of every module to a `.symbols` file next to the object
+/// file of the module.
+pub(crate) fn write_symbol_tables<'a>(
+    tcx: TyCtxt<'_>,
+    modules: impl Iterator<Item = &'a CompiledModule>,
+) {
+    for module in modules {
+        let path = if let Some(path) = &module.object {
+            path
+        } else {
+            continue;
+        };
+        let res: Result<(), String> = try {
+            let data = std::fs::read(path).map_err(|err| err.to_string())?;
+            let obj = object::File::parse(&data).map_err(|err| err.to_string())?;
+
+            let mut symbols = String::new();
+            for symbol in obj.symbols() {
+                let name = symbol.name().map_err(|err| err.to_string())?;
+                if name.is_empty() {
+                    continue;
+                }
+                let binding = if symbol.is_weak() {
+                    "weak"
+                } else if symbol.is_global() {
+                    "global"
+                } else {
+                    "local"
+                };
+                let scope = match symbol.scope() {
+                    SymbolScope::Unknown => "unknown",
+                    SymbolScope::Compilation => "compilation",
+                    SymbolScope::Linkage => "hidden",
+                    SymbolScope::Dynamic => "default",
+                };
+                writeln!(
+                    symbols,
+                    "{} {:?} {} {} {}",
+                    if symbol.is_undefined() { "U" } else { "D" },
+                    symbol.kind(),
+                    binding,
+                    scope,
+                    name,
+                )
+                .unwrap();
+            }
+
+            std::fs::write(path.with_extension("symbols"), symbols)
+                .map_err(|err| err.to_string())?;
+        };
+        if let Err(err) = res {
+            tcx.sess.warn(&format!(
+                "error dumping symbol table of {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+}
+
+/// Report every global symbol that is defined by more than one module, together with the names of
+/// the modules defining it. Weak symbols may be defined multiple times.
+pub(crate) fn check_duplicate_symbols<'a>(
+    tcx: TyCtxt<'_>,
+    modules: impl Iterator<Item = &'a CompiledModule>,
+) {
+    let mut definitions = FxHashMap::<String, &str>::default();
+    let mut duplicates = Vec::new();
+    for module in modules {
+        let path = if let Some(path) = &module.object {
+            path
+        } else {
+            continue;
+        };
+        let res: Result<(), String> = try {
+            let data = std::fs::read(path).map_err(|err| err.to_string())?;
+            let obj = object::File::parse(&data).map_err(|err| err.to_string())?;
+
+            for symbol in obj.symbols() {
+                if symbol.is_undefined() || !symbol.is_global() || symbol.is_weak() {
+                    continue;
+                }
+                let name = symbol.name().map_err(|err| err.to_string())?;
+                if let Some(&first_module) = definitions.get(name) {
+                    duplicates.push((name.to_string(), first_module, &*module.name));
+                } else {
+                    definitions.insert(name.to_string(), &module.name);
+                }
+            }
+        };
+        if let Err(err) = res {
+            tcx.sess.fatal(&format!(
+                "error reading symbol table of {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+
+    duplicates.sort();
+    for (name, first_module, second_module) in duplicates {
+        tcx.sess.err(&format!(
+            "symbol `{}` is defined by both `{}` and `{}`",
+            name, first_module, second_module
+        ));
+    }
+    tcx.sess.abort_if_errors();
+}
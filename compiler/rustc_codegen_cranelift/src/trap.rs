@@ -61,8 +61,19 @@ pub(crate) fn trap_unreachable_ret_value<'tcx>(
 /// Unlike `trap_unreachable` this will not fill the current block, so you **must** add instructions
 /// to it afterwards.
 ///
+/// A warning pointing at the statement or terminator currently being codegened is emitted, so all
+/// unimplemented features used by a crate can be found without running it.
+///
 /// Trap code: user65535
 pub(crate) fn trap_unimplemented(fx: &mut FunctionCx<'_, '_, impl Module>, msg: impl AsRef<str>) {
+    fx.tcx.sess.span_warn(
+        fx.current_span,
+        &format!(
+            "{} in `{}`; replacing with trap",
+            msg.as_ref(),
+            fx.tcx.symbol_name(fx.instance)
+        ),
+    );
     codegen_print(fx, msg.as_ref());
     let true_ = fx.bcx.ins().iconst(types::I32, 1);
     fx.bcx.ins().trapnz(true_, TrapCode::User(!0));
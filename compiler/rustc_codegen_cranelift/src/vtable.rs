@@ -95,6 +95,7 @@ fn build_vtable<'tcx>(
     let drop_in_place_fn = import_function(
         tcx,
         &mut fx.cx.module,
+        fx.cx.symbol_prefix.as_deref(),
         Instance::resolve_drop_in_place(tcx, layout.ty).polymorphize(fx.tcx),
     );
 
@@ -112,6 +113,7 @@ fn build_vtable<'tcx>(
             import_function(
                 tcx,
                 &mut fx.cx.module,
+                fx.cx.symbol_prefix.as_deref(),
                 Instance::resolve_for_vtable(tcx, ParamEnv::reveal_all(), def_id, substs)
                     .unwrap()
                     .polymorphize(fx.tcx),